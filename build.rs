@@ -0,0 +1,26 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use std::env;
+use std::process::Command;
+
+// Records the version of the compiler building the crate, which is reported
+// by the `build_info` module.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(version) = version {
+        println!(
+            "cargo:rustc-env=NEWRELIC_TELEMETRY_RUSTC_VERSION={}",
+            version.trim()
+        );
+    }
+}
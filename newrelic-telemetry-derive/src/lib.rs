@@ -0,0 +1,163 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Field, Fields, Lit, Meta, NestedMeta, Type,
+};
+
+/// Derives `IntoAttributes` for structs with named fields.
+///
+/// Each field becomes an attribute named like the field. Field values are
+/// converted via `Value::from`, fields of type `Option` are left out if they
+/// are `None`. Fields can be configured with the `attribute` attribute:
+///
+///  * `#[attribute(rename = "http.method")]` uses the given attribute name.
+///  * `#[attribute(skip)]` leaves the field out.
+///  * `#[attribute(flatten)]` adds the attributes of a field whose type
+///    implements `IntoAttributes` itself.
+#[proc_macro_derive(IntoAttributes, attributes(attribute))]
+pub fn derive_into_attributes(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// Options of a field given via `#[attribute(...)]`.
+#[derive(Default)]
+struct Options {
+    rename: Option<String>,
+    skip: bool,
+    flatten: bool,
+}
+
+impl Options {
+    fn parse(field: &Field) -> syn::Result<Self> {
+        let mut options = Options::default();
+
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("attribute")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(Error::new_spanned(meta, "expected #[attribute(...)]")),
+            };
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        options.skip = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("flatten") => {
+                        options.flatten = true
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                        match nv.lit {
+                            Lit::Str(name) => options.rename = Some(name.value()),
+                            lit => return Err(Error::new_spanned(lit, "expected a string")),
+                        }
+                    }
+                    nested => {
+                        return Err(Error::new_spanned(
+                            nested,
+                            "unknown option, expected rename, skip or flatten",
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(unsupported(input)),
+        },
+        _ => return Err(unsupported(input)),
+    };
+
+    let mut inserts = vec![];
+
+    for field in fields {
+        let options = Options::parse(field)?;
+        let ident = match &field.ident {
+            Some(ident) if !options.skip => ident,
+            _ => continue,
+        };
+        let key = options.rename.unwrap_or_else(|| ident.unraw().to_string());
+
+        let insert = if options.flatten {
+            quote! {
+                attributes.extend(
+                    ::newrelic_telemetry::attribute::IntoAttributes::into_attributes(self.#ident)
+                );
+            }
+        } else if is_option(&field.ty) {
+            quote! {
+                if let ::std::option::Option::Some(value) = self.#ident {
+                    attributes.insert(
+                        #key.to_string(),
+                        ::newrelic_telemetry::attribute::Value::from(value),
+                    );
+                }
+            }
+        } else {
+            quote! {
+                attributes.insert(
+                    #key.to_string(),
+                    ::newrelic_telemetry::attribute::Value::from(self.#ident),
+                );
+            }
+        };
+
+        inserts.push(insert);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::newrelic_telemetry::attribute::IntoAttributes
+            for #name #ty_generics #where_clause
+        {
+            fn into_attributes(
+                self,
+            ) -> ::std::collections::HashMap<
+                ::std::string::String,
+                ::newrelic_telemetry::attribute::Value,
+            > {
+                let mut attributes = ::std::collections::HashMap::new();
+                #(#inserts)*
+                attributes
+            }
+        }
+    })
+}
+
+fn unsupported(input: &DeriveInput) -> Error {
+    Error::new_spanned(
+        &input.ident,
+        "IntoAttributes can only be derived for structs with named fields",
+    )
+}
+
+// Returns `true` if the type is an `Option`, which is recognized by name.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == "Option")
+            .unwrap_or(false),
+        _ => false,
+    }
+}
@@ -1,7 +1,20 @@
+//
+// Copyright 2020 New Relic Corporation. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// The name of an attribute.
 ///
-/// Copyright 2020 New Relic Corporation. All rights reserved.
-/// SPDX-License-Identifier: Apache-2.0
-///
+/// Attribute names are mostly known at compile time. They are stored without
+/// allocating a copy when set via `Span::attribute_static`, and are copied
+/// otherwise.
+pub type Key = Cow<'static, str>;
+
+/// Derives `IntoAttributes` for structs with named fields.
+#[cfg(feature = "derive")]
+pub use newrelic_telemetry_derive::IntoAttributes;
 
 /// Represents any valid attribute value.
 ///
@@ -75,6 +88,77 @@ pub enum Value {
     Bool(bool),
 }
 
+/// Types that can be converted into a set of attributes.
+///
+/// With the `derive` feature, this can be derived for structs with named
+/// fields, turning each field into an attribute:
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # {
+/// use newrelic_telemetry::attribute::IntoAttributes;
+/// use newrelic_telemetry::Span;
+///
+/// #[derive(IntoAttributes)]
+/// struct Request {
+///     #[attribute(rename = "http.method")]
+///     method: &'static str,
+///     status: u32,
+///     #[attribute(skip)]
+///     body: Vec<u8>,
+/// }
+///
+/// let request = Request { method: "GET", status: 200, body: vec![] };
+///
+/// let span = Span::new("id", "trace_id", 1000).with_attributes(request);
+/// # }
+/// ```
+///
+/// It is implemented for maps and iterators of key value pairs, so existing
+/// attribute sets can be applied at once:
+///
+/// ```
+/// # use newrelic_telemetry::Span;
+/// let tags = vec![("region", "eu"), ("zone", "eu-1")];
+///
+/// let span = Span::new("id", "trace_id", 1000).with_attributes(tags);
+/// ```
+pub trait IntoAttributes {
+    /// Converts `self` into attributes, keyed by attribute name.
+    fn into_attributes(self) -> HashMap<String, Value>;
+}
+
+/// Maps, like `HashMap<String, Value>` or `BTreeMap<&str, &str>`, and other
+/// collections of key value pairs are converted pair by pair.
+impl<I, K, V> IntoAttributes for I
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<Value>,
+{
+    fn into_attributes(self) -> HashMap<String, Value> {
+        self.into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect()
+    }
+}
+
+impl Value {
+    // Returns an estimate of the size of the value serialized as JSON.
+    //
+    // Numbers are estimated with their maximum width, strings without
+    // escaping.
+    pub(crate) fn estimated_size(&self) -> usize {
+        match self {
+            Value::Int(_) | Value::UInt(_) => 20,
+            Value::Int128(_) | Value::UInt128(_) => 40,
+            Value::Str(s) => s.len() + 2,
+            Value::Float(_) => 24,
+            Value::Bool(_) => 5,
+        }
+    }
+}
+
 /// Converts an i128 to an attribute value.
 ///
 /// ```
@@ -173,6 +257,20 @@ impl From<&str> for Value {
     }
 }
 
+/// Converts a String to an attribute value.
+///
+/// ```
+/// # use newrelic_telemetry::attribute::Value;
+/// #
+/// let v = String::from("root");
+/// assert_eq!(Value::Str(String::from("root")), v.into());
+/// ```
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Str(value)
+    }
+}
+
 /// Converts a f64 to an attribute value.
 ///
 /// ```
@@ -217,31 +315,47 @@ impl From<bool> for Value {
 
 #[cfg(test)]
 mod tests {
-    use super::Value;
+    use super::{IntoAttributes, Value};
     use serde_json::json;
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn value_to_json() {
         // Attribute values should serialize to plain JSON values.
         assert_eq!(json!(Value::Int(-5)), json!(-5));
         assert_eq!(json!(Value::UInt(5)), json!(5));
-        assert_eq!(json!(Value::Float(3.14159)), json!(3.14159));
+        assert_eq!(json!(Value::Float(1.5)), json!(1.5));
         assert_eq!(json!(Value::Str(String::from("root"))), json!("root"));
         assert_eq!(json!(Value::Bool(true)), json!(true));
     }
 
+    #[test]
+    fn into_attributes() {
+        let mut expected = HashMap::new();
+        expected.insert("a".to_string(), Value::Int(1));
+        expected.insert("b".to_string(), Value::Int(2));
+
+        assert_eq!(expected.clone().into_attributes(), expected);
+        assert_eq!(vec![("a", 1), ("b", 2)].into_attributes(), expected);
+
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        assert_eq!(map.into_attributes(), expected);
+    }
+
     #[test]
     fn into_value() {
         // Should be able to use Value::from or .into() to create Values
         assert_eq!(Value::Int(-5), Value::from(-5));
-        assert_eq!(Value::Int(-5), (-5 as i32).into());
+        assert_eq!(Value::Int(-5), (-5_i32).into());
 
         // cast needed because integer types default to i32
-        assert_eq!(Value::UInt(5), Value::from(5 as u64));
-        assert_eq!(Value::UInt(5), (5 as u64).into());
+        assert_eq!(Value::UInt(5), Value::from(5_u64));
+        assert_eq!(Value::UInt(5), 5_u64.into());
 
-        assert_eq!(Value::Float(3.14159), Value::from(3.14159));
-        assert_eq!(Value::Float(3.14159), (3.14159 as f64).into());
+        assert_eq!(Value::Float(1.5), Value::from(1.5));
+        assert_eq!(Value::Float(1.5), 1.5_f64.into());
 
         assert_eq!(Value::Str("root".to_string()), Value::from("root"));
         assert_eq!(Value::Str("root".to_string()), "root".into());
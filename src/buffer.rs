@@ -0,0 +1,176 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use crate::span::{Span, SpanBatch};
+use std::mem;
+
+/// Buffers spans, handing out a batch as soon as it is full.
+///
+/// A batch is full once it holds the maximum number of spans or reaches the
+/// maximum estimated payload size, whichever comes first. A span that would
+/// push the batch over the size limit starts a new batch instead. This keeps
+/// bursts of spans from building batches which are rejected as too large.
+///
+/// The buffer complements periodic flushing: call `flush` on a timer to send
+/// spans of batches that never fill up.
+///
+/// ```
+/// # use newrelic_telemetry::{Span, SpanBuffer};
+/// let mut buffer = SpanBuffer::new(2, 1_000_000).attribute("service.name", "service");
+///
+/// assert!(buffer.record(Span::new("id1", "trace_id", 1000)).is_none());
+///
+/// let batch = buffer.record(Span::new("id2", "trace_id", 1000)).unwrap();
+/// assert_eq!(batch.spans().len(), 2);
+/// assert!(buffer.flush().is_none());
+/// ```
+#[derive(Debug)]
+pub struct SpanBuffer {
+    batch: SpanBatch,
+    max_spans: usize,
+    max_bytes: usize,
+}
+
+impl SpanBuffer {
+    /// Creates a buffer handing out batches of at most the given number of
+    /// spans and estimated payload size in bytes.
+    pub fn new(max_spans: usize, max_bytes: usize) -> Self {
+        SpanBuffer {
+            batch: SpanBatch::new(),
+            max_spans: max_spans.max(1),
+            max_bytes,
+        }
+    }
+
+    /// Sets a common attribute on all batches handed out by the buffer.
+    pub fn attribute<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
+        self.batch.set_attribute(key, value);
+        self
+    }
+
+    /// Adds a span to the buffer, returning a batch if one is full.
+    ///
+    /// A single span exceeding the size limit on its own is handed out in a
+    /// batch of its own. If the buffer is empty, that batch is returned right
+    /// away. Otherwise, the buffered spans are returned, and the span is
+    /// handed out on the next call to `record` or `flush`.
+    pub fn record(&mut self, span: Span) -> Option<SpanBatch> {
+        if !self.is_empty()
+            && self.batch.estimated_json_bytes() + span.estimated_size() > self.max_bytes
+        {
+            let full = self.take();
+            self.batch.record(span);
+            return Some(full);
+        }
+
+        self.batch.record(span);
+
+        if self.len() >= self.max_spans || self.batch.estimated_json_bytes() >= self.max_bytes {
+            return Some(self.take());
+        }
+
+        None
+    }
+
+    /// Hands out the buffered spans as a batch, or `None` if the buffer is
+    /// empty.
+    pub fn flush(&mut self) -> Option<SpanBatch> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(self.take())
+    }
+
+    /// Returns the number of buffered spans.
+    pub fn len(&self) -> usize {
+        self.batch.spans().len()
+    }
+
+    /// Returns `true` if no spans are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Takes the buffered batch, leaving an empty batch with the same common
+    // attributes.
+    fn take(&mut self) -> SpanBatch {
+        let next = self.batch.with_spans(vec![]);
+        mem::replace(&mut self.batch, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(id: &str) -> Span {
+        Span::new(id, "trace_id", 1000)
+    }
+
+    #[test]
+    fn flush_on_max_spans() {
+        let mut buffer = SpanBuffer::new(3, 1_000_000);
+
+        assert!(buffer.record(span("id1")).is_none());
+        assert!(buffer.record(span("id2")).is_none());
+
+        let batch = buffer.record(span("id3")).expect("full batch");
+        assert_eq!(batch.spans(), &[span("id1"), span("id2"), span("id3")]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn flush_on_max_bytes() {
+        let max_bytes = SpanBatch::from(vec![span("id1"), span("id2")]).estimated_json_bytes();
+        let mut buffer = SpanBuffer::new(100, max_bytes + 10);
+
+        assert!(buffer.record(span("id1")).is_none());
+        assert!(buffer.record(span("id2")).is_none());
+
+        // The third span doesn't fit, so it starts a new batch.
+        let batch = buffer.record(span("id3")).expect("full batch");
+        assert_eq!(batch.spans(), &[span("id1"), span("id2")]);
+        assert_eq!(buffer.len(), 1);
+
+        let batch = buffer.flush().expect("buffered spans");
+        assert_eq!(batch.spans(), &[span("id3")]);
+        assert!(buffer.flush().is_none());
+    }
+
+    #[test]
+    fn oversized_span() {
+        let large = || span("large").attribute("large", "x".repeat(1000).as_str());
+
+        // An oversized first span is handed out right away.
+        let max_bytes = SpanBatch::from(vec![span("id1"), span("id2")]).estimated_json_bytes();
+        let mut buffer = SpanBuffer::new(100, max_bytes);
+        let batch = buffer.record(large()).expect("oversized span");
+        assert_eq!(batch.spans(), &[large()]);
+        assert!(buffer.is_empty());
+
+        // Otherwise, it is handed out on the next call.
+        assert!(buffer.record(span("id1")).is_none());
+        let batch = buffer.record(large()).expect("buffered spans");
+        assert_eq!(batch.spans(), &[span("id1")]);
+        assert_eq!(buffer.len(), 1);
+
+        let batch = buffer.flush().expect("oversized span");
+        assert_eq!(batch.spans(), &[large()]);
+    }
+
+    #[test]
+    fn common_attributes() {
+        let mut buffer = SpanBuffer::new(1, 1_000_000).attribute("host", "host1");
+
+        for id in &["id1", "id2"] {
+            let batch = buffer.record(span(id)).expect("full batch");
+            assert_eq!(
+                batch.common_attributes().get("host"),
+                Some(&Value::from("host1"))
+            );
+        }
+    }
+}
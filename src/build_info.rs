@@ -0,0 +1,140 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use std::collections::HashMap;
+
+/// The version of the compiler this crate was built with.
+pub const RUSTC_VERSION: Option<&str> = option_env!("NEWRELIC_TELEMETRY_RUSTC_VERSION");
+
+/// Build and version information of a crate.
+///
+/// Use the `build_info!` macro to capture the information of the crate
+/// invoking it at compile time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildInfo {
+    /// The name of the crate.
+    pub name: &'static str,
+
+    /// The version of the crate.
+    pub version: &'static str,
+
+    /// The git commit the crate was built from, if known.
+    pub git_sha: Option<&'static str>,
+
+    /// The version of the compiler.
+    pub rustc_version: Option<&'static str>,
+}
+
+impl BuildInfo {
+    /// Returns the build information as attribute map.
+    ///
+    /// The attributes reported are `build.crate.name`, `build.crate.version`,
+    /// `build.git.sha` and `build.rustc.version`. Values that are unknown are
+    /// omitted.
+    pub fn attributes(&self) -> HashMap<String, Value> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("build.crate.name".to_string(), self.name.into());
+        attributes.insert("build.crate.version".to_string(), self.version.into());
+
+        if let Some(sha) = self.git_sha {
+            attributes.insert("build.git.sha".to_string(), sha.into());
+        }
+
+        if let Some(version) = self.rustc_version {
+            attributes.insert("build.rustc.version".to_string(), version.into());
+        }
+
+        attributes
+    }
+}
+
+/// Returns the build information of this crate as attribute map.
+///
+/// ```
+/// # use newrelic_telemetry::build_info;
+/// let attributes = build_info::attributes();
+///
+/// assert_eq!(attributes["build.crate.name"], "newrelic-telemetry".into());
+/// ```
+pub fn attributes() -> HashMap<String, Value> {
+    crate::build_info!().attributes()
+}
+
+/// Captures the build information of the invoking crate at compile time.
+///
+/// The crate name and version are taken from Cargo. The git commit is taken
+/// from the `GIT_SHA` or `VERGEN_GIT_SHA` environment variables at compile
+/// time, which can be set by a build script, e.g.:
+///
+/// ```no_run
+/// // build.rs
+/// use std::process::Command;
+///
+/// fn main() {
+///     let output = Command::new("git").args(&["rev-parse", "HEAD"]).output().unwrap();
+///     let sha = String::from_utf8(output.stdout).unwrap();
+///     println!("cargo:rustc-env=GIT_SHA={}", sha.trim());
+/// }
+/// ```
+///
+/// The returned `BuildInfo` can be attached to batches as common attributes:
+///
+/// ```
+/// # use newrelic_telemetry::{build_info, SpanBatch};
+/// let mut batch = SpanBatch::new();
+///
+/// for (key, value) in build_info!().attributes() {
+///     batch.set_attribute(&key, value);
+/// }
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build_info::BuildInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("GIT_SHA").or(option_env!("VERGEN_GIT_SHA")),
+            rustc_version: $crate::build_info::RUSTC_VERSION,
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attributes, BuildInfo};
+    use crate::attribute::Value;
+
+    #[test]
+    fn build_info_attributes() {
+        let info = BuildInfo {
+            name: "service",
+            version: "1.0.0",
+            git_sha: None,
+            rustc_version: Some("rustc 1.45.0"),
+        };
+
+        let attributes = info.attributes();
+
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(attributes["build.crate.name"], Value::from("service"));
+        assert_eq!(attributes["build.crate.version"], Value::from("1.0.0"));
+        assert_eq!(
+            attributes["build.rustc.version"],
+            Value::from("rustc 1.45.0")
+        );
+    }
+
+    #[test]
+    fn crate_attributes() {
+        let attributes = attributes();
+
+        assert_eq!(
+            attributes["build.crate.version"],
+            Value::from(env!("CARGO_PKG_VERSION"))
+        );
+        assert!(attributes.contains_key("build.rustc.version"));
+    }
+}
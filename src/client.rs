@@ -2,56 +2,173 @@
 /// Copyright 2020 New Relic Corporation. All rights reserved.
 /// SPDX-License-Identifier: Apache-2.0
 ///
+use crate::attribute::Value;
+use crate::clock::{Clock, OffsetClock, SystemClock};
+use crate::sendable::Sendable;
 use crate::span::SpanBatch;
 use anyhow::{anyhow, Result};
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use futures::stream::{self, StreamExt};
+use hyper::body::HttpBody;
 use hyper::client::HttpConnector;
-use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
+use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE, DATE, USER_AGENT};
+use hyper::http::request::Builder as RequestBuilder;
 use hyper::{Body, HeaderMap, Method, Request, Response, Uri};
 use hyper_tls::HttpsConnector;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs;
 use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
+use std::net::IpAddr;
+#[cfg(all(unix, feature = "uds"))]
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::thread;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "tower")]
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const TRACE_API_PATH: &'static str = "trace/v1";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+// The maximum estimated size of payloads created by splitting payloads that
+// are too large, which is the payload size limit of ingest endpoints.
+const SPLIT_BYTES_MAX: usize = 1_000_000;
 
-/// Types that can be sent to a New Relic ingest API
+// The time after which a learned payload size limit is forgotten, so that
+// payloads up to the configured limit are tried again.
+const SIZE_LIMIT_TTL: Duration = Duration::from_secs(600);
+
+// The default maximum number of spans sent in one payload, which is the
+// limit documented for the Trace API.
+const SPANS_PER_BATCH_MAX: usize = 2000;
+
+// The default maximum number of chunks of a batch that are sent concurrently.
+const CONCURRENT_REQUESTS_MAX: usize = 4;
+
+// The minimum skew of the local clock which is corrected, in milliseconds.
+const CLOCK_SKEW_MIN_MS: i64 = 2000;
+
+// Prefixes of informational response headers, like rate limit hints and
+// diagnostics, which are kept in response errors.
+const INFO_HEADER_PREFIXES: [&str; 5] = ["nr-", "x-nr-", "ratelimit", "x-ratelimit", "retry-after"];
+
+// The interval at which a shutdown checks for batches being done.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const TRACE_API_HOST: &str = "trace-api.newrelic.com";
+const TRACE_API_PATH: &str = "trace/v1";
+
+// The host and default port of the HTTP listener of a local New Relic agent.
+const LOCAL_AGENT_HOST: &str = "localhost";
+const LOCAL_AGENT_PORT: u16 = 8001;
+
+#[cfg(feature = "otlp")]
+const OTLP_HOST: &str = "otlp.nr-data.net";
+#[cfg(feature = "otlp")]
+const OTLP_PORT: u16 = 4318;
+#[cfg(feature = "otlp")]
+const OTLP_TRACES_PATH: &str = "v1/traces";
+
+// With the `zeroize` feature enabled, the memory holding the API key is wiped
+// when the key is dropped.
+#[cfg(feature = "zeroize")]
+type ApiKey = zeroize::Zeroizing<String>;
+#[cfg(not(feature = "zeroize"))]
+type ApiKey = String;
+
+// Emits a `tracing` event with the given level, if the `tracing` feature is
+// enabled.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
+}
+
+// Generates request ids for batches sent by a client.
+type RequestIdGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+// Observes status codes and headers of responses received by a client.
+type ResponseHook = Arc<dyn Fn(u16, &HeaderMap) + Send + Sync>;
+
+// Computes additional authentication headers for each request.
+type AuthHeaders = Arc<dyn Fn() -> Vec<(String, String)> + Send + Sync>;
+
+// Observes the timing of sends made by a client.
+type TimingHook = Arc<dyn Fn(&SendTiming) + Send + Sync>;
+
+/// A source of API keys, consulted for each request.
 ///
-/// New Relic ingest APIs currently accept batches of traces, metrics, events
-/// or logs.
-pub trait Sendable: std::fmt::Display + Send {
-    /// Return the uuid for the `Sendable`
+/// This allows rotating API keys at runtime, for example when they are
+/// fetched from a secret store, without rebuilding the client. See
+/// `ClientBuilder::key_provider`.
+///
+/// ```
+/// # use newrelic_telemetry::KeyProvider;
+/// # use std::sync::RwLock;
+/// struct RotatingKey(RwLock<String>);
+///
+/// impl KeyProvider for RotatingKey {
+///     fn api_key(&self) -> String {
+///         self.0.read().unwrap().clone()
+///     }
+/// }
+/// ```
+pub trait KeyProvider: Send + Sync {
+    /// Returns the current API key.
     ///
-    /// This method returns a version 4 UUID string which enables the ingest
-    /// service to identify duplicate requests.
-    fn uuid(&self) -> &str;
+    /// This is called before each request, so it should return quickly, for
+    /// example by returning a cached key.
+    fn api_key(&self) -> String;
 
-    // Create a payload
-    //
-    // This method creates a JSON payload representing the contents of the
-    // `Sendable` object, conforming to the requirements of a related ingest
-    // API (traces, metrics, events or logs).
-    fn marshall(&self) -> Result<String>;
+    /// Refreshes the API key after it was rejected.
+    ///
+    /// This is called when a request is rejected with a 401 or 403 status
+    /// code. If `true` is returned, a new key is available and the request is
+    /// retried once with it. The default implementation returns `false`, so
+    /// the batch is dropped.
+    fn refresh(&self) -> bool {
+        false
+    }
+}
 
-    // Split a `Sendable`
-    //
-    // New Relic ingest APIs reject payloads that are too large. In that case,
-    // a 413 response code is sent, the payload must be split and sent again
-    // (see [the specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md#response-codes)
-    // for further details).
-    //
-    // This method removes half of the content of the `Sendable` object and
-    // puts it into a second `Sendable` object, which is returned.
-    fn split(&mut self) -> Box<dyn Sendable>;
+impl<K: KeyProvider + ?Sized> KeyProvider for Arc<K> {
+    fn api_key(&self) -> String {
+        (**self).api_key()
+    }
+
+    fn refresh(&self) -> bool {
+        (**self).refresh()
+    }
+}
+
+/// Common interface of clients sending data to New Relic ingest APIs.
+///
+/// Code taking a `TelemetryClient` instead of a concrete client can be unit
+/// tested with a `MockClient`, which requires the `test-support` feature.
+///
+/// ```
+/// # use newrelic_telemetry::{SpanBatch, TelemetryClient};
+/// async fn flush(client: &impl TelemetryClient, batch: SpanBatch) {
+///     client.send_spans(batch).await;
+/// }
+/// ```
+pub trait TelemetryClient {
+    /// Sends a span batch.
+    ///
+    /// The returned future completes once the client is done with the batch.
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
 }
 
 // Represents a New Relic ingest endpoint.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Endpoint {
     // The host name or address of the endpoint.
     host: String,
@@ -61,7 +178,7 @@ struct Endpoint {
     port: Option<u16>,
 
     // The path for the endpoint.
-    path: &'static str,
+    path: String,
 }
 
 impl Endpoint {
@@ -85,6 +202,64 @@ impl Endpoint {
 
         Ok(uri.parse::<Uri>()?)
     }
+
+    // Checks that the endpoint forms a valid URI.
+    fn validate(&self, use_tls: bool) -> Result<(), ConfigError> {
+        if self.host.is_empty() {
+            return Err(ConfigError::InvalidEndpoint("empty host".to_string()));
+        }
+
+        match self.uri(use_tls) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ConfigError::InvalidEndpoint(format!(
+                "{}: {}",
+                self.host, e
+            ))),
+        }
+    }
+
+    // Returns the OTLP endpoint for traces corresponding to this endpoint.
+    //
+    // The default trace endpoint is mapped to the New Relic OTLP endpoint,
+    // custom hosts are kept.
+    #[cfg(feature = "otlp")]
+    fn otlp_traces(&self) -> Endpoint {
+        if self.host == TRACE_API_HOST && self.port.is_none() {
+            Endpoint {
+                host: OTLP_HOST.to_string(),
+                port: Some(OTLP_PORT),
+                path: OTLP_TRACES_PATH.to_string(),
+            }
+        } else {
+            Endpoint {
+                host: self.host.clone(),
+                port: self.port,
+                path: OTLP_TRACES_PATH.to_string(),
+            }
+        }
+    }
+}
+
+// Represents the data format of payloads sent to an ingest endpoint.
+//
+// The format and its version are sent as `Data-Format` and
+// `Data-Format-Version` headers.
+#[derive(Clone, Debug, PartialEq)]
+struct DataFormat {
+    // The name of the data format.
+    format: String,
+
+    // The version of the data format.
+    version: String,
+}
+
+impl Default for DataFormat {
+    fn default() -> Self {
+        DataFormat {
+            format: "newrelic".to_string(),
+            version: "1".to_string(),
+        }
+    }
 }
 
 /// `ClientBuilder` acts as builder for initializing a `Client`.
@@ -97,7 +272,7 @@ impl Endpoint {
 /// # use newrelic_telemetry::ClientBuilder;
 /// # use std::time::Duration;
 /// # fn main() -> Result<()> {
-/// # let api_key = "";
+/// # let api_key = "api-key";
 /// let mut builder = ClientBuilder::new(api_key);
 ///
 /// let client = builder.backoff_factor(Duration::from_secs(10))
@@ -107,13 +282,98 @@ impl Endpoint {
 /// # }
 /// ```
 pub struct ClientBuilder {
-    api_key: String,
+    api_key: ApiKey,
+    key_provider: Option<Arc<dyn KeyProvider>>,
     backoff_factor: Duration,
+    backoff_max: Option<Duration>,
     retries_max: u32,
+    retry_budget: Option<Duration>,
+    max_batch_age: Option<Duration>,
     endpoint_traces: Endpoint,
-    product_info: Option<(String, String)>,
+    path_traces: Option<String>,
+    data_format_traces: DataFormat,
+    product_info: Vec<(String, String)>,
+    user_agent: Option<String>,
+    service_name: Option<String>,
+    enrich_attributes: bool,
+    #[cfg(feature = "host-metadata")]
+    host_metadata: bool,
+    #[cfg(feature = "container-metadata")]
+    container_metadata: bool,
     blocking_queue_max: usize,
+    blocking_queue_max_bytes: usize,
     use_tls: bool,
+    http2_only: bool,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    resolve_overrides: HashMap<String, Vec<IpAddr>>,
+    resolver: Option<CustomResolver>,
+    local_agent: bool,
+    dry_run: bool,
+    skip_empty_batches: bool,
+    dedup_window: usize,
+    max_spans_per_batch: usize,
+    max_concurrent_requests: usize,
+    stream_payloads: bool,
+    request_id_per_attempt: bool,
+    request_id_generator: Option<RequestIdGenerator>,
+    response_hook: Option<ResponseHook>,
+    auth_headers: Option<AuthHeaders>,
+    timing_hook: Option<TimingHook>,
+    clock: Arc<dyn Clock>,
+    correct_clock_skew: bool,
+    #[cfg(feature = "otlp")]
+    otlp: bool,
+    #[cfg(feature = "blocking")]
+    runtime_handle: Option<tokio::runtime::Handle>,
+    #[cfg(feature = "infinite-tracing")]
+    trace_observer: Option<Endpoint>,
+    #[cfg(all(unix, feature = "uds"))]
+    uds_path: Option<PathBuf>,
+}
+
+// The API key is redacted, as builders might be logged.
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("api_key", &redact_api_key(&self.api_key))
+            .field("key_provider", &self.key_provider.is_some())
+            .field("backoff_factor", &self.backoff_factor)
+            .field("backoff_max", &self.backoff_max)
+            .field("retries_max", &self.retries_max)
+            .field("retry_budget", &self.retry_budget)
+            .field("max_batch_age", &self.max_batch_age)
+            .field("endpoint_traces", &self.endpoint_traces)
+            .field("path_traces", &self.path_traces)
+            .field("data_format_traces", &self.data_format_traces)
+            .field("product_info", &self.product_info)
+            .field("user_agent", &self.user_agent)
+            .field("service_name", &self.service_name)
+            .field("enrich_attributes", &self.enrich_attributes)
+            .field("blocking_queue_max", &self.blocking_queue_max)
+            .field("blocking_queue_max_bytes", &self.blocking_queue_max_bytes)
+            .field("use_tls", &self.use_tls)
+            .field("http2_only", &self.http2_only)
+            .field("pool_max_idle_per_host", &self.pool_max_idle_per_host)
+            .field("pool_idle_timeout", &self.pool_idle_timeout)
+            .field("resolve_overrides", &self.resolve_overrides)
+            .field("resolver", &self.resolver.is_some())
+            .field("local_agent", &self.local_agent)
+            .field("dry_run", &self.dry_run)
+            .field("skip_empty_batches", &self.skip_empty_batches)
+            .field("dedup_window", &self.dedup_window)
+            .field("max_spans_per_batch", &self.max_spans_per_batch)
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .field("stream_payloads", &self.stream_payloads)
+            .field("request_id_per_attempt", &self.request_id_per_attempt)
+            .field("request_id_generator", &self.request_id_generator.is_some())
+            .field("response_hook", &self.response_hook.is_some())
+            .field("auth_headers", &self.auth_headers.is_some())
+            .field("timing_hook", &self.timing_hook.is_some())
+            .field("clock", &self.clock)
+            .field("correct_clock_skew", &self.correct_clock_skew)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ClientBuilder {
@@ -121,32 +381,126 @@ impl ClientBuilder {
     ///
     /// Other values will be set to defaults:
     ///  * The default backoff factor will be 5 seconds.
+    ///  * By default, the backoff interval is not capped.
     ///  * The default maximum of retries is 8.
+    ///  * By default, there is no retry budget.
     ///  * The default trace endpoint is `https://trace-api.newrelic.com/trace/v1` on port 80.
+    ///  * By default, the standard path of the trace endpoint is used.
+    ///  * The default data format for traces is `newrelic`, version `1`.
     ///  * By default, product information is empty.
+    ///  * By default, the `User-Agent` header identifies the SDK.
+    ///  * By default, no service name is configured.
+    ///  * By default, batches are not enriched with attributes.
     ///  * By default, no more than 100 batches are sent in one go in blocking mode.
+    ///  * By default, the blocking client creates its own runtime.
+    ///  * By default, requests are sent via HTTP/1.1.
+    ///  * By default, the number of idle connections per host is not limited.
+    ///  * By default, idle connections are closed after 90 seconds.
+    ///  * By default, host names are resolved by the system resolver.
+    ///  * By default, the API key passed to the builder is used for all requests.
+    ///  * By default, data is sent to New Relic rather than to a local agent.
+    ///  * By default, dry run mode is disabled.
+    ///  * By default, empty batches are sent.
+    ///  * By default, batches identical to sent batches are sent again.
+    ///  * By default, batches of more than 2000 spans are sent in chunks.
+    ///  * By default, up to 4 chunks of a batch are sent concurrently.
+    ///  * By default, payloads are compressed completely before being sent.
+    ///  * By default, request ids are kept stable across retries.
+    ///  * By default, the uuid of a batch is used as request id.
+    ///  * By default, no response hook is configured.
+    ///  * By default, no additional authentication headers are sent.
+    ///  * By default, no timing hook is configured.
+    ///  * By default, batches are sent regardless of their age.
+    ///  * By default, the time is read from the system clock.
+    ///  * By default, clock skew is not corrected.
     ///
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # let api_key = "";
+    /// # let api_key = "api-key";
     /// let mut builder = ClientBuilder::new(api_key);
     /// ```
     pub fn new(api_key: &str) -> Self {
         ClientBuilder {
-            api_key: api_key.to_string(),
+            api_key: ApiKey::from(api_key.to_string()),
+            key_provider: None,
             backoff_factor: Duration::from_secs(5),
+            backoff_max: None,
             retries_max: 8,
+            retry_budget: None,
+            max_batch_age: None,
             endpoint_traces: Endpoint {
-                host: "trace-api.newrelic.com".to_string(),
+                host: TRACE_API_HOST.to_string(),
                 port: None,
-                path: TRACE_API_PATH,
+                path: TRACE_API_PATH.to_string(),
             },
-            product_info: None,
+            path_traces: None,
+            data_format_traces: DataFormat::default(),
+            product_info: vec![],
+            user_agent: None,
+            service_name: None,
+            enrich_attributes: false,
+            #[cfg(feature = "host-metadata")]
+            host_metadata: false,
+            #[cfg(feature = "container-metadata")]
+            container_metadata: false,
             blocking_queue_max: 100,
+            blocking_queue_max_bytes: usize::MAX,
             use_tls: true,
+            http2_only: false,
+            pool_max_idle_per_host: usize::MAX,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            resolve_overrides: HashMap::new(),
+            resolver: None,
+            local_agent: false,
+            dry_run: false,
+            skip_empty_batches: false,
+            dedup_window: 0,
+            max_spans_per_batch: SPANS_PER_BATCH_MAX,
+            max_concurrent_requests: CONCURRENT_REQUESTS_MAX,
+            stream_payloads: false,
+            request_id_per_attempt: false,
+            request_id_generator: None,
+            response_hook: None,
+            auth_headers: None,
+            timing_hook: None,
+            clock: Arc::new(SystemClock),
+            correct_clock_skew: false,
+            #[cfg(feature = "otlp")]
+            otlp: false,
+            #[cfg(feature = "blocking")]
+            runtime_handle: None,
+            #[cfg(feature = "infinite-tracing")]
+            trace_observer: None,
+            #[cfg(all(unix, feature = "uds"))]
+            uds_path: None,
         }
     }
 
+    /// Configures a provider of API keys.
+    ///
+    /// The provider is asked for the API key before each request, instead of
+    /// using the API key passed to the builder, which may be empty. When a
+    /// request is rejected with a 401 or 403 status code, the provider is
+    /// asked to refresh the key, and the request is retried once if a new key
+    /// is available.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{ClientBuilder, KeyProvider};
+    /// struct Vault;
+    ///
+    /// impl KeyProvider for Vault {
+    ///     fn api_key(&self) -> String {
+    ///         "api-key".to_string()
+    ///     }
+    /// }
+    ///
+    /// let mut builder = ClientBuilder::new("").key_provider(Vault);
+    /// ```
+    pub fn key_provider<P: KeyProvider + 'static>(mut self, provider: P) -> Self {
+        self.key_provider = Some(Arc::new(provider));
+        self
+    }
+
     /// Configures a backoff factor.
     ///
     /// If a request fails, the SDK retries the request at increasing intervals
@@ -166,7 +520,7 @@ impl ClientBuilder {
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
     /// # use std::time::Duration;
-    /// # let api_key = "";
+    /// # let api_key = "api-key";
     /// let mut builder =
     ///     ClientBuilder::new(api_key).backoff_factor(Duration::from_secs(10));
     /// ```
@@ -175,6 +529,28 @@ impl ClientBuilder {
         self
     }
 
+    /// Configures a maximum backoff interval.
+    ///
+    /// The exponentially growing retry delay intervals are capped at the given
+    /// maximum. For a backoff factor of 1 second, a maximum of 6 retries and a
+    /// maximum backoff interval of 5 seconds, the retry delay interval follows
+    /// a pattern of [0, 1, 2, 4, 5, 5].
+    ///
+    /// Retry intervals requested by the ingest endpoint via the `Retry-After`
+    /// header are not capped.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).backoff_max(Duration::from_secs(60));
+    /// ```
+    pub fn backoff_max(mut self, max: Duration) -> Self {
+        self.backoff_max = Some(max);
+        self
+    }
+
     /// Configures the maximum numbers of retries.
     ///
     /// If a request fails, the SDK retries the request at increasing intervals
@@ -191,7 +567,7 @@ impl ClientBuilder {
     ///
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # let api_key = "";
+    /// # let api_key = "api-key";
     /// let mut builder =
     ///     ClientBuilder::new(api_key).retries_max(4);
     /// ```
@@ -200,6 +576,50 @@ impl ClientBuilder {
         self
     }
 
+    /// Configures a retry budget.
+    ///
+    /// The retry budget limits the cumulative time spent on requests and
+    /// backoff for a single payload. If waiting for the next retry would
+    /// exceed the budget, the payload is dropped, even if the maximum number
+    /// of retries is not reached yet.
+    ///
+    /// With the default backoff factor and maximum of retries, a payload can
+    /// be retried for more than 10 minutes.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).retry_budget(Duration::from_secs(60));
+    /// ```
+    pub fn retry_budget(mut self, budget: Duration) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// Configures the maximum age of batches.
+    ///
+    /// Batches waiting in the queue of the blocking client for longer than
+    /// this are dropped instead of being sent, and retries of a payload are
+    /// abandoned once it would exceed this age. This avoids delivering stale
+    /// data late, e.g. after an outage of the network. The age of queued
+    /// batches includes the time spent in the queue, the age of batches sent
+    /// directly is counted from when they are passed to the client. Dropped
+    /// batches are counted by `Client::expired_batches`.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).max_batch_age(Duration::from_secs(600));
+    /// ```
+    pub fn max_batch_age(mut self, age: Duration) -> Self {
+        self.max_batch_age = Some(age);
+        self
+    }
+
     /// Configure the ingest host for traces.
     ///
     /// Overrides the default ingest host for traces to facilitate communication
@@ -207,15 +627,53 @@ impl ClientBuilder {
     ///
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # let api_key = "";
+    /// # let api_key = "api-key";
     /// let mut builder =
     ///     ClientBuilder::new(api_key).endpoint_traces("127.0.0.1", None);
     /// ```
     pub fn endpoint_traces(mut self, url: &str, port: Option<u16>) -> Self {
         self.endpoint_traces = Endpoint {
             host: url.to_string(),
-            path: TRACE_API_PATH,
-            port: port,
+            path: TRACE_API_PATH.to_string(),
+            port,
+        };
+        self
+    }
+
+    /// Configure the path of the ingest endpoint for traces.
+    ///
+    /// Overrides the standard path of the trace endpoint, which is `/trace/v1`
+    /// for the Trace API and `/v1/traces` in OTLP mode. This allows sending
+    /// traces to gateways exposing ingest APIs under custom routes.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key)
+    ///     .endpoint_traces("gateway.example.com", None)
+    ///     .path_traces("/newrelic/trace");
+    /// ```
+    pub fn path_traces(mut self, path: &str) -> Self {
+        self.path_traces = Some(path.trim_start_matches('/').to_string());
+        self
+    }
+
+    /// Configure the data format for traces.
+    ///
+    /// Overrides the `Data-Format` and `Data-Format-Version` headers sent to
+    /// the trace endpoint, which default to `newrelic` and `1`. This allows
+    /// targeting experimental or future versions of the Trace API.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).data_format_traces("newrelic", "2");
+    /// ```
+    pub fn data_format_traces(mut self, format: &str, version: &str) -> Self {
+        self.data_format_traces = DataFormat {
+            format: format.to_string(),
+            version: version.to_string(),
         };
         self
     }
@@ -223,19 +681,114 @@ impl ClientBuilder {
     /// Configure a product and version.
     ///
     /// The specified product and version will be appended to the `User-Agent`
-    /// header of payloads.
+    /// header of payloads. This can be called several times, the products are
+    /// appended in the order they were configured.
     ///
     /// See the [specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md#user-agent)
     /// for further details.
     ///
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # let api_key = "";
-    /// let mut builder =
-    ///     ClientBuilder::new(api_key).product_info("NewRelic-Cpp-OpenTelemetry", "0.2.1");
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key)
+    ///     .product_info("NewRelic-Cpp-OpenTelemetry", "0.2.1")
+    ///     .product_info("MyExporter", "1.0");
     /// ```
     pub fn product_info(mut self, product: &str, version: &str) -> Self {
-        self.product_info = Some((product.to_string(), version.to_string()));
+        self.product_info
+            .push((product.to_string(), version.to_string()));
+        self
+    }
+
+    /// Configure the full `User-Agent` header.
+    ///
+    /// This replaces the `User-Agent` header identifying the SDK and the
+    /// products configured via `product_info`. It is meant for integrations
+    /// that must follow their own conventions; where possible,
+    /// `product_info` should be used instead.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).user_agent("MyAgent/2.0");
+    /// ```
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Configure the name of the instrumented service.
+    ///
+    /// If attribute enrichment is enabled, the service name is added as
+    /// `service.name` attribute to outgoing batches.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).service_name("checkout");
+    /// ```
+    pub fn service_name(mut self, service_name: &str) -> Self {
+        self.service_name = Some(service_name.to_string());
+        self
+    }
+
+    /// Configure attribute enrichment.
+    ///
+    /// If enabled, the following common attributes are added to outgoing
+    /// batches, unless a batch already has a common attribute with the same
+    /// key:
+    ///  * `service.name`, if configured via `service_name`.
+    ///  * `host.name`, if the host name can be determined.
+    ///  * `process.pid`, the id of the current process.
+    ///
+    /// This matches the attributes reported by New Relic agents and helps
+    /// linking telemetry to entities.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key)
+    ///     .service_name("checkout")
+    ///     .enrich_attributes(true);
+    /// ```
+    pub fn enrich_attributes(mut self, enrich: bool) -> Self {
+        self.enrich_attributes = enrich;
+        self
+    }
+
+    /// Configure host metadata enrichment.
+    ///
+    /// If enabled, host and operating system metadata is gathered once when
+    /// the client is built, and added as common attributes to outgoing
+    /// batches, unless a batch already has a common attribute with the same
+    /// key. See `HostMetadata` for the attributes reported.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).host_metadata(true);
+    /// ```
+    #[cfg(feature = "host-metadata")]
+    pub fn host_metadata(mut self, enabled: bool) -> Self {
+        self.host_metadata = enabled;
+        self
+    }
+
+    /// Configure container metadata enrichment.
+    ///
+    /// If enabled, Kubernetes and container metadata is gathered once when
+    /// the client is built, and added as common attributes to outgoing
+    /// batches, unless a batch already has a common attribute with the same
+    /// key. See `ContainerMetadata` for the attributes reported.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).container_metadata(true);
+    /// ```
+    #[cfg(feature = "container-metadata")]
+    pub fn container_metadata(mut self, enabled: bool) -> Self {
+        self.container_metadata = enabled;
         self
     }
 
@@ -249,7 +802,7 @@ impl ClientBuilder {
     ///
     /// ```
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # let api_key = "";
+    /// # let api_key = "api-key";
     /// let mut builder =
     ///     ClientBuilder::new(api_key).blocking_queue_max(10);
     /// ```
@@ -258,195 +811,2246 @@ impl ClientBuilder {
         self
     }
 
-    // Configure TLS usage.
-    //
-    // New Relic endpoints exclusively support HTTPS. This is mainly provided
-    // for testing purposes.
-    pub fn tls(mut self, tls: bool) -> Self {
-        self.use_tls = tls;
+    /// Configure the maximum estimated size of batches sent in one go in
+    /// blocking mode.
+    ///
+    /// This configuration has no effect for default non-blocking clients.
+    ///
+    /// Like `blocking_queue_max`, but bounds the total estimated JSON size
+    /// of the queued batches in bytes, so a few very large batches cannot
+    /// exhaust memory. Batches are dropped by the same policy as batches
+    /// exceeding the maximum number of batches. By default, the size is not
+    /// limited.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).blocking_queue_max_bytes(50_000_000);
+    /// ```
+    pub fn blocking_queue_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.blocking_queue_max_bytes = max_bytes;
         self
     }
 
-    /// Build a client.
+    /// Configure a runtime for the blocking client.
+    ///
+    /// This configuration has no effect for default non-blocking clients.
+    ///
+    /// By default, the blocking client creates its own threaded runtime. If a
+    /// handle to an existing runtime is given, the blocking client sends its
+    /// batches on that runtime instead, which reduces the number of threads
+    /// in processes with several clients. Only tokio runtimes are supported,
+    /// as requests are sent via hyper.
     ///
     /// ```
     /// # use anyhow::Result;
     /// # use newrelic_telemetry::ClientBuilder;
     /// # fn main() -> Result<()> {
-    /// # let api_key = "";
-    /// let builder = ClientBuilder::new(api_key);
+    /// # let api_key = "api-key";
+    /// let runtime = tokio::runtime::Builder::new()
+    ///     .threaded_scheduler()
+    ///     .enable_all()
+    ///     .build()?;
     ///
-    /// let client = builder.build()?;
+    /// let client = ClientBuilder::new(api_key)
+    ///     .runtime_handle(runtime.handle().clone())
+    ///     .build_blocking()?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn build(self) -> Result<Client> {
-        Client::new(self)
+    #[cfg(feature = "blocking")]
+    pub fn runtime_handle(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.runtime_handle = Some(handle);
+        self
     }
 
-    /// Build a blocking client.
+    /// Configure OTLP export mode.
+    ///
+    /// In OTLP mode, span batches are encoded as OTLP protobuf export
+    /// requests, instead of using the New Relic data format. Unless a custom
+    /// trace endpoint is configured, they are sent to the New Relic OTLP
+    /// endpoint `https://otlp.nr-data.net:4318/v1/traces`. Custom trace
+    /// endpoints are used with the `/v1/traces` path.
+    ///
+    /// Retries and backoff work the same as for the Trace API. Span and trace
+    /// ids must be hex encoded, batches containing other ids are dropped.
     ///
     /// ```
-    /// # use anyhow::Result;
     /// # use newrelic_telemetry::ClientBuilder;
-    /// # fn main() -> Result<()> {
-    /// # let api_key = "";
-    /// let builder = ClientBuilder::new(api_key);
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).otlp(true);
+    /// ```
+    #[cfg(feature = "otlp")]
+    pub fn otlp(mut self, otlp: bool) -> Self {
+        self.otlp = otlp;
+        self
+    }
+
+    /// Configure an Infinite Tracing trace observer.
+    ///
+    /// Spans sent via a client built with `build_infinite_tracing` are
+    /// streamed to the given trace observer host via gRPC, instead of being
+    /// sent in batches to the Trace API. If no port is given, it defaults to
+    /// the standard HTTPS port.
     ///
-    /// let client = builder.build_blocking()?;
-    /// # Ok(())
-    /// # }
     /// ```
-    #[cfg(feature = "blocking")]
-    pub fn build_blocking(self) -> Result<blocking::Client> {
-        blocking::Client::new(self)
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key)
+    ///     .trace_observer("trace-observer.example.com", Some(443));
+    /// ```
+    #[cfg(feature = "infinite-tracing")]
+    pub fn trace_observer(mut self, host: &str, port: Option<u16>) -> Self {
+        self.trace_observer = Some(Endpoint {
+            host: host.to_string(),
+            path: String::new(),
+            port,
+        });
+        self
     }
 
-    fn get_backoff_sequence(&self) -> Vec<Duration> {
-        (0..self.retries_max)
-            .map(|num_retry| {
-                if num_retry == 0 {
-                    Duration::from_secs(0)
-                } else {
-                    self.backoff_factor * (2_u32.pow(num_retry - 1))
-                }
-            })
-            .collect()
+    /// Configure dry run mode.
+    ///
+    /// In dry run mode, the client creates requests for all batches, but never
+    /// opens a connection. Instead, it logs the batches that would have been
+    /// sent. This is useful for validating instrumentation without sending
+    /// data to New Relic.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).dry_run(true);
+    /// ```
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
     }
 
-    fn get_user_agent_header(&self) -> String {
-        let product_info = match &self.product_info {
-            Some(s) => format!(" {}/{}", s.0, s.1),
-            _ => "".to_string(),
-        };
+    /// Configure whether empty batches are skipped.
+    ///
+    /// Empty batches don't contain any data, but are sent by default, which
+    /// can serve as a heartbeat. If skipped, they are reported as sent
+    /// without a request being made.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).skip_empty_batches(true);
+    /// ```
+    pub fn skip_empty_batches(mut self, skip: bool) -> Self {
+        self.skip_empty_batches = skip;
+        self
+    }
 
-        format!("NewRelic-Rust-TelemetrySDK/{}{}", VERSION, product_info)
+    /// Configure skipping of batches identical to recently sent batches.
+    ///
+    /// The client keeps the content hashes of the given number of batches
+    /// sent last. Batches with the same content as one of them are not sent,
+    /// and reported as `SendResult::Duplicate`, which avoids duplicates when
+    /// applications retransmit batches. A window of 0 disables this.
+    ///
+    /// Batches are compared by their payload, not by their identity, so a
+    /// new batch which happens to contain the same spans and attributes as a
+    /// recently sent batch is skipped as well. Empty batches are never
+    /// skipped.
+    ///
+    /// Computing content hashes requires serializing batches an additional
+    /// time.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).dedup_window(100);
+    /// ```
+    pub fn dedup_window(mut self, window: usize) -> Self {
+        self.dedup_window = window;
+        self
     }
-}
 
-// An internal enum representing the state of a payload.
-#[derive(Debug, PartialEq)]
-enum SendableState {
-    // No retry should be made.
-    Done,
+    /// Configure the maximum number of spans sent in one payload.
+    ///
+    /// Span batches holding more spans are sent in chunks of at most this
+    /// many spans, instead of waiting for the ingest endpoint to reject the
+    /// payload as too large. Each chunk gets its own uuid.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).max_spans_per_batch(1000);
+    /// ```
+    pub fn max_spans_per_batch(mut self, max: usize) -> Self {
+        self.max_spans_per_batch = max;
+        self
+    }
 
-    // A retry should be made. Either after the given duration, or, if it
-    // is `None`, according to the backoff sequence.
-    Retry(Option<Duration>),
+    /// Configure the maximum number of chunks of a batch sent concurrently.
+    ///
+    /// Batches that are chunked because they hold too many spans, or split
+    /// because the ingest endpoint rejected them as too large, are sent as
+    /// several independent requests. Up to this many of these requests are in
+    /// flight at the same time, which lowers the time it takes to send large
+    /// batches. A value of 1 sends chunks one after another.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).max_concurrent_requests(8);
+    /// ```
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.max_concurrent_requests = max;
+        self
+    }
+
+    /// Configure whether compressed payloads are streamed.
+    ///
+    /// By default, a payload is compressed completely before the request is
+    /// sent. When streaming is enabled, the payload is compressed while it is
+    /// being sent, using chunked transfer encoding. This lowers the peak
+    /// memory usage for very large payloads, as the compressed payload is
+    /// never held in memory as a whole. Payloads sent to a local agent are
+    /// not compressed, and therefore never streamed.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).stream_payloads(true);
+    /// ```
+    pub fn stream_payloads(mut self, stream: bool) -> Self {
+        self.stream_payloads = stream;
+        self
+    }
+
+    /// Configure whether each attempt to send a batch uses a new request id.
+    ///
+    /// Each request carries an `x-request-id` header, which enables the
+    /// ingest service to detect duplicate requests. By default, the uuid of
+    /// a batch is used as request id, so it stays the same across all retries
+    /// of an unchanged payload, and only halves of a split batch get new ids.
+    ///
+    /// When enabled, a new request id is generated for every attempt, which
+    /// disables server-side deduplication of retried requests.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).request_id_per_attempt(true);
+    /// ```
+    pub fn request_id_per_attempt(mut self, per_attempt: bool) -> Self {
+        self.request_id_per_attempt = per_attempt;
+        self
+    }
+
+    /// Configure a generator for request ids.
+    ///
+    /// By default, the random version 4 uuid of a batch is sent as
+    /// `x-request-id` header. A custom generator allows using deterministic
+    /// ids instead, e.g. when replaying spooled batches or when integrating
+    /// with external deduplication systems.
+    ///
+    /// The generator is called once for every batch and once for each half of
+    /// a split batch. If `request_id_per_attempt` is enabled, it is called for
+    /// every attempt instead. Generated ids must be valid HTTP header values.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::sync::atomic::{AtomicU64, Ordering};
+    /// # let api_key = "api-key";
+    /// let counter = AtomicU64::new(0);
+    /// let mut builder = ClientBuilder::new(api_key).request_id_generator(move || {
+    ///     format!("replay-{}", counter.fetch_add(1, Ordering::SeqCst))
+    /// });
+    /// ```
+    pub fn request_id_generator<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.request_id_generator = Some(Arc::new(generator));
+        self
+    }
+
+    /// Configure a hook observing responses.
+    ///
+    /// The hook is called with the status code and the headers of every
+    /// response received from an ingest endpoint, including responses to
+    /// retried requests. This gives access to rate limit hints, `NR-*`
+    /// diagnostic headers or the `Date` header, e.g. for custom throttling.
+    ///
+    /// The hook is called on the task sending the batch, so it should return
+    /// quickly.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).response_hook(|status, headers| {
+    ///     if let Some(date) = headers.get("date") {
+    ///         println!("response {} at {:?}", status, date);
+    ///     }
+    /// });
+    /// ```
+    pub fn response_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(u16, &HeaderMap) + Send + Sync + 'static,
+    {
+        self.response_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Configure a hook computing additional authentication headers.
+    ///
+    /// The hook is called each time a request is created, including retries,
+    /// and the returned headers are added to the request. This allows
+    /// authenticating with egress proxies requiring their own, possibly
+    /// short-lived, tokens. Requests with invalid header names or values are
+    /// dropped.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).auth_headers(|| {
+    ///     vec![("Proxy-Authorization".to_string(), "Bearer token".to_string())]
+    /// });
+    /// ```
+    pub fn auth_headers<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Vec<(String, String)> + Send + Sync + 'static,
+    {
+        self.auth_headers = Some(Arc::new(hook));
+        self
+    }
+
+    /// Configure a hook observing the timing of sends.
+    ///
+    /// The hook is called once a batch is sent, rejected or dropped, with
+    /// the number of requests made, the time spent on these requests and the
+    /// time spent backing off between them. This allows monitoring the export
+    /// latency and detecting slow network paths to the ingest endpoint. The
+    /// chunks of a split batch are reported separately.
+    ///
+    /// The hook is called on the task sending the batch, so it should return
+    /// quickly.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).timing_hook(|timing| {
+    ///     println!(
+    ///         "{} requests took {:?}, backoff took {:?}",
+    ///         timing.attempts, timing.requests, timing.backoff
+    ///     );
+    /// });
+    /// ```
+    pub fn timing_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&SendTiming) + Send + Sync + 'static,
+    {
+        self.timing_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Configure the clock the client reads the current time from.
+    ///
+    /// The clock is used to measure the retry budget, shutdown timeouts and
+    /// the timing of requests. Waiting between retries is not affected.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use newrelic_telemetry::clock::ManualClock;
+    /// # use std::sync::Arc;
+    /// # use std::time::SystemTime;
+    /// # let api_key = "api-key";
+    /// let clock = Arc::new(ManualClock::new(SystemTime::now()));
+    /// let mut builder = ClientBuilder::new(api_key).clock(clock.clone());
+    /// ```
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Configure whether the skew of the local clock is corrected.
+    ///
+    /// When enabled, the local time is compared with the `Date` header of
+    /// responses from ingest endpoints, and the clock returned by
+    /// `Client::clock` is shifted by the difference. Taking span timestamps
+    /// from that clock makes sure data from hosts with drifting clocks lands
+    /// in the right time buckets. Skews below 2 seconds are ignored, as the
+    /// `Date` header has a resolution of one second.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).correct_clock_skew(true);
+    /// ```
+    pub fn correct_clock_skew(mut self, correct: bool) -> Self {
+        self.correct_clock_skew = correct;
+        self
+    }
+
+    /// Configure HTTP/2 usage.
+    ///
+    /// By default, requests are sent via HTTP/1.1. With this enabled, the
+    /// client exclusively uses HTTP/2 with prior knowledge, which works for
+    /// plaintext endpoints and proxies that don't handle HTTP/1.1 well.
+    ///
+    /// The TLS connector doesn't negotiate protocols via ALPN, so with TLS
+    /// enabled, the endpoint must accept HTTP/2 connections without ALPN.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).http2_only(true);
+    /// ```
+    pub fn http2_only(mut self, http2_only: bool) -> Self {
+        self.http2_only = http2_only;
+        self
+    }
+
+    /// Configure the maximum of idle connections per host.
+    ///
+    /// Connections are kept alive in a pool after a request is done, so they
+    /// can be reused for subsequent requests. Limiting the number of idle
+    /// connections reduces the number of open sockets, a limit of 0 disables
+    /// reusing connections.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).pool_max_idle_per_host(1);
+    /// ```
+    pub fn pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.pool_max_idle_per_host = max_idle;
+        self
+    }
+
+    /// Configure the timeout for idle connections.
+    ///
+    /// Idle connections kept alive in the pool are closed after this timeout.
+    /// Clients sending batches at longer intervals can keep a warm connection
+    /// by increasing the timeout, or by passing `None`, which disables the
+    /// timeout.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # let api_key = "api-key";
+    /// let mut builder =
+    ///     ClientBuilder::new(api_key).pool_idle_timeout(Some(Duration::from_secs(300)));
+    /// ```
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Resolve a host name to a fixed address.
+    ///
+    /// Connections to the host are made to the given address, bypassing the
+    /// system resolver, while the host name is still sent in the `Host`
+    /// header. This helps in environments with broken DNS, or for tests that
+    /// need to keep the production host name. Adding several addresses for
+    /// the same host makes them all candidates for a connection.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::net::{IpAddr, Ipv4Addr};
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key)
+    ///     .resolve("trace-api.newrelic.com", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    /// ```
+    pub fn resolve(mut self, host: &str, addr: IpAddr) -> Self {
+        self.resolve_overrides
+            .entry(host.to_string())
+            .or_default()
+            .push(addr);
+        self
+    }
+
+    /// Configure a custom resolver.
+    ///
+    /// The resolver is called with the host name for each new connection.
+    /// If it returns `None`, the system resolver is used. Static overrides
+    /// configured via `resolve` take precedence over the custom resolver.
+    ///
+    /// The resolver is called on the task opening the connection, so it
+    /// should not block.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::net::{IpAddr, Ipv4Addr};
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).resolver(|host| {
+    ///     if host.ends_with(".newrelic.com") {
+    ///         Some(vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))])
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    /// ```
+    pub fn resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Configure sending data via a local New Relic agent.
+    ///
+    /// Instead of sending data to the public ingest APIs, it is sent to the
+    /// HTTP listener of an agent running on the same host, which forwards it
+    /// to New Relic. This allows applications in locked-down networks to
+    /// piggyback on the connection of the agent. The listener is expected on
+    /// `localhost`, on port 8001 if no port is given.
+    ///
+    /// Requests to the agent are sent via plain HTTP without compression and
+    /// without an API key, so the API key given to the builder may be empty.
+    /// The path of the listener can be configured via `path_traces`.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// let mut builder = ClientBuilder::new("")
+    ///     .local_agent(None)
+    ///     .path_traces("/v1/traces");
+    /// ```
+    pub fn local_agent(mut self, port: Option<u16>) -> Self {
+        self.endpoint_traces = Endpoint {
+            host: LOCAL_AGENT_HOST.to_string(),
+            port: Some(port.unwrap_or(LOCAL_AGENT_PORT)),
+            path: TRACE_API_PATH.to_string(),
+        };
+        self.use_tls = false;
+        self.local_agent = true;
+        self
+    }
+
+    /// Configure a Unix domain socket for sending data.
+    ///
+    /// Instead of connecting to the ingest host, connections are made to the
+    /// given socket, as commonly exposed by local forwarders running as
+    /// sidecars. Requests are sent without TLS, the configured ingest host is
+    /// still sent in the `Host` header.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # let api_key = "api-key";
+    /// let mut builder = ClientBuilder::new(api_key).endpoint_uds("/var/run/newrelic.sock");
+    /// ```
+    #[cfg(all(unix, feature = "uds"))]
+    pub fn endpoint_uds<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.uds_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    // Configure TLS usage.
+    //
+    // New Relic endpoints exclusively support HTTPS. This is mainly provided
+    // for testing purposes.
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.use_tls = tls;
+        self
+    }
+
+    /// Build a client.
+    ///
+    /// The configuration is validated before building the client. For an
+    /// invalid configuration, the returned error can be downcast to a
+    /// `ConfigError`.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let builder = ClientBuilder::new(api_key);
+    ///
+    /// let client = builder.build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build(self) -> Result<Client> {
+        Client::new(self)
+    }
+
+    /// Build a blocking client.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let builder = ClientBuilder::new(api_key);
+    ///
+    /// let client = builder.build_blocking()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<blocking::Client> {
+        blocking::Client::new(self)
+    }
+
+    /// Build a client streaming spans to an Infinite Tracing trace observer.
+    ///
+    /// A trace observer must be configured via `trace_observer`, and the
+    /// client must be built from within a tokio runtime.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let builder = ClientBuilder::new(api_key)
+    ///     .trace_observer("trace-observer.example.com", None);
+    ///
+    /// let client = builder.build_infinite_tracing()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "infinite-tracing")]
+    pub fn build_infinite_tracing(self) -> Result<infinite_tracing::Client> {
+        infinite_tracing::Client::new(self)
+    }
+
+    // Validates the configuration.
+    //
+    // This catches configurations which would make every send fail, and
+    // reports them before the first batch is sent.
+    fn validate(&self) -> Result<(), ConfigError> {
+        // Local agents don't require an API key, and keys of a provider are
+        // only known once requests are sent.
+        if !self.local_agent && self.key_provider.is_none() {
+            if self.api_key.is_empty() {
+                return Err(ConfigError::EmptyApiKey);
+            }
+
+            if !self.api_key.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(ConfigError::InvalidApiKey);
+            }
+        }
+
+        if self.retry_budget == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::InvalidRetries(
+                "the retry budget must not be zero".to_string(),
+            ));
+        }
+
+        if self.max_batch_age == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::InvalidBatchLimit(
+                "the maximum age of batches must not be zero".to_string(),
+            ));
+        }
+
+        if self.max_spans_per_batch == 0 {
+            return Err(ConfigError::InvalidBatchLimit(
+                "the maximum of spans per batch must be at least 1".to_string(),
+            ));
+        }
+
+        if self.max_concurrent_requests == 0 {
+            return Err(ConfigError::InvalidConcurrency(
+                "the maximum of concurrent requests must be at least 1".to_string(),
+            ));
+        }
+
+        self.get_endpoint_traces().validate(self.use_tls)?;
+
+        #[cfg(feature = "infinite-tracing")]
+        {
+            if let Some(observer) = &self.trace_observer {
+                observer.validate(self.use_tls)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the endpoint traces are sent to, taking the OTLP mode and a
+    // custom path into account.
+    fn get_endpoint_traces(&self) -> Endpoint {
+        #[cfg(feature = "otlp")]
+        let mut endpoint = if self.otlp {
+            self.endpoint_traces.otlp_traces()
+        } else {
+            self.endpoint_traces.clone()
+        };
+        #[cfg(not(feature = "otlp"))]
+        let mut endpoint = self.endpoint_traces.clone();
+
+        if let Some(path) = &self.path_traces {
+            endpoint.path = path.clone();
+        }
+
+        endpoint
+    }
+
+    fn get_backoff_sequence(&self) -> Vec<Duration> {
+        (0..self.retries_max)
+            .map(|num_retry| {
+                let backoff = if num_retry == 0 {
+                    Duration::from_secs(0)
+                } else {
+                    self.backoff_factor * 2_u32.saturating_pow(num_retry - 1)
+                };
+
+                match self.backoff_max {
+                    Some(max) => backoff.min(max),
+                    None => backoff,
+                }
+            })
+            .collect()
+    }
+
+    // Returns the current API key, from the key provider if one is
+    // configured.
+    #[cfg(feature = "infinite-tracing")]
+    fn get_api_key(&self) -> ApiKey {
+        match &self.key_provider {
+            Some(provider) => ApiKey::from(provider.api_key()),
+            None => self.api_key.clone(),
+        }
+    }
+
+    fn get_user_agent_header(&self) -> String {
+        if let Some(user_agent) = &self.user_agent {
+            return user_agent.clone();
+        }
+
+        let product_info: String = self
+            .product_info
+            .iter()
+            .map(|(product, version)| format!(" {}/{}", product, version))
+            .collect();
+
+        format!("NewRelic-Rust-TelemetrySDK/{}{}", VERSION, product_info)
+    }
+
+    // Returns the attributes outgoing batches are enriched with.
+    fn get_enrichment_attributes(&self) -> Vec<(String, Value)> {
+        let mut attributes = vec![];
+
+        if self.enrich_attributes {
+            if let Some(service_name) = &self.service_name {
+                attributes.push(("service.name".to_string(), service_name.as_str().into()));
+            }
+
+            if let Some(host_name) = host_name() {
+                attributes.push(("host.name".to_string(), host_name.as_str().into()));
+            }
+
+            attributes.push(("process.pid".to_string(), std::process::id().into()));
+        }
+
+        #[cfg(feature = "host-metadata")]
+        {
+            if self.host_metadata {
+                let metadata = crate::metadata::HostMetadata::collect().attributes();
+                attributes.extend(sorted(metadata));
+            }
+        }
+
+        #[cfg(feature = "container-metadata")]
+        {
+            if self.container_metadata {
+                let metadata = crate::metadata::ContainerMetadata::collect().attributes();
+                attributes.extend(sorted(metadata));
+            }
+        }
+
+        attributes
+    }
+}
+
+// Returns the entries of an attribute map, sorted by key.
+#[cfg(any(feature = "host-metadata", feature = "container-metadata"))]
+fn sorted(attributes: HashMap<String, Value>) -> Vec<(String, Value)> {
+    let mut attributes: Vec<(String, Value)> = attributes.into_iter().collect();
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    attributes
+}
+
+// Returns the host name of the machine, if it can be determined.
+fn host_name() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| env::var("HOSTNAME"))
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+// Adds the given attributes as common attributes to a batch, unless the batch
+// already has common attributes with the same keys.
+fn enrich(batch: &mut SpanBatch, attributes: &[(String, Value)]) {
+    for (key, value) in attributes {
+        if !batch.attributes.contains_key(key.as_str()) {
+            batch.set_attribute(key, value.clone());
+        }
+    }
+}
+
+// Logs that a batch is dropped.
+//
+// Fields are logged as `key=value` pairs, so log pipelines can aggregate data
+// loss by type, item count and size. The size is the compressed size of the
+// payload, or zero if no payload was created. The hash is the one of the
+// payload, if it was created.
+fn log_drop<'a>(batch: &(dyn Sendable + 'a), bytes: usize, hash: Option<u64>, reason: &str) {
+    let hash = format_hash(hash);
+
+    error!(
+        "dropped batch uuid={} type={} items={} bytes={} hash={} reason={:?}",
+        batch.uuid(),
+        batch.data_type(),
+        batch.len(),
+        bytes,
+        hash,
+        reason
+    );
+    trace_event!(
+        ERROR,
+        uuid = batch.uuid(),
+        data_type = batch.data_type(),
+        items = batch.len(),
+        bytes,
+        hash = %hash,
+        reason,
+        "dropped batch"
+    );
+}
+
+// A payload created from a batch, with a hash of its content.
+//
+// The payload is created once per batch and reused for all attempts to send
+// it, and for skipping identical batches.
+struct Payload {
+    body: Vec<u8>,
+    hash: u64,
+}
+
+impl Payload {
+    fn new(body: Vec<u8>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+
+        Payload {
+            hash: hasher.finish(),
+            body,
+        }
+    }
+}
+
+// Formats a content hash for log messages.
+fn format_hash(hash: Option<u64>) -> String {
+    match hash {
+        Some(hash) => format!("{:016x}", hash),
+        None => "none".to_string(),
+    }
+}
+
+// The smallest estimated payload size rejected as too large by the ingest
+// endpoint. Payloads of at least that size are split before they are sent,
+// rather than being rejected and split on every attempt. The limit is
+// forgotten after `SIZE_LIMIT_TTL`, as the endpoint limit may be raised again.
+#[derive(Default)]
+struct SizeLimit(Mutex<Option<(usize, Instant)>>);
+
+impl SizeLimit {
+    // Records the estimated size of a payload rejected as too large.
+    fn learn(&self, rejected: usize, now: Instant) {
+        if let Ok(mut learned) = self.0.lock() {
+            match *learned {
+                Some((limit, since))
+                    if limit <= rejected
+                        && now.saturating_duration_since(since) < SIZE_LIMIT_TTL => {}
+                _ => {
+                    info!(
+                        "payloads of {} bytes are too large, splitting them",
+                        rejected
+                    );
+                    *learned = Some((rejected, now));
+                }
+            }
+        }
+    }
+
+    // Returns the maximum estimated size of payloads that are sent unsplit.
+    fn max_bytes(&self, now: Instant) -> usize {
+        let mut learned = match self.0.lock() {
+            Ok(learned) => learned,
+            Err(_) => return usize::MAX - 1,
+        };
+
+        match *learned {
+            Some((limit, since)) if now.saturating_duration_since(since) < SIZE_LIMIT_TTL => {
+                limit.saturating_sub(1)
+            }
+            Some((limit, _)) => {
+                debug!("forgetting the payload size limit of {} bytes", limit);
+                *learned = None;
+                usize::MAX - 1
+            }
+            None => usize::MAX - 1,
+        }
+    }
+
+    // Returns whether a payload of the given estimated size must be split.
+    fn exceeded_by(&self, bytes: usize, now: Instant) -> bool {
+        bytes > self.max_bytes(now)
+    }
+}
+
+// Content hashes of recently sent batches, used to skip retransmits of
+// identical payloads.
+#[derive(Default)]
+struct RecentHashes {
+    max: usize,
+    hashes: Mutex<VecDeque<u64>>,
+}
+
+impl RecentHashes {
+    fn new(max: usize) -> Self {
+        RecentHashes {
+            max,
+            hashes: Mutex::new(VecDeque::with_capacity(max)),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max > 0
+    }
+
+    fn contains(&self, hash: u64) -> bool {
+        match self.hashes.lock() {
+            Ok(hashes) => hashes.contains(&hash),
+            Err(_) => false,
+        }
+    }
+
+    // Adds a hash, evicting the oldest hash if the window is full.
+    fn insert(&self, hash: u64) {
+        if let Ok(mut hashes) = self.hashes.lock() {
+            if hashes.len() >= self.max {
+                hashes.pop_front();
+            }
+            hashes.push_back(hash);
+        }
+    }
+}
+
+// An internal enum representing the state of a payload.
+#[derive(Debug, PartialEq)]
+enum SendableState {
+    // The payload was accepted, no retry should be made.
+    Done,
+
+    // The payload was rejected by the ingest endpoint, no retry should be
+    // made.
+    Reject(ResponseError),
+
+    // The payload cannot be sent for the given reason, no retry should be
+    // made.
+    Drop(String),
+
+    // A retry should be made. Either after the given duration, or, if it
+    // is `None`, according to the backoff sequence.
+    Retry(Option<Duration>),
+
+    // The payload should be split and a retry should be made for both
+    // payloads.
+    Split,
+}
+
+/// Details of an error response from an ingest endpoint.
+///
+/// New Relic ingest endpoints reply with a JSON body containing a request id
+/// and, for most errors, a message. Both are included when reporting issues to
+/// New Relic support.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseError {
+    /// The HTTP status code of the response.
+    pub status: u16,
+
+    /// The error message, if the response contained one.
+    pub message: Option<String>,
+
+    /// The request id assigned by the ingest endpoint, if the response
+    /// contained one.
+    pub request_id: Option<String>,
+
+    /// Informational headers of the response, with lowercase names.
+    ///
+    /// These are rate limit hints, like `X-RateLimit-*` or `Retry-After`,
+    /// and `NR-*` diagnostics, which allow callers to throttle before being
+    /// rate limited. Headers of all responses can be observed via
+    /// `ClientBuilder::response_hook`.
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseError {
+    // Extracts error details from a response.
+    //
+    // The message is either given as `error` string, as `message` field of an
+    // `error` object, or as top level `message` field. Bodies which aren't
+    // valid JSON are ignored.
+    fn from_response<T: AsRef<[u8]>>(response: &Response<T>) -> Self {
+        let body: serde_json::Value =
+            serde_json::from_slice(response.body().as_ref()).unwrap_or_default();
+
+        let message = match &body["error"] {
+            serde_json::Value::String(message) => Some(message.as_str()),
+            error => error["message"]
+                .as_str()
+                .or_else(|| body["message"].as_str()),
+        };
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                INFO_HEADER_PREFIXES
+                    .iter()
+                    .any(|prefix| name.as_str().starts_with(prefix))
+            })
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        ResponseError {
+            status: response.status().as_u16(),
+            message: message.map(str::to_string),
+            request_id: body["requestId"].as_str().map(str::to_string),
+            headers,
+        }
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "response {}", self.status)?;
+
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id {})", request_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Errors for invalid client configurations.
+///
+/// Building a client fails with one of these errors if the configuration
+/// given to the `ClientBuilder` is invalid.
+///
+/// ```
+/// # use newrelic_telemetry::{ClientBuilder, ConfigError};
+/// let err = ClientBuilder::new("").build().unwrap_err();
+///
+/// assert_eq!(err.downcast_ref(), Some(&ConfigError::EmptyApiKey));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    /// The API key is empty.
+    EmptyApiKey,
+
+    /// The API key contains whitespace or characters other than ASCII.
+    InvalidApiKey,
+
+    /// The retry and backoff settings are inconsistent.
+    InvalidRetries(String),
+
+    /// An endpoint doesn't form a valid URI.
+    InvalidEndpoint(String),
+
+    /// A limit of the size of batches is invalid.
+    InvalidBatchLimit(String),
+
+    /// The limit of concurrent requests is invalid.
+    InvalidConcurrency(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::EmptyApiKey => write!(f, "the API key is empty"),
+            ConfigError::InvalidApiKey => write!(f, "the API key contains invalid characters"),
+            ConfigError::InvalidRetries(msg) => write!(f, "invalid retry settings: {}", msg),
+            ConfigError::InvalidEndpoint(msg) => write!(f, "invalid endpoint: {}", msg),
+            ConfigError::InvalidBatchLimit(msg) => write!(f, "invalid batch limit: {}", msg),
+            ConfigError::InvalidConcurrency(msg) => {
+                write!(f, "invalid concurrency limit: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Problems found by checking the connectivity to an ingest endpoint.
+///
+/// See `Client::check_connectivity`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectivityError {
+    /// No request could be created for the endpoint.
+    InvalidRequest(String),
+
+    /// The endpoint cannot be reached, for example because its host name
+    /// cannot be resolved, no connection can be established or the TLS
+    /// handshake fails.
+    Unreachable(String),
+
+    /// The endpoint rejected the API key.
+    Unauthorized(ResponseError),
+
+    /// The endpoint replied with an unexpected error.
+    UnexpectedResponse(ResponseError),
+
+    /// The endpoint didn't reply in time.
+    Timeout,
+}
+
+impl fmt::Display for ConnectivityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectivityError::InvalidRequest(msg) => write!(f, "cannot create request: {}", msg),
+            ConnectivityError::Unreachable(msg) => write!(f, "endpoint unreachable: {}", msg),
+            ConnectivityError::Unauthorized(e) => write!(f, "API key rejected: {}", e),
+            ConnectivityError::UnexpectedResponse(e) => write!(f, "unexpected {}", e),
+            ConnectivityError::Timeout => write!(f, "no response in time"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectivityError {}
+
+/// Outcome of sending a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SendResult {
+    /// The batch was accepted by the ingest endpoint.
+    ///
+    /// In dry run mode, batches are reported as sent without being sent.
+    Sent,
+
+    /// The batch was not sent, because a batch with the same content was sent
+    /// recently.
+    ///
+    /// See `ClientBuilder::dedup_window`.
+    Duplicate,
+
+    /// The batch was rejected by the ingest endpoint.
+    Rejected(ResponseError),
+
+    /// The batch was dropped, because it couldn't be sent or all retries
+    /// failed.
+    Dropped,
+
+    /// The batch was dropped, because no attempt to send it got a response.
+    ///
+    /// This tells which step of the last attempt failed, to distinguish
+    /// network, proxy or certificate problems from outages of the ingest
+    /// endpoint.
+    Failed(TransportFailure),
+
+    /// The batch was not sent before the deadline passed.
+    Timeout,
+}
+
+impl SendResult {
+    // Combines the results of sending the chunks of a split batch.
+    fn and(self, other: SendResult) -> SendResult {
+        match (self, other) {
+            (SendResult::Timeout, _) | (_, SendResult::Timeout) => SendResult::Timeout,
+            (SendResult::Rejected(e), _) | (_, SendResult::Rejected(e)) => SendResult::Rejected(e),
+            (SendResult::Failed(f), _) | (_, SendResult::Failed(f)) => SendResult::Failed(f),
+            (SendResult::Dropped, _) | (_, SendResult::Dropped) => SendResult::Dropped,
+            (SendResult::Duplicate, SendResult::Duplicate) => SendResult::Duplicate,
+            _ => SendResult::Sent,
+        }
+    }
+}
+
+/// Timing of sending a batch.
+///
+/// See `ClientBuilder::timing_hook`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SendTiming {
+    /// The number of requests made, including retries.
+    pub attempts: usize,
+
+    /// The total time spent on requests, until a response was received or
+    /// the request failed.
+    pub requests: Duration,
+
+    /// The total time spent backing off between requests.
+    pub backoff: Duration,
+}
+
+/// Step of sending a request that failed without a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportFailure {
+    /// The host name of the endpoint could not be resolved.
+    Dns,
+
+    /// No connection to the endpoint could be established.
+    Connect,
+
+    /// The TLS handshake with the endpoint failed.
+    Tls,
+
+    /// The connection failed while the request was sent or the response was
+    /// received.
+    Stream,
+}
+
+impl TransportFailure {
+    // Classifies an error of the HTTP client.
+    //
+    // Resolution and TLS errors are found by looking for their types in the
+    // causes of connection errors.
+    fn from_error(error: &hyper::Error) -> Self {
+        if !error.is_connect() {
+            return TransportFailure::Stream;
+        }
+
+        let mut cause = std::error::Error::source(error);
+        while let Some(e) = cause {
+            if e.is::<ResolveError>() {
+                return TransportFailure::Dns;
+            }
+            if e.is::<hyper_tls::native_tls::Error>() {
+                return TransportFailure::Tls;
+            }
+            cause = e.source();
+        }
+
+        TransportFailure::Connect
+    }
+}
+
+impl fmt::Display for TransportFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportFailure::Dns => write!(f, "DNS resolution failed"),
+            TransportFailure::Connect => write!(f, "connection failed"),
+            TransportFailure::Tls => write!(f, "TLS handshake failed"),
+            TransportFailure::Stream => write!(f, "connection broken"),
+        }
+    }
+}
+
+/// Outcome of shutting down a `Client`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// Batches that were still being sent when the shutdown deadline passed.
+    ///
+    /// Each batch is described by its uuid and its `Display` output.
+    pub abandoned: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// Returns `true` if all batches were sent before the deadline.
+    pub fn is_clean(&self) -> bool {
+        self.abandoned.is_empty()
+    }
+}
+
+// Tracks batches that are being sent by a client and its clones.
+#[derive(Default)]
+struct InFlight {
+    closed: AtomicBool,
+    next_id: AtomicU64,
+    batches: Mutex<HashMap<u64, String>>,
+}
+
+impl InFlight {
+    // Registers a batch as being sent. Returns `None` if the client is shut
+    // down.
+    fn track<'a>(self: &Arc<Self>, batch: &(dyn Sendable + 'a)) -> Option<InFlightGuard> {
+        if self.closed.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut batches) = self.batches.lock() {
+            batches.insert(id, format!("{} {}", batch.uuid(), batch));
+        }
+
+        Some(InFlightGuard {
+            in_flight: Arc::clone(self),
+            id,
+        })
+    }
+
+    fn pending(&self) -> Vec<String> {
+        match self.batches.lock() {
+            Ok(batches) => batches.values().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+// Unregisters a batch when sending it completes, or is cancelled.
+struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut batches) = self.in_flight.batches.lock() {
+            batches.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    api_key: ApiKey,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    user_agent: String,
+    backoff_sequence: Vec<Duration>,
+    retry_budget: Option<Duration>,
+    max_batch_age: Option<Duration>,
+    endpoint_traces: Uri,
+    data_format_traces: DataFormat,
+    dry_run: bool,
+    skip_empty_batches: bool,
+    max_spans_per_batch: usize,
+    max_concurrent_requests: usize,
+    stream_payloads: bool,
+    request_id_per_attempt: bool,
+    request_id_generator: Option<RequestIdGenerator>,
+    response_hook: Option<ResponseHook>,
+    auth_headers: Option<AuthHeaders>,
+    timing_hook: Option<TimingHook>,
+    clock: Arc<OffsetClock>,
+    correct_clock_skew: bool,
+    local_agent: bool,
+    #[cfg(feature = "otlp")]
+    otlp: bool,
+    enrichment: Vec<(String, Value)>,
+    in_flight: Arc<InFlight>,
+    recent_hashes: Arc<RecentHashes>,
+    size_limit: Arc<SizeLimit>,
+    expired: Arc<AtomicU64>,
+    client: hyper::Client<Connector>,
+}
+
+// The API key is redacted, as clients might be logged.
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("api_key", &redact_api_key(&self.api_key))
+            .field("key_provider", &self.key_provider.is_some())
+            .field("user_agent", &self.user_agent)
+            .field("backoff_sequence", &self.backoff_sequence)
+            .field("retry_budget", &self.retry_budget)
+            .field("max_batch_age", &self.max_batch_age)
+            .field("endpoint_traces", &self.endpoint_traces)
+            .field("data_format_traces", &self.data_format_traces)
+            .field("dry_run", &self.dry_run)
+            .field("skip_empty_batches", &self.skip_empty_batches)
+            .field("max_spans_per_batch", &self.max_spans_per_batch)
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .field("stream_payloads", &self.stream_payloads)
+            .field("request_id_per_attempt", &self.request_id_per_attempt)
+            .field("request_id_generator", &self.request_id_generator.is_some())
+            .field("response_hook", &self.response_hook.is_some())
+            .field("auth_headers", &self.auth_headers.is_some())
+            .field("timing_hook", &self.timing_hook.is_some())
+            .field("clock", &self.clock)
+            .field("correct_clock_skew", &self.correct_clock_skew)
+            .finish_non_exhaustive()
+    }
+}
+
+// Masks an API key, so it can be displayed without leaking credentials.
+//
+// Only the last four characters of keys long enough to stay unguessable are
+// shown.
+fn redact_api_key(api_key: &str) -> String {
+    let chars: Vec<char> = api_key.chars().collect();
+
+    if chars.len() > 8 {
+        let suffix: String = chars[chars.len() - 4..].iter().collect();
+        format!("****{}", suffix)
+    } else {
+        "****".to_string()
+    }
+}
+
+impl Client {
+    /// Constructs a `Client` from a `ClientBuilder`.
+    pub fn new(builder: ClientBuilder) -> Result<Self> {
+        builder.validate()?;
+
+        let user_agent = builder.get_user_agent_header();
+        let enrichment = builder.get_enrichment_attributes();
+        let mut backoff_seq = builder.get_backoff_sequence();
+        let endpoint_traces = builder.get_endpoint_traces();
+
+        // Without retries, batches are still sent once.
+        if backoff_seq.is_empty() {
+            backoff_seq.push(Duration::from_secs(0));
+        }
+
+        let mut http = HttpConnector::new_with_resolver(Resolver::new(
+            builder.resolve_overrides,
+            builder.resolver,
+        ));
+        http.enforce_http(false);
+        let https = Connector::Tcp(HttpsConnector::new_with_connector(http));
+
+        // Requests to Unix domain sockets are sent without TLS.
+        #[cfg(all(unix, feature = "uds"))]
+        let (connector, use_tls) = match builder.uds_path {
+            Some(path) => (Connector::Uds(Arc::new(path)), false),
+            None => (https, builder.use_tls),
+        };
+        #[cfg(not(all(unix, feature = "uds")))]
+        let (connector, use_tls) = (https, builder.use_tls);
+
+        Ok(Client {
+            api_key: builder.api_key,
+            key_provider: builder.key_provider,
+            endpoint_traces: endpoint_traces.uri(use_tls)?,
+            data_format_traces: builder.data_format_traces,
+            user_agent,
+            backoff_sequence: backoff_seq,
+            retry_budget: builder.retry_budget,
+            max_batch_age: builder.max_batch_age,
+            dry_run: builder.dry_run,
+            skip_empty_batches: builder.skip_empty_batches,
+            max_spans_per_batch: builder.max_spans_per_batch,
+            max_concurrent_requests: builder.max_concurrent_requests,
+            stream_payloads: builder.stream_payloads,
+            request_id_per_attempt: builder.request_id_per_attempt,
+            request_id_generator: builder.request_id_generator,
+            response_hook: builder.response_hook,
+            auth_headers: builder.auth_headers,
+            timing_hook: builder.timing_hook,
+            clock: Arc::new(OffsetClock::new(builder.clock)),
+            correct_clock_skew: builder.correct_clock_skew,
+            local_agent: builder.local_agent,
+            #[cfg(feature = "otlp")]
+            otlp: builder.otlp,
+            enrichment,
+            in_flight: Arc::new(InFlight::default()),
+            recent_hashes: Arc::new(RecentHashes::new(builder.dedup_window)),
+            size_limit: Arc::new(SizeLimit::default()),
+            expired: Arc::new(AtomicU64::new(0)),
+            client: hyper::Client::builder()
+                .http2_only(builder.http2_only)
+                .pool_max_idle_per_host(builder.pool_max_idle_per_host)
+                .pool_idle_timeout(builder.pool_idle_timeout)
+                .build::<_, hyper::Body>(connector),
+        })
+    }
+
+    /// Sends a span batch.
+    ///
+    /// This asynchronously sends a span batch, encapsulating retry and backoff
+    /// mechanisms defined in the [specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md)
+    /// and customized via the `ClientBuilder`.
+    pub async fn send_spans(&self, batch: SpanBatch) {
+        self.send_spans_tracked(batch).await;
+    }
+
+    /// Sends a span batch, giving up once the deadline passes.
+    ///
+    /// This behaves like `send_spans`, but caps the total time spent on
+    /// sending the batch, including retries and backoff, to the given
+    /// deadline. The returned result tells whether the batch was sent, dropped
+    /// or timed out.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::{ClientBuilder, SendResult, SpanBatch};
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let client = ClientBuilder::new(api_key).dry_run(true).build()?;
+    ///
+    /// let result = client
+    ///     .send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(2))
+    ///     .await;
+    /// assert_eq!(result, SendResult::Sent);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_spans_with_deadline(
+        &self,
+        batch: SpanBatch,
+        deadline: Duration,
+    ) -> SendResult {
+        match tokio::time::timeout(deadline, self.send_spans_tracked(batch)).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("deadline of {:?} passed, giving up sending", deadline);
+                SendResult::Timeout
+            }
+        }
+    }
+
+    /// Sends a shared span batch and returns the outcome.
+    ///
+    /// Unlike `send_spans`, this doesn't consume the batch, so callers can
+    /// keep a reference to it, to retry with their own policy or to log its
+    /// content when sending fails. The batch is only copied if it has to be
+    /// modified, that is when common attributes of the client are added or
+    /// when it is split because the payload is too large.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::{ClientBuilder, SendResult, Span, SpanBatch};
+    /// # use std::sync::Arc;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let client = ClientBuilder::new(api_key).dry_run(true).build()?;
+    ///
+    /// let batch = Arc::new(SpanBatch::from(vec![Span::new("id", "trace_id", 1000)]));
+    ///
+    /// let result = client.send_spans_arc(Arc::clone(&batch)).await;
+    /// if result != SendResult::Sent {
+    ///     eprintln!("failed to send {:?}", batch);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_spans_arc(&self, mut batch: Arc<SpanBatch>) -> SendResult {
+        let enriched = self
+            .enrichment
+            .iter()
+            .all(|(key, _)| batch.attributes.contains_key(key.as_str()));
+
+        // Batches that are too large are chunked, which requires a copy.
+        if batch.spans().len() > self.max_spans_per_batch {
+            let batch = Arc::try_unwrap(batch).unwrap_or_else(|batch| (*batch).clone());
+            return self.send_spans_tracked(batch).await;
+        }
+
+        if !enriched {
+            enrich(Arc::make_mut(&mut batch), &self.enrichment);
+        }
+
+        self.send_tracked(Box::new(batch), self.clock.instant())
+            .await
+    }
+
+    // Enriches and sends a span batch, tracking it as in flight.
+    async fn send_spans_tracked(&self, batch: SpanBatch) -> SendResult {
+        self.send_spans_queued(batch, self.clock.instant()).await
+    }
+
+    // Enriches and sends a span batch which was queued at the given instant,
+    // tracking it as in flight.
+    //
+    // Batches with more spans than allowed per payload are sent in chunks.
+    async fn send_spans_queued(&self, mut batch: SpanBatch, queued: Instant) -> SendResult {
+        enrich(&mut batch, &self.enrichment);
+
+        if batch.spans().len() <= self.max_spans_per_batch {
+            return self.send_tracked(Box::new(batch), queued).await;
+        }
+
+        debug!(
+            "sending {} in chunks of {} spans",
+            batch, self.max_spans_per_batch
+        );
+
+        let sends: Vec<_> = batch
+            .chunks(self.max_spans_per_batch)
+            .into_iter()
+            .map(|chunk| self.send_tracked(Box::new(chunk), queued))
+            .collect();
+
+        self.join_bounded(sends).await
+    }
+
+    // Sends a span batch which was queued at the given instant, tracking it
+    // as in flight.
+    async fn send_tracked(&self, batch: Box<dyn Sendable>, queued: Instant) -> SendResult {
+        let _guard = match self.in_flight.track(&*batch) {
+            Some(guard) => guard,
+            None => {
+                warn!("client is shut down, dropping {}", batch);
+                return SendResult::Dropped;
+            }
+        };
+
+        self.send(
+            batch,
+            &self.endpoint_traces,
+            &self.data_format_traces,
+            queued,
+        )
+        .await
+    }
+
+    // Sends the chunks of a split batch, which started to be sent and was
+    // queued at the given instants.
+    async fn send_chunks(
+        &self,
+        batch: Box<dyn Sendable>,
+        chunks: Vec<Box<dyn Sendable>>,
+        endpoint: &Uri,
+        format: &DataFormat,
+        started: Instant,
+        queued: Instant,
+    ) -> SendResult {
+        let sends: Vec<_> = std::iter::once(batch)
+            .chain(chunks)
+            .map(|chunk| self.send_since(chunk, None, endpoint, format, started, queued))
+            .collect();
+
+        self.join_bounded(sends).await
+    }
+
+    // Awaits the sends of independent chunks of a batch and combines their
+    // results. At most `max_concurrent_requests` sends are polled at a time.
+    async fn join_bounded<F>(&self, sends: Vec<F>) -> SendResult
+    where
+        F: Future<Output = SendResult>,
+    {
+        let mut results = stream::iter(sends).buffer_unordered(self.max_concurrent_requests);
+
+        let mut result = SendResult::Sent;
+        while let Some(r) = results.next().await {
+            result = result.and(r);
+        }
+
+        result
+    }
+
+    /// Returns the clock of the client.
+    ///
+    /// This is the clock configured via `ClientBuilder::clock`. If clock skew
+    /// correction is enabled, its wall clock time is shifted to match the
+    /// time of the ingest endpoint, so it should be used to create
+    /// timestamps of spans.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::{ClientBuilder, Span};
+    /// # fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let client = ClientBuilder::new(api_key).correct_clock_skew(true).build()?;
+    ///
+    /// let span = Span::new("id", "trace_id", client.clock().timestamp());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Returns the number of batches dropped for exceeding the maximum age.
+    ///
+    /// This counts batches sent by the client and all of its clones,
+    /// including batches dropped from the queue of the blocking client. See
+    /// `ClientBuilder::max_batch_age`.
+    pub fn expired_batches(&self) -> u64 {
+        self.expired.load(Ordering::SeqCst)
+    }
+
+    /// Shuts down the client.
+    ///
+    /// The client, and all of its clones, stop accepting new batches. This
+    /// waits until all batches that are being sent, including their retries,
+    /// are done, or until the timeout expires. Batches that were not done in
+    /// time are listed in the returned report.
+    ///
+    /// Sends that are still pending are not cancelled, it is up to the caller
+    /// to drop them.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let client = ClientBuilder::new(api_key).build()?;
+    ///
+    /// let report = client.shutdown(Duration::from_secs(5)).await;
+    /// assert!(report.is_clean());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        self.in_flight.closed.store(true, Ordering::SeqCst);
+
+        // The deadline is measured in real time, as the wait is, so that a
+        // manual client clock cannot keep the shutdown from timing out.
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let pending = self.in_flight.pending();
+
+            if pending.is_empty() {
+                return ShutdownReport::default();
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(
+                    "shutdown deadline passed, abandoning {} batches",
+                    pending.len()
+                );
+                return ShutdownReport { abandoned: pending };
+            }
+
+            tokio::time::delay_for(SHUTDOWN_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /// Checks that the trace endpoint can be reached and accepts the API key.
+    ///
+    /// This sends a single request with an empty span batch, which doesn't
+    /// create any data, without retries. It allows validating the
+    /// configuration and the network path at startup, instead of finding
+    /// problems when sending the first batch. The check fails if no response
+    /// is received within the given timeout.
+    ///
+    /// ```no_run
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::{ClientBuilder, ConnectivityError};
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let api_key = "api-key";
+    /// let client = ClientBuilder::new(api_key).build()?;
+    ///
+    /// match client.check_connectivity(Duration::from_secs(5)).await {
+    ///     Ok(()) => println!("ready to send"),
+    ///     Err(ConnectivityError::Unauthorized(_)) => println!("invalid API key"),
+    ///     Err(e) => println!("cannot reach New Relic: {}", e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_connectivity(&self, timeout: Duration) -> Result<(), ConnectivityError> {
+        let batch = SpanBatch::new();
+
+        let request = self
+            .request(
+                &batch,
+                &self.request_id(&batch),
+                &self.endpoint_traces,
+                &self.data_format_traces,
+            )
+            .map_err(|e| ConnectivityError::InvalidRequest(e.to_string()))?;
+
+        let response = match tokio::time::timeout(timeout, self.client.request(request)).await {
+            Ok(Ok(response)) => Self::read_error_body(response).await,
+            Ok(Err(e)) => {
+                let failure = TransportFailure::from_error(&e);
+                return Err(ConnectivityError::Unreachable(format!(
+                    "{}: {}",
+                    failure, e
+                )));
+            }
+            Err(_) => return Err(ConnectivityError::Timeout),
+        };
+
+        if response.status().is_success() {
+            debug!("connectivity check of {} passed", self.endpoint_traces);
+            return Ok(());
+        }
+
+        let error = ResponseError::from_response(&response);
+
+        match error.status {
+            401 | 403 => Err(ConnectivityError::Unauthorized(error)),
+            _ => Err(ConnectivityError::UnexpectedResponse(error)),
+        }
+    }
+
+    // Returns a gzip compressed version of the given data.
+    fn to_gzip(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    // Extract the value of the Retry-After HTTP response header
+    fn extract_retry_after(headers: &HeaderMap) -> Result<Duration> {
+        if let Some(dur) = headers.get("retry-after") {
+            Ok(Duration::from_secs(dur.to_str()?.parse::<u64>()?))
+        } else {
+            Err(anyhow!("missing retry-after header"))
+        }
+    }
+
+    // Sends a given `Sendable` asynchronously to a given endpoint, using the
+    // given data format. The maximum age of the batch counts from the instant
+    // it was queued at.
+    async fn send(
+        &self,
+        batch: Box<dyn Sendable>,
+        endpoint: &Uri,
+        format: &DataFormat,
+        queued: Instant,
+    ) -> SendResult {
+        if self.skip_empty_batches && batch.is_empty() {
+            debug!("skipping empty batch {}", batch.uuid());
+            return SendResult::Sent;
+        }
+
+        // Empty batches, like heartbeats, are always sent.
+        let payload = match self.recent_hashes.is_enabled() && !batch.is_empty() {
+            true => match self.payload(&*batch) {
+                Ok(payload) => Some(payload),
+                Err(e) => {
+                    log_drop(&*batch, 0, None, &format!("cannot create payload: {}", e));
+                    return SendResult::Dropped;
+                }
+            },
+            false => None,
+        };
+        let hash = payload.as_ref().map(|payload| payload.hash);
+
+        if let Some(hash) = hash {
+            if self.recent_hashes.contains(hash) {
+                info!(
+                    "skipping batch {} identical to a sent batch, hash={:016x}",
+                    batch.uuid(),
+                    hash
+                );
+                return SendResult::Duplicate;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("send", batch = %batch.uuid(), endpoint = %endpoint);
+
+        let started = self.clock.instant();
+        let send = self.send_since(batch, payload, endpoint, format, started, queued);
+
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(send, span);
+
+        let result = send.await;
+
+        if let (SendResult::Sent, Some(hash)) = (&result, hash) {
+            self.recent_hashes.insert(hash);
+        }
+
+        result
+    }
+
+    // Sends a given `Sendable`, which started to be sent at the given instant,
+    // reusing its payload if it was already created.
+    //
+    // The retry budget is counted from that instant, so it is shared by both
+    // halves of a split payload. The maximum age is counted from the instant
+    // the batch was queued at, which includes the time spent in the queue of
+    // the blocking client.
+    fn send_since<'a>(
+        &'a self,
+        mut batch: Box<dyn Sendable>,
+        payload: Option<Payload>,
+        endpoint: &'a Uri,
+        format: &'a DataFormat,
+        started: Instant,
+        queued: Instant,
+    ) -> Pin<Box<dyn Future<Output = SendResult> + Send + 'a>> {
+        Box::pin(async move {
+            // Split payloads known to be too large before sending them.
+            if let Some(estimated) = batch.estimated_bytes() {
+                let now = self.clock.instant();
+                if self.size_limit.exceeded_by(estimated, now) && batch.len() > 1 {
+                    let chunks = batch.split_by_size(self.size_limit.max_bytes(now));
+
+                    if !chunks.is_empty() {
+                        debug!(
+                            "payload of {} bytes exceeds the size limit, split into {} chunks",
+                            estimated,
+                            chunks.len() + 1
+                        );
+
+                        return self
+                            .send_chunks(batch, chunks, endpoint, format, started, queued)
+                            .await;
+                    }
+                }
+            }
+
+            let mut timing = SendTiming::default();
+            let result = self
+                .send_attempts(
+                    batch,
+                    payload,
+                    endpoint,
+                    format,
+                    started,
+                    queued,
+                    &mut timing,
+                )
+                .await;
+
+            if let (Some(hook), true) = (&self.timing_hook, timing.attempts > 0) {
+                hook(&timing);
+            }
+
+            result
+        })
+    }
+
+    // Makes the attempts to send a given `Sendable`, recording their timing.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_attempts(
+        &self,
+        mut batch: Box<dyn Sendable>,
+        payload: Option<Payload>,
+        endpoint: &Uri,
+        format: &DataFormat,
+        started: Instant,
+        queued: Instant,
+        timing: &mut SendTiming,
+    ) -> SendResult {
+        let payload = match payload.map_or_else(|| self.payload(&*batch), Ok) {
+            Ok(payload) => payload,
+            Err(e) => {
+                log_drop(&*batch, 0, None, &format!("cannot create payload: {}", e));
+                return SendResult::Dropped;
+            }
+        };
+        let hash = Some(payload.hash);
+
+        let mut request_id = self.request_id(&*batch);
+        let mut bytes = 0;
+        let mut failure = None;
+        let mut refreshed = false;
+
+        // The backoff sequence only advances after backing off, so a request
+        // retried with a refreshed API key doesn't take a slot of it.
+        let mut backoff = self.backoff_sequence.iter().enumerate().peekable();
+
+        while let Some(&(attempt, duration)) = backoff.peek() {
+            if attempt > 0 && self.request_id_per_attempt {
+                request_id = self.request_id(&*batch);
+            }
+
+            let body = payload.body.clone();
+            let request = match self.payload_request(body, &request_id, endpoint, format) {
+                Ok(r) => r,
+                Err(e) => {
+                    log_drop(&*batch, 0, hash, &format!("cannot create request: {}", e));
+                    return SendResult::Dropped;
+                }
+            };
+
+            // The size of streamed bodies is unknown before they are sent.
+            bytes = request.body().size_hint().exact().unwrap_or(0) as usize;
+
+            if self.dry_run {
+                info!("dry run, not sending {} to {}", batch, endpoint);
+                return SendResult::Sent;
+            }
 
-    // The payload should be split and a retry should be made for both
-    // payloads.
-    Split,
-}
+            let sent = self.clock.instant();
+            let status = match self.client.request(request).await {
+                Ok(response) => {
+                    if let Some(hook) = &self.response_hook {
+                        hook(response.status().as_u16(), response.headers());
+                    }
 
-pub struct Client {
-    api_key: String,
-    user_agent: String,
-    backoff_sequence: Vec<Duration>,
-    endpoint_traces: Uri,
-    client: hyper::Client<HttpsConnector<HttpConnector>>,
-}
+                    if self.correct_clock_skew {
+                        self.correct_skew(response.headers());
+                    }
 
-impl Client {
-    /// Constructs a `Client` from a `ClientBuilder`.
-    pub fn new(builder: ClientBuilder) -> Result<Self> {
-        let https = HttpsConnector::new();
-        let user_agent = builder.get_user_agent_header();
-        let backoff_seq = builder.get_backoff_sequence();
+                    failure = None;
+                    let response = Self::read_error_body(response).await;
+                    Self::process_response(&*batch, response)
+                }
+                Err(e) => {
+                    let kind = TransportFailure::from_error(&e);
+                    failure = Some(kind);
+                    Self::process_error(&*batch, &e, kind)
+                }
+            };
 
-        Ok(Client {
-            api_key: builder.api_key,
-            endpoint_traces: builder.endpoint_traces.uri(builder.use_tls)?,
-            user_agent: user_agent,
-            backoff_sequence: backoff_seq,
-            client: hyper::Client::builder().build::<_, hyper::Body>(https),
-        })
+            timing.attempts += 1;
+            timing.requests += self.clock.instant() - sent;
+
+            trace_event!(DEBUG, attempt, state = ?status, "request done");
+
+            let duration = match status {
+                SendableState::Done => return SendResult::Sent,
+                SendableState::Reject(e) => {
+                    // Retry once without backoff if the key provider has a
+                    // new key.
+                    if matches!(e.status, 401 | 403) && !refreshed {
+                        refreshed = true;
+
+                        if self.key_provider.as_ref().is_some_and(|p| p.refresh()) {
+                            info!("{}, API key refreshed, retrying {}", e, batch);
+                            continue;
+                        }
+                    }
+
+                    log_drop(&*batch, bytes, hash, &e.to_string());
+                    return SendResult::Rejected(e);
+                }
+                SendableState::Drop(reason) => {
+                    log_drop(&*batch, bytes, hash, &reason);
+                    return SendResult::Dropped;
+                }
+                SendableState::Retry(Some(duration)) => duration,
+                SendableState::Split => {
+                    // Batches of a single item cannot be split, so their size
+                    // says nothing about the size of batches worth splitting.
+                    let chunks = if batch.len() > 1 {
+                        let now = self.clock.instant();
+                        if let Some(estimated) = batch.estimated_bytes() {
+                            self.size_limit.learn(estimated, now);
+                        }
+                        batch.split_by_size(self.split_bytes())
+                    } else {
+                        vec![]
+                    };
+
+                    if chunks.is_empty() {
+                        // Remove the largest attributes of payloads that
+                        // cannot be split until they fit, and try again.
+                        let removed = Self::trim(&mut *batch, self.split_bytes());
+
+                        if removed.is_empty() {
+                            log_drop(&*batch, bytes, hash, "payload too large");
+                            return SendResult::Dropped;
+                        }
+
+                        warn!(
+                            "payload too large, removed attributes {:?} from batch {}",
+                            removed,
+                            batch.uuid()
+                        );
+                        trace_event!(WARN, attributes = ?removed, "trimmed attributes");
+                        return self
+                            .send_since(batch, None, endpoint, format, started, queued)
+                            .await;
+                    }
+
+                    return self
+                        .send_chunks(batch, chunks, endpoint, format, started, queued)
+                        .await;
+                }
+                _ => *duration,
+            };
+
+            if let Some(budget) = self.retry_budget {
+                if self.clock.instant() - started + duration > budget {
+                    let reason = format!("retry budget of {:?} exhausted", budget);
+                    log_drop(&*batch, bytes, hash, &reason);
+                    return Self::exhausted(failure);
+                }
+            }
+
+            if let Some(age) = self.max_batch_age {
+                if self.clock.instant().saturating_duration_since(queued) + duration > age {
+                    let reason = format!("maximum age of {:?} exceeded", age);
+                    log_drop(&*batch, bytes, hash, &reason);
+                    self.expired.fetch_add(1, Ordering::SeqCst);
+                    return Self::exhausted(failure);
+                }
+            }
+
+            trace_event!(DEBUG, delay = ?duration, "backing off");
+            tokio::time::delay_for(duration).await;
+            timing.backoff += duration;
+            backoff.next();
+        }
+
+        log_drop(&*batch, bytes, hash, "retries exhausted");
+        Self::exhausted(failure)
     }
 
-    /// Sends a span batch.
-    ///
-    /// This asynchronously sends a span batch, encapsulating retry and backoff
-    /// mechanisms defined in the [specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md)
-    /// and customized via the `ClientBuilder`.
-    pub async fn send_spans(&self, batch: SpanBatch) {
-        self.send(Box::new(batch), &self.endpoint_traces).await
+    // Returns the maximum estimated size of payloads created by splitting or
+    // trimming payloads rejected as too large.
+    fn split_bytes(&self) -> usize {
+        SPLIT_BYTES_MAX.min(self.size_limit.max_bytes(self.clock.instant()))
     }
 
-    // Returns a gzip compressed version of the given string.
-    fn to_gzip(text: &String) -> Result<Vec<u8>> {
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(text.as_bytes())?;
-        Ok(encoder.finish()?)
+    // Removes the largest attributes of a payload rejected as too large,
+    // until its estimated size is within the given limit. At least one
+    // attribute is removed, as the payload was too large for the endpoint.
+    // Returns the keys of the removed attributes.
+    fn trim(batch: &mut dyn Sendable, max_bytes: usize) -> Vec<String> {
+        let mut removed = vec![];
+
+        while let Some(key) = batch.trim() {
+            removed.push(key);
+
+            if batch
+                .estimated_bytes()
+                .is_none_or(|bytes| bytes <= max_bytes)
+            {
+                break;
+            }
+        }
+
+        removed
     }
 
-    // Extract the value of the Retry-After HTTP response header
-    fn extract_retry_after(headers: &HeaderMap) -> Result<Duration> {
-        if let Some(dur) = headers.get("retry-after") {
-            Ok(Duration::from_secs(dur.to_str()?.parse::<u64>()?))
-        } else {
-            Err(anyhow!("missing retry-after header"))
+    // Updates the offset of the clock from the `Date` header of a response.
+    //
+    // The offset is only changed if it differs significantly from the current
+    // one, so it doesn't jitter with the resolution of the header.
+    fn correct_skew(&self, headers: &HeaderMap) {
+        let date = match headers
+            .get(DATE)
+            .and_then(|d| d.to_str().ok())
+            .and_then(|d| httpdate::parse_http_date(d).ok())
+        {
+            Some(date) => date,
+            None => return,
+        };
+
+        let offset = match self.clock.offset_to(date) {
+            o if o.abs() < CLOCK_SKEW_MIN_MS => 0,
+            o => o,
+        };
+
+        if (offset - self.clock.offset_ms()).abs() >= CLOCK_SKEW_MIN_MS {
+            info!("correcting clock skew, offset {} ms", offset);
+            self.clock.set_offset_ms(offset);
         }
     }
 
-    // Sends a given `Sendable` asynchronously to a given endpoint.
-    fn send<'a>(
-        &'a self,
-        mut batch: Box<dyn Sendable>,
-        endpoint: &'a Uri,
-    ) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
-        Box::pin(async move {
-            for duration in self.backoff_sequence.iter() {
-                let request = match self.request(&*batch, endpoint) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        error!("cannot create request for {}, dropping due to {}", batch, e);
-                        return;
-                    }
-                };
+    // Returns the result of a batch dropped after all retries, given the
+    // transport failure of the last attempt, if any.
+    fn exhausted(failure: Option<TransportFailure>) -> SendResult {
+        match failure {
+            Some(failure) => SendResult::Failed(failure),
+            None => SendResult::Dropped,
+        }
+    }
 
-                let response = match self.client.request(request).await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        error!("cannot send request for {}, dropping due to {}", batch, e);
-                        return;
-                    }
-                };
+    // Returns a request id for sending the given batch.
+    //
+    // Unless configured otherwise, this is the uuid of the batch, which stays
+    // the same across retries of an unchanged payload.
+    fn request_id<'a>(&self, batch: &(dyn Sendable + 'a)) -> String {
+        match &self.request_id_generator {
+            Some(generate) => generate(),
+            None if self.request_id_per_attempt => Uuid::new_v4().to_string(),
+            None => batch.uuid().to_string(),
+        }
+    }
 
-                let status = Self::process_response(&*batch, response);
+    // Create a request from the given batch, request id, endpoint and data
+    // format.
+    fn request<'a>(
+        &self,
+        batch: &(dyn Sendable + 'a),
+        request_id: &str,
+        endpoint: &Uri,
+        format: &DataFormat,
+    ) -> Result<Request<Body>> {
+        let payload = self.payload(batch)?;
+        self.payload_request(payload.body, request_id, endpoint, format)
+    }
 
-                let duration = match status {
-                    SendableState::Done => return,
-                    SendableState::Retry(Some(duration)) => duration,
-                    SendableState::Split => {
-                        let batch2 = batch.split();
-                        self.send(batch, endpoint).await;
-                        self.send(batch2, endpoint).await;
-                        return;
-                    }
-                    _ => *duration,
-                };
+    // Create a payload from the given batch, in the format of the endpoint.
+    fn payload<'a>(&self, batch: &(dyn Sendable + 'a)) -> Result<Payload> {
+        #[cfg(feature = "otlp")]
+        {
+            if self.otlp {
+                return Ok(Payload::new(batch.marshall_otlp()?));
+            }
+        }
+
+        Ok(Payload::new(batch.marshall()?.into_bytes()))
+    }
 
-                thread::sleep(duration);
+    // Create a request from the given payload body, request id, endpoint and
+    // data format.
+    fn payload_request(
+        &self,
+        body: Vec<u8>,
+        request_id: &str,
+        endpoint: &Uri,
+        format: &DataFormat,
+    ) -> Result<Request<Body>> {
+        #[cfg(feature = "otlp")]
+        {
+            if self.otlp {
+                return self.request_otlp(body, request_id, endpoint);
             }
-        })
+        }
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(endpoint)
+            .header("Data-Format", format.format.as_str())
+            .header("Data-Format-Version", format.version.as_str())
+            .header("x-request-id", request_id)
+            .header(USER_AGENT, &self.user_agent)
+            .header(CONTENT_TYPE, "application/json");
+
+        self.authorize_and_encode(request, body)
     }
 
-    // Create a request from the given batch and endpoint.
-    fn request<'a>(&self, batch: &(dyn Sendable + 'a), endpoint: &Uri) -> Result<Request<Body>> {
-        let raw = batch.marshall()?;
-        let gzipped = Self::to_gzip(&raw)?;
+    // Adds the API key and additional authentication headers to a request and
+    // sets its gzipped body.
+    //
+    // Requests to local agents are sent without API key and compression. When
+    // payloads are streamed, the body is compressed while it is being sent.
+    fn authorize_and_encode(
+        &self,
+        mut request: RequestBuilder,
+        body: Vec<u8>,
+    ) -> Result<Request<Body>> {
+        if let Some(hook) = &self.auth_headers {
+            for (name, value) in hook() {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        }
+
+        if self.local_agent {
+            return Ok(request.body(Body::from(body))?);
+        }
+
+        let api_key = match &self.key_provider {
+            Some(provider) => ApiKey::from(provider.api_key()),
+            None => self.api_key.clone(),
+        };
+
+        let request = request
+            .header("Api-Key", api_key.as_str())
+            .header(CONTENT_ENCODING, "gzip");
 
-        Ok(Request::builder()
+        if self.stream_payloads {
+            return Ok(request.body(Body::wrap_stream(GzipStream::new(body)))?);
+        }
+
+        Ok(request.body(Body::from(Self::to_gzip(&body)?))?)
+    }
+
+    // Create an OTLP request from the given payload body, request id and
+    // endpoint.
+    #[cfg(feature = "otlp")]
+    fn request_otlp(
+        &self,
+        body: Vec<u8>,
+        request_id: &str,
+        endpoint: &Uri,
+    ) -> Result<Request<Body>> {
+        let request = Request::builder()
             .method(Method::POST)
             .uri(endpoint)
-            .header("Api-Key", &self.api_key)
-            .header("Data-Format", "newrelic")
-            .header("Data-Format-Version", "1")
-            .header("x-request-id", batch.uuid())
+            .header("x-request-id", request_id)
             .header(USER_AGENT, &self.user_agent)
-            .header(CONTENT_ENCODING, "gzip")
-            .header(CONTENT_TYPE, "application/json")
-            .body(Body::from(gzipped))?)
+            .header(CONTENT_TYPE, "application/x-protobuf");
+
+        self.authorize_and_encode(request, body)
     }
 
     // Based on the response from an ingest endpoint, decide whether to
@@ -454,125 +3058,518 @@ impl Client {
     //
     // See the [specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md#response-codes)
     // for further details.
-    fn process_response<'a, T>(
+    fn process_response<'a, T: AsRef<[u8]>>(
         batch: &(dyn Sendable + 'a),
         response: Response<T>,
     ) -> SendableState {
         let status = response.status();
 
+        if status.is_success() {
+            debug!("response {}, successfully sent {}", status, batch);
+            return SendableState::Done;
+        }
+
+        let details = ResponseError::from_response(&response);
+
         match status.as_u16() {
-            200..=299 => {
-                debug!("response {}, successfully sent {}", status, batch);
-            }
             400 | 401 | 403 | 404 | 405 | 409 | 410 | 411 => {
-                error!("response {}, dropping {}", status, batch);
+                debug!("{}, dropping {}", details, batch);
+                SendableState::Reject(details)
             }
             413 => {
-                info!(
-                    "response {}, payload too large, splitting {}",
-                    status, batch
-                );
-                return SendableState::Split;
+                info!("{}, payload too large, splitting {}", details, batch);
+                SendableState::Split
             }
             429 => match Self::extract_retry_after(response.headers()) {
                 Ok(duration) => {
                     info!(
-                        "response {}: retry interval {:?}, retrying {}",
-                        status, duration, batch
+                        "{}: retry interval {:?}, retrying {}",
+                        details, duration, batch
                     );
 
-                    return SendableState::Retry(Some(duration));
+                    SendableState::Retry(Some(duration))
                 }
                 Err(e) => {
-                    error!("response {}, {}, dropping {}", status, e, batch);
+                    info!("{}, {}, retry {}", details, e, batch);
+                    SendableState::Retry(None)
                 }
             },
             _ => {
-                debug!("response {}, retry {}", status, batch);
-                return SendableState::Retry(None);
+                debug!("{}, retry {}", details, batch);
+                SendableState::Retry(None)
             }
         }
-        return SendableState::Done;
+    }
+
+    // Reads the body of error responses, which contains error details.
+    //
+    // The body of successful responses is discarded.
+    async fn read_error_body(response: Response<Body>) -> Response<hyper::body::Bytes> {
+        let (parts, body) = response.into_parts();
+
+        let body = if parts.status.is_success() {
+            hyper::body::Bytes::new()
+        } else {
+            hyper::body::to_bytes(body).await.unwrap_or_default()
+        };
+
+        Response::from_parts(parts, body)
+    }
+
+    // Based on a transport error, decide whether to retry a payload.
+    //
+    // Transient network failures, like refused or reset connections, failed
+    // DNS lookups or timeouts, are retried. Errors caused by invalid requests
+    // or responses are permanent.
+    fn process_error<'a>(
+        batch: &(dyn Sendable + 'a),
+        error: &hyper::Error,
+        failure: TransportFailure,
+    ) -> SendableState {
+        if error.is_parse() || error.is_user() {
+            SendableState::Drop(format!("cannot send request: {}", error))
+        } else {
+            info!(
+                "cannot send request for {}, {}: {}, retrying",
+                batch, failure, error
+            );
+            SendableState::Retry(None)
+        }
+    }
+}
+
+impl TelemetryClient for Client {
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(Client::send_spans(self, batch))
+    }
+}
+
+/// Exposes the send pipeline as a `tower::Service`.
+///
+/// This allows composing standard tower middleware around the client. Each
+/// call sends the given batch to the trace endpoint, applying the retry and
+/// backoff mechanisms configured via the `ClientBuilder`. The outcome is
+/// returned as response, so middleware like retry policies can react to
+/// rejected or dropped batches.
+#[cfg(feature = "tower")]
+impl tower_service::Service<Box<dyn Sendable>> for Client {
+    type Response = SendResult;
+    type Error = std::convert::Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<SendResult, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, batch: Box<dyn Sendable>) -> Self::Future {
+        let client = self.clone();
+
+        Box::pin(async move {
+            let queued = client.clock.instant();
+            Ok(client.send_tracked(batch, queued).await)
+        })
+    }
+}
+
+/// Exposes sending span batches as a `tower::Service`.
+///
+/// Unlike the service for any `Sendable`, this adds the common attributes of
+/// the client to the batch, and sends batches with more spans than allowed
+/// per payload in chunks, like `Client::send_spans`.
+#[cfg(feature = "tower")]
+impl tower_service::Service<SpanBatch> for Client {
+    type Response = SendResult;
+    type Error = std::convert::Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<SendResult, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, batch: SpanBatch) -> Self::Future {
+        let client = self.clone();
+
+        Box::pin(async move { Ok(client.send_spans_tracked(batch).await) })
     }
 }
 
+#[cfg(feature = "infinite-tracing")]
+pub mod infinite_tracing;
+
+mod connector;
+use connector::Connector;
+
+mod gzip_stream;
+use gzip_stream::GzipStream;
+
+mod multi;
+pub use multi::MultiClient;
+
+mod resolver;
+use resolver::{CustomResolver, ResolveError, Resolver};
+
 #[cfg(feature = "blocking")]
 pub mod blocking {
-    use super::{ClientBuilder, SpanBatch};
-    use anyhow::Result;
+    use super::{log_drop, ClientBuilder, SendResult, SpanBatch, TelemetryClient};
+    use crate::{Clock, Priority, Sendable};
+    use anyhow::{anyhow, Result};
     use futures::future;
     use log::warn;
+    use std::cmp::Reverse;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::Ordering;
     use std::sync::mpsc;
     use std::sync::Mutex;
     use std::thread;
-    use tokio::runtime::Builder;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::{Builder, Handle, Runtime};
 
+    // A queued batch, with the instant it was queued at.
     enum SendableType {
-        Spans(SpanBatch),
+        Spans(SpanBatch, Instant),
+    }
+
+    impl SendableType {
+        fn priority(&self) -> Priority {
+            match self {
+                SendableType::Spans(batch, _) => batch.priority(),
+            }
+        }
+
+        fn queued(&self) -> Instant {
+            match self {
+                SendableType::Spans(_, queued) => *queued,
+            }
+        }
+
+        fn estimated_bytes(&self) -> usize {
+            match self {
+                SendableType::Spans(batch, _) => batch.estimated_json_bytes(),
+            }
+        }
+    }
+
+    // Drops batches that were queued for longer than the maximum age. Returns
+    // the number of dropped batches.
+    fn drop_expired(batches: &mut Vec<SendableType>, now: Instant, max_age: Duration) -> usize {
+        let len = batches.len();
+
+        batches.retain(|b| {
+            let expired = now.saturating_duration_since(b.queued()) > max_age;
+
+            if expired {
+                match b {
+                    SendableType::Spans(batch, _) => log_drop(
+                        batch,
+                        0,
+                        None,
+                        &format!("maximum age of {:?} exceeded", max_age),
+                    ),
+                }
+            }
+
+            !expired
+        });
+
+        len - batches.len()
+    }
+
+    // Drops batches that exceed the maximum queue size, in number of batches
+    // or in estimated bytes.
+    //
+    // Batches of the lowest priority are dropped first, and within a priority
+    // the batches that arrived last.
+    fn drop_excess(batches: &mut Vec<SendableType>, queue_max: usize, bytes_max: usize) {
+        let bytes = batches
+            .iter()
+            .fold(0usize, |sum, b| sum.saturating_add(b.estimated_bytes()));
+
+        if batches.len() <= queue_max && bytes <= bytes_max {
+            return;
+        }
+
+        // The sort is stable, so batches keep their order within a priority.
+        batches.sort_by_key(|b| Reverse(b.priority()));
+
+        let mut bytes = 0usize;
+        let keep = batches
+            .iter()
+            .take(queue_max)
+            .take_while(|b| {
+                bytes = bytes.saturating_add(b.estimated_bytes());
+                bytes <= bytes_max
+            })
+            .count();
+
+        warn!(
+            "back pressure, dropping {} span batches",
+            batches.len() - keep
+        );
+
+        for b in batches.drain(keep..) {
+            match b {
+                SendableType::Spans(batch, _) => log_drop(&batch, 0, None, "back pressure"),
+            }
+        }
+    }
+
+    // The runtime the blocking client sends batches on.
+    enum Executor {
+        // A runtime owned by the blocking client.
+        Runtime(Runtime),
+
+        // A runtime owned by the application.
+        Handle(Handle),
+    }
+
+    impl Executor {
+        fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+            match self {
+                Executor::Runtime(runtime) => runtime.block_on(future),
+                Executor::Handle(handle) => handle.block_on(future),
+            }
+        }
+
+        fn handle(&self) -> Handle {
+            match self {
+                Executor::Runtime(runtime) => runtime.handle().clone(),
+                Executor::Handle(handle) => handle.clone(),
+            }
+        }
     }
 
     pub struct Client {
         channel: Mutex<mpsc::Sender<Box<SendableType>>>,
         handle: thread::JoinHandle<()>,
+
+        // Used for sending batches on the calling thread.
+        client: super::Client,
+        runtime: Handle,
     }
 
     impl Client {
         pub fn new(builder: ClientBuilder) -> Result<Self> {
             let (tx, rx) = mpsc::channel::<Box<SendableType>>();
-            let mut runtime = Builder::new().threaded_scheduler().enable_all().build()?;
+            let mut executor = match &builder.runtime_handle {
+                Some(handle) => Executor::Handle(handle.clone()),
+                None => {
+                    Executor::Runtime(Builder::new().threaded_scheduler().enable_all().build()?)
+                }
+            };
             let queue_max = builder.blocking_queue_max;
+            let bytes_max = builder.blocking_queue_max_bytes;
+            let max_age = builder.max_batch_age;
             let client = builder.build()?;
+            let sync_client = client.clone();
+            let runtime = executor.handle();
 
             let handle = thread::spawn(move || loop {
                 let mut batches = vec![];
 
                 // Wait until at least one batch is received.
                 match rx.recv() {
-                    Ok(b) => batches.push(b),
+                    Ok(b) => batches.push(*b),
                     Err(_) => break,
                 };
 
                 // Empty the channel.
-                loop {
-                    match rx.try_recv() {
-                        Ok(b) => batches.push(b),
-                        Err(_) => break,
-                    }
+                while let Ok(b) = rx.try_recv() {
+                    batches.push(*b);
                 }
 
-                // Drop batches that exceed the maximum defined queue size.
-                if batches.len() > queue_max {
-                    warn!(
-                        "back pressure, dropping {} span batches",
-                        batches.len() - queue_max
-                    );
-                    batches.drain(queue_max..);
+                // Drop batches that are too old to be delivered.
+                if let Some(max_age) = max_age {
+                    let expired = drop_expired(&mut batches, client.clock.instant(), max_age);
+                    client.expired.fetch_add(expired as u64, Ordering::SeqCst);
                 }
 
+                // Drop batches that exceed the maximum defined queue size.
+                drop_excess(&mut batches, queue_max, bytes_max);
+
+                #[cfg(feature = "tracing")]
+                let span = tracing::info_span!("flush", batches = batches.len());
+
+                let send = future::join_all(batches.drain(..).map(|b| match b {
+                    SendableType::Spans(batch, queued) => client.send_spans_queued(batch, queued),
+                }));
+
+                #[cfg(feature = "tracing")]
+                let send = tracing::Instrument::instrument(send, span);
+
                 // Block until all batches are sent.
-                runtime.block_on(future::join_all(batches.drain(..).map(|b| match *b {
-                    SendableType::Spans(batch) => client.send_spans(batch),
-                })));
+                executor.block_on(send);
             });
 
             Ok(Client {
                 channel: Mutex::new(tx),
                 handle,
+                client: sync_client,
+                runtime,
             })
         }
 
         pub fn send_spans(&self, b: SpanBatch) {
             if let Ok(ch) = self.channel.lock() {
-                if let Err(_) = ch.send(Box::new(SendableType::Spans(b))) {}
+                let queued = self.client.clock.instant();
+                let _ = ch.send(Box::new(SendableType::Spans(b, queued)));
             }
         }
 
+        /// Sends a span batch on the calling thread and returns the outcome.
+        ///
+        /// Unlike `send_spans`, this bypasses the batch queue and blocks until
+        /// the batch is sent, including retries and backoff, or dropped. This
+        /// is meant for command line tools and batch jobs which must know
+        /// whether data was delivered before exiting.
+        ///
+        /// An error is returned if this is called from within an asynchronous
+        /// context, where blocking is not allowed.
+        ///
+        /// ```
+        /// # use anyhow::Result;
+        /// # use newrelic_telemetry::{ClientBuilder, SendResult, SpanBatch};
+        /// # fn main() -> Result<()> {
+        /// # let api_key = "api-key";
+        /// let client = ClientBuilder::new(api_key).dry_run(true).build_blocking()?;
+        ///
+        /// let result = client.send_spans_sync(SpanBatch::new())?;
+        /// assert_eq!(result, SendResult::Sent);
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub fn send_spans_sync(&self, batch: SpanBatch) -> Result<SendResult> {
+            if Handle::try_current().is_ok() {
+                return Err(anyhow!("cannot block within an asynchronous context"));
+            }
+
+            Ok(self.runtime.block_on(self.client.send_spans_tracked(batch)))
+        }
+
+        /// Returns the number of batches dropped for exceeding the maximum age.
+        ///
+        /// This includes batches dropped while waiting in the queue and
+        /// batches whose retries were abandoned. See
+        /// `ClientBuilder::max_batch_age`.
+        pub fn expired_batches(&self) -> u64 {
+            self.client.expired_batches()
+        }
+
         pub fn shutdown(self) {
             drop(self.channel);
 
             let _ = self.handle.join();
         }
     }
+
+    impl TelemetryClient for Client {
+        // The batch is queued, so the returned future is ready immediately.
+        fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+            Client::send_spans(self, batch);
+            Box::pin(future::ready(()))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn drop_excess_by_priority() {
+            let batch = |id: &str, priority| {
+                let batch = SpanBatch::from(vec![crate::Span::new(id, "tid", 1000)]);
+                SendableType::Spans(batch.with_priority(priority), Instant::now())
+            };
+
+            let mut batches = vec![
+                batch("low1", Priority::Low),
+                batch("normal1", Priority::Normal),
+                batch("high1", Priority::High),
+                batch("low2", Priority::Low),
+                batch("normal2", Priority::Normal),
+            ];
+
+            drop_excess(&mut batches, 3, usize::MAX);
+
+            let spans: Vec<&[crate::Span]> = batches
+                .iter()
+                .map(|b| match b {
+                    SendableType::Spans(batch, _) => batch.spans(),
+                })
+                .collect();
+            assert_eq!(
+                spans,
+                vec![
+                    &[crate::Span::new("high1", "tid", 1000)],
+                    &[crate::Span::new("normal1", "tid", 1000)],
+                    &[crate::Span::new("normal2", "tid", 1000)],
+                ]
+            );
+        }
+
+        #[test]
+        fn drop_expired_batches() {
+            let now = Instant::now();
+            let batch = |id: &str, age| {
+                let batch = SpanBatch::from(vec![crate::Span::new(id, "tid", 1000)]);
+                SendableType::Spans(batch, now - Duration::from_secs(age))
+            };
+
+            let mut batches = vec![batch("old", 120), batch("new", 10), batch("limit", 60)];
+            assert_eq!(drop_expired(&mut batches, now, Duration::from_secs(60)), 1);
+
+            let spans: Vec<&[crate::Span]> = batches
+                .iter()
+                .map(|b| match b {
+                    SendableType::Spans(batch, _) => batch.spans(),
+                })
+                .collect();
+            assert_eq!(
+                spans,
+                vec![
+                    &[crate::Span::new("new", "tid", 1000)],
+                    &[crate::Span::new("limit", "tid", 1000)],
+                ]
+            );
+        }
+
+        #[test]
+        fn drop_excess_by_bytes() {
+            let batch = |spans: usize, priority| {
+                let spans = (0..spans)
+                    .map(|i| crate::Span::new(&i.to_string(), "tid", 1000))
+                    .collect::<Vec<_>>();
+                SendableType::Spans(
+                    SpanBatch::from(spans).with_priority(priority),
+                    Instant::now(),
+                )
+            };
+
+            let mut batches = vec![
+                batch(1, Priority::Low),
+                batch(100, Priority::Normal),
+                batch(10, Priority::High),
+            ];
+            let bytes: Vec<usize> = batches.iter().map(|b| b.estimated_bytes()).collect();
+
+            // Within the limit, nothing is dropped.
+            drop_excess(&mut batches, 3, bytes.iter().sum());
+            assert_eq!(batches.len(), 3);
+
+            // The high and normal priority batches fit, the low priority batch
+            // is dropped.
+            drop_excess(&mut batches, 3, bytes[1] + bytes[2]);
+
+            let priorities: Vec<Priority> = batches.iter().map(|b| b.priority()).collect();
+            assert_eq!(priorities, vec![Priority::High, Priority::Normal]);
+
+            // Only the high priority batch fits.
+            drop_excess(&mut batches, 3, bytes[2]);
+
+            let priorities: Vec<Priority> = batches.iter().map(|b| b.priority()).collect();
+            assert_eq!(priorities, vec![Priority::High]);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -584,6 +3581,7 @@ mod tests {
     use hyper::{Method, Response};
     use std::fmt;
     use std::io::Read;
+    use std::sync::atomic::AtomicUsize;
     use std::time::Duration;
     pub struct TestBatch;
 
@@ -592,6 +3590,10 @@ mod tests {
             ""
         }
 
+        fn len(&self) -> usize {
+            1
+        }
+
         fn marshall(&self) -> Result<String> {
             Ok("".to_string())
         }
@@ -616,7 +3618,7 @@ mod tests {
             .product_info("Test", "1.0")
             .build()?;
 
-        assert_eq!(client.api_key, "0000");
+        assert_eq!(client.api_key.as_str(), "0000");
         assert_eq!(client.endpoint_traces.host(), Some("127.0.0.1"));
         assert_eq!(client.endpoint_traces.port_u16(), Some(8080));
         assert_eq!(client.endpoint_traces.scheme().unwrap().as_str(), "https");
@@ -628,13 +3630,38 @@ mod tests {
             client.backoff_sequence,
             vec![0, 2, 4, 8, 16, 32]
                 .into_iter()
-                .map(|d| Duration::from_secs(d))
+                .map(Duration::from_secs)
                 .collect::<Vec<Duration>>()
         );
 
         Ok(())
     }
 
+    #[test]
+    fn build_path_traces() -> Result<()> {
+        let client = ClientBuilder::new("0000")
+            .endpoint_traces("127.0.0.1", Some(8080))
+            .path_traces("/newrelic/trace")
+            .build()?;
+
+        assert_eq!(client.endpoint_traces.path(), "/newrelic/trace");
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_local_agent() -> Result<()> {
+        let client = ClientBuilder::new("").local_agent(None).build()?;
+
+        assert!(client.local_agent);
+        assert_eq!(
+            client.endpoint_traces.to_string(),
+            "http://localhost:8001/trace/v1"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn build_error() {
         let client = ClientBuilder::new("0000")
@@ -644,11 +3671,61 @@ mod tests {
         assert!(client.is_err());
     }
 
+    #[test]
+    fn build_config_error() {
+        let error = |builder: ClientBuilder| -> ConfigError {
+            builder.build().unwrap_err().downcast().unwrap()
+        };
+
+        assert_eq!(error(ClientBuilder::new("")), ConfigError::EmptyApiKey);
+        assert_eq!(
+            error(ClientBuilder::new("00 00")),
+            ConfigError::InvalidApiKey
+        );
+        assert_eq!(
+            error(ClientBuilder::new("0000\n")),
+            ConfigError::InvalidApiKey
+        );
+
+        // A backoff maximum below the factor gives short, flat retries.
+        assert!(ClientBuilder::new("0000")
+            .backoff_factor(Duration::from_secs(10))
+            .backoff_max(Duration::from_secs(5))
+            .build()
+            .is_ok());
+        assert!(matches!(
+            error(ClientBuilder::new("0000").retry_budget(Duration::from_secs(0))),
+            ConfigError::InvalidRetries(_)
+        ));
+        assert!(matches!(
+            error(ClientBuilder::new("0000").max_batch_age(Duration::from_secs(0))),
+            ConfigError::InvalidBatchLimit(_)
+        ));
+
+        assert!(matches!(
+            error(ClientBuilder::new("0000").max_spans_per_batch(0)),
+            ConfigError::InvalidBatchLimit(_)
+        ));
+        assert!(matches!(
+            error(ClientBuilder::new("0000").max_concurrent_requests(0)),
+            ConfigError::InvalidConcurrency(_)
+        ));
+
+        assert_eq!(
+            error(ClientBuilder::new("0000").endpoint_traces("", None)),
+            ConfigError::InvalidEndpoint("empty host".to_string())
+        );
+        assert!(matches!(
+            error(ClientBuilder::new("0000").endpoint_traces(":80", Some(8080))),
+            ConfigError::InvalidEndpoint(_)
+        ));
+    }
+
     #[test]
     fn uri_from_endpoint_ok() -> Result<()> {
         let endpoint = Endpoint {
             host: "host".to_string(),
-            path: TRACE_API_PATH,
+            path: TRACE_API_PATH.to_string(),
             port: Some(80),
         };
 
@@ -665,7 +3742,7 @@ mod tests {
     fn uri_from_endpoint_no_tls_ok() -> Result<()> {
         let endpoint = Endpoint {
             host: "host".to_string(),
-            path: TRACE_API_PATH,
+            path: TRACE_API_PATH.to_string(),
             port: Some(80),
         };
 
@@ -680,20 +3757,20 @@ mod tests {
 
     #[test]
     fn uri_from_endpoint_error() -> Result<()> {
-        for endpoint in vec![
+        for endpoint in [
             Endpoint {
                 host: "host:80".to_string(),
-                path: TRACE_API_PATH,
+                path: TRACE_API_PATH.to_string(),
                 port: Some(80),
             },
             Endpoint {
                 host: "?".to_string(),
-                path: TRACE_API_PATH,
+                path: TRACE_API_PATH.to_string(),
                 port: Some(80),
             },
             Endpoint {
                 host: "".to_string(),
-                path: TRACE_API_PATH,
+                path: TRACE_API_PATH.to_string(),
                 port: None,
             },
         ] {
@@ -701,7 +3778,9 @@ mod tests {
 
             assert!(
                 uri.is_err(),
-                format!("Could create an uri from {:?}: {:?}", endpoint, uri)
+                "Could create an uri from {:?}: {:?}",
+                endpoint,
+                uri
             );
         }
 
@@ -711,7 +3790,7 @@ mod tests {
     #[test]
     fn to_gzip() -> Result<()> {
         let text = "Text to be encoded".to_string();
-        let encoded = Client::to_gzip(&text)?;
+        let encoded = Client::to_gzip(text.as_bytes())?;
 
         let mut gz = GzDecoder::new(&encoded[..]);
         let mut decoded = String::new();
@@ -746,7 +3825,7 @@ mod tests {
     fn process_response_success() -> Result<()> {
         for code in 200..300 {
             let batch = Box::new(TestBatch);
-            let response = Response::builder().status(code).body(())?;
+            let response = Response::builder().status(code).body("")?;
 
             assert_eq!(
                 Client::process_response(&*batch, response),
@@ -759,23 +3838,195 @@ mod tests {
 
     #[test]
     fn process_response_error() -> Result<()> {
-        for code in vec![400, 401, 403, 404, 405, 409, 410, 411] {
+        for code in [400, 401, 403, 404, 405, 409, 410, 411] {
             let batch = Box::new(TestBatch);
-            let response = Response::builder().status(code).body(())?;
+            let response = Response::builder().status(code).body("")?;
 
             assert_eq!(
                 Client::process_response(&*batch, response),
-                SendableState::Done
+                SendableState::Reject(ResponseError {
+                    status: code,
+                    message: None,
+                    request_id: None,
+                    headers: vec![],
+                })
             );
         }
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn transport_failure() -> Result<()> {
+        let client = ClientBuilder::new("api-key").build()?;
+
+        let error = client
+            .client
+            .get("http://host.invalid".parse()?)
+            .await
+            .unwrap_err();
+        assert_eq!(TransportFailure::from_error(&error), TransportFailure::Dns);
+
+        // A server that closes connections without a response.
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+        let port = listener.local_addr()?.port();
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let _ = listener.accept();
+            }
+        });
+
+        let error = client
+            .client
+            .get(format!("http://127.0.0.1:{}", port).parse()?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            TransportFailure::from_error(&error),
+            TransportFailure::Stream
+        );
+
+        let error = client
+            .client
+            .get(format!("https://127.0.0.1:{}", port).parse()?)
+            .await
+            .unwrap_err();
+        assert_eq!(TransportFailure::from_error(&error), TransportFailure::Tls);
+
+        server.join().expect("server thread panicked");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn process_error_retry() -> Result<()> {
+        // Bind and release a port, so no one is listening on it.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+
+        let client = ClientBuilder::new("api-key").build()?;
+        let uri: Uri = format!("http://127.0.0.1:{}", port).parse()?;
+        let error = client.client.get(uri).await.unwrap_err();
+
+        assert!(error.is_connect());
+
+        let failure = TransportFailure::from_error(&error);
+        assert_eq!(failure, TransportFailure::Connect);
+        assert_eq!(
+            Client::process_error(&TestBatch, &error, failure),
+            SendableState::Retry(None)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn response_error() -> Result<()> {
+        let bodies = [
+            r#"{"requestId": "id", "error": "invalid"}"#,
+            r#"{"requestId": "id", "error": {"message": "invalid"}}"#,
+            r#"{"requestId": "id", "message": "invalid"}"#,
+        ];
+
+        for body in bodies {
+            let response = Response::builder().status(400).body(body)?;
+            let error = ResponseError::from_response(&response);
+
+            assert_eq!(error.status, 400);
+            assert_eq!(error.message.as_deref(), Some("invalid"));
+            assert_eq!(error.request_id.as_deref(), Some("id"));
+            assert_eq!(error.to_string(), "response 400: invalid (request id id)");
+        }
+
+        let response = Response::builder()
+            .status(503)
+            .header("Retry-After", "5")
+            .header("NR-Diagnostic", "overloaded")
+            .header(CONTENT_TYPE, "text/html")
+            .body("<html></html>")?;
+        let error = ResponseError::from_response(&response);
+
+        assert_eq!(
+            error.headers,
+            vec![
+                ("retry-after".to_string(), "5".to_string()),
+                ("nr-diagnostic".to_string(), "overloaded".to_string()),
+            ]
+        );
+        assert_eq!(error.message, None);
+        assert_eq!(error.request_id, None);
+        assert_eq!(error.to_string(), "response 503");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn tracing_events() -> Result<()> {
+        use std::sync::atomic::AtomicUsize;
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        // Records names of spans and counts events emitted by this crate.
+        #[derive(Default)]
+        struct Recorder {
+            spans: Mutex<Vec<&'static str>>,
+            events: AtomicUsize,
+        }
+
+        impl Subscriber for &'static Recorder {
+            fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+                metadata.target().starts_with("newrelic_telemetry")
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(span.metadata().name());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, _: &Event<'_>) {
+                self.events.fetch_add(1, Ordering::SeqCst);
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let recorder: &'static Recorder = Box::leak(Box::new(Recorder::default()));
+        let _guard = tracing::subscriber::set_default(recorder);
+
+        // Bind and release a port, so no one is listening on it.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+
+        let client = ClientBuilder::new("api-key")
+            .endpoint_traces("127.0.0.1", Some(port))
+            .tls(false)
+            .retries_max(1)
+            .build()?;
+
+        client.send_spans(SpanBatch::new()).await;
+
+        assert_eq!(*recorder.spans.lock().unwrap(), vec!["send"]);
+        // One event for the failed request, for backing off and for dropping
+        // the batch.
+        assert_eq!(recorder.events.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
     #[test]
     fn process_response_split() -> Result<()> {
         let batch = Box::new(TestBatch);
-        let response = Response::builder().status(413).body(())?;
+        let response = Response::builder().status(413).body("")?;
 
         assert_eq!(
             Client::process_response(&*batch, response),
@@ -791,7 +4042,7 @@ mod tests {
         let response = Response::builder()
             .status(429)
             .header("retry-after", "7")
-            .body(())?;
+            .body("")?;
 
         assert_eq!(
             Client::process_response(&*batch, response),
@@ -801,6 +4052,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn process_response_retry_without_header() -> Result<()> {
+        // Missing or invalid headers fall back to the backoff sequence.
+        for retry_after in [None, Some("seven")] {
+            let batch = Box::new(TestBatch);
+            let mut response = Response::builder().status(429);
+            if let Some(retry_after) = retry_after {
+                response = response.header("retry-after", retry_after);
+            }
+
+            assert_eq!(
+                Client::process_response(&*batch, response.body("")?),
+                SendableState::Retry(None)
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn process_response_retry() -> Result<()> {
         let mut codes = vec![402, 406, 407, 408];
@@ -810,7 +4080,7 @@ mod tests {
 
         for code in codes {
             let batch = Box::new(TestBatch);
-            let response = Response::builder().status(code).body(())?;
+            let response = Response::builder().status(code).body("")?;
 
             assert_eq!(
                 Client::process_response(&*batch, response),
@@ -826,14 +4096,19 @@ mod tests {
     #[test]
     fn request() -> Result<()> {
         let batch = Box::new(TestBatch);
-        let client = ClientBuilder::new("").build()?;
+        let client = ClientBuilder::new("api-key").build()?;
         let endpoint = Endpoint {
             host: "host".to_string(),
-            path: TRACE_API_PATH,
+            path: TRACE_API_PATH.to_string(),
             port: None,
         };
 
-        let request = client.request(&*batch, &endpoint.uri(true)?)?;
+        let request = client.request(
+            &*batch,
+            batch.uuid(),
+            &endpoint.uri(true)?,
+            &DataFormat::default(),
+        )?;
 
         assert_eq!(request.uri().port(), None);
         assert_eq!(request.uri().host(), Some("host"));
@@ -845,7 +4120,7 @@ mod tests {
         let expected_headers = vec![
             (CONTENT_ENCODING.as_str(), "gzip"),
             (CONTENT_TYPE.as_str(), "application/json"),
-            ("Api-Key", &client.api_key),
+            ("Api-Key", client.api_key.as_str()),
             ("Data-Format", "newrelic"),
             ("Data-Format-Version", "1"),
             ("x-request-id", batch.uuid()),
@@ -863,17 +4138,89 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn request_key_provider() -> Result<()> {
+        struct Rotating(Mutex<u32>);
+
+        impl KeyProvider for Rotating {
+            fn api_key(&self) -> String {
+                format!("key-{}", self.0.lock().unwrap())
+            }
+        }
+
+        let batch = Box::new(TestBatch);
+        let provider = Arc::new(Rotating(Mutex::new(1)));
+
+        // An empty API key is valid if a provider is configured.
+        let client = ClientBuilder::new("")
+            .key_provider(provider.clone())
+            .build()?;
+        let uri = client.endpoint_traces.clone();
+
+        let request = client.request(&*batch, batch.uuid(), &uri, &DataFormat::default())?;
+        assert_eq!(request.headers()["Api-Key"], "key-1");
+
+        *provider.0.lock().unwrap() = 2;
+
+        let request = client.request(&*batch, batch.uuid(), &uri, &DataFormat::default())?;
+        assert_eq!(request.headers()["Api-Key"], "key-2");
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_auth_headers() -> Result<()> {
+        let batch = Box::new(TestBatch);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let client = ClientBuilder::new("api-key")
+            .auth_headers(move || {
+                let call = counter.fetch_add(1, Ordering::SeqCst);
+                vec![(
+                    "Proxy-Authorization".to_string(),
+                    format!("Bearer {}", call),
+                )]
+            })
+            .build()?;
+        let uri = client.endpoint_traces.clone();
+
+        // Headers are computed for each request.
+        for call in 0..2 {
+            let request = client.request(&*batch, batch.uuid(), &uri, &DataFormat::default())?;
+            let headers = request.headers();
+
+            assert_eq!(headers["Proxy-Authorization"], format!("Bearer {}", call));
+            assert_eq!(headers["Api-Key"], "api-key");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Invalid headers fail the request.
+        let client = ClientBuilder::new("api-key")
+            .auth_headers(|| vec![("Invalid Name".to_string(), "value".to_string())])
+            .build()?;
+        assert!(client
+            .request(&*batch, batch.uuid(), &uri, &DataFormat::default())
+            .is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn request_port() -> Result<()> {
         let batch = Box::new(TestBatch);
-        let client = ClientBuilder::new("").build()?;
+        let client = ClientBuilder::new("api-key").build()?;
         let endpoint = Endpoint {
             host: "host".to_string(),
-            path: TRACE_API_PATH,
+            path: TRACE_API_PATH.to_string(),
             port: Some(80),
         };
 
-        let request = client.request(&*batch, &endpoint.uri(true)?)?;
+        let request = client.request(
+            &*batch,
+            batch.uuid(),
+            &endpoint.uri(true)?,
+            &DataFormat::default(),
+        )?;
 
         assert_eq!(request.uri().port().unwrap().as_u16(), 80);
         assert_eq!(request.uri().host(), Some("host"));
@@ -882,36 +4229,344 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn otlp_endpoint() -> Result<()> {
+        let client = ClientBuilder::new("api-key").otlp(true).build()?;
+        assert_eq!(
+            client.endpoint_traces.to_string(),
+            "https://otlp.nr-data.net:4318/v1/traces"
+        );
+
+        let client = ClientBuilder::new("api-key")
+            .endpoint_traces("127.0.0.1", Some(8080))
+            .otlp(true)
+            .build()?;
+        assert_eq!(
+            client.endpoint_traces.to_string(),
+            "https://127.0.0.1:8080/v1/traces"
+        );
+
+        let client = ClientBuilder::new("api-key")
+            .endpoint_traces("127.0.0.1", Some(8080))
+            .path_traces("otlp/v1/traces")
+            .otlp(true)
+            .build()?;
+        assert_eq!(
+            client.endpoint_traces.to_string(),
+            "https://127.0.0.1:8080/otlp/v1/traces"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn request_otlp() -> Result<()> {
+        let batch = Box::new(SpanBatch::from(vec![crate::span::Span::new("1", "2", 1)]));
+        let client = ClientBuilder::new("api-key").otlp(true).build()?;
+
+        let request = client.request(
+            &*batch,
+            batch.uuid(),
+            &client.endpoint_traces,
+            &DataFormat::default(),
+        )?;
+
+        let headers = request.headers();
+        assert_eq!(
+            headers.get(CONTENT_TYPE),
+            Some(&HeaderValue::from_static("application/x-protobuf"))
+        );
+        assert_eq!(
+            headers.get(CONTENT_ENCODING),
+            Some(&HeaderValue::from_static("gzip"))
+        );
+        assert_eq!(headers.get("Data-Format"), None);
+
+        // Batches that cannot be encoded are rejected.
+        let batch = Box::new(TestBatch);
+        assert!(client
+            .request(
+                &*batch,
+                batch.uuid(),
+                &client.endpoint_traces,
+                &DataFormat::default(),
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn enrichment_attributes() {
+        let attributes = ClientBuilder::new("")
+            .service_name("service")
+            .get_enrichment_attributes();
+        assert!(attributes.is_empty());
+
+        let attributes = ClientBuilder::new("")
+            .service_name("service")
+            .enrich_attributes(true)
+            .get_enrichment_attributes();
+        assert_eq!(
+            attributes[0],
+            ("service.name".to_string(), Value::from("service"))
+        );
+        assert!(attributes.contains(&("process.pid".to_string(), Value::from(std::process::id()))));
+    }
+
+    #[cfg(feature = "host-metadata")]
+    #[test]
+    fn host_metadata_attributes() {
+        let attributes = ClientBuilder::new("")
+            .host_metadata(true)
+            .get_enrichment_attributes();
+
+        assert!(attributes.contains(&("os.type".to_string(), Value::from(std::env::consts::OS))));
+        assert!(!attributes.iter().any(|(key, _)| key == "process.pid"));
+    }
+
+    #[test]
+    fn enrich() {
+        let mut batch = SpanBatch::new().attribute("service.name", "custom");
+        let attributes = vec![
+            ("service.name".to_string(), Value::from("service")),
+            ("host.name".to_string(), Value::from("host")),
+        ];
+
+        super::enrich(&mut batch, &attributes);
+
+        assert_eq!(
+            batch.attributes.get("service.name"),
+            Some(&Value::from("custom"))
+        );
+        assert_eq!(
+            batch.attributes.get("host.name"),
+            Some(&Value::from("host"))
+        );
+    }
+
+    #[test]
+    fn send_result_and() {
+        use SendResult::*;
+
+        assert_eq!(Sent.and(Sent), Sent);
+        assert_eq!(Sent.and(Dropped), Dropped);
+        assert_eq!(Dropped.and(Sent), Dropped);
+        assert_eq!(Dropped.and(Timeout), Timeout);
+        assert_eq!(Duplicate.and(Sent), Sent);
+        assert_eq!(Duplicate.and(Duplicate), Duplicate);
+        assert_eq!(Timeout.and(Sent), Timeout);
+        assert_eq!(
+            Dropped.and(Failed(TransportFailure::Dns)),
+            Failed(TransportFailure::Dns)
+        );
+
+        let error = ResponseError {
+            status: 400,
+            message: None,
+            request_id: None,
+            headers: vec![],
+        };
+        assert_eq!(
+            Dropped.and(Rejected(error.clone())),
+            Rejected(error.clone())
+        );
+        assert_eq!(Rejected(error.clone()).and(Timeout), Timeout);
+    }
+
+    #[test]
+    fn in_flight() {
+        let in_flight = Arc::new(InFlight::default());
+
+        let guard = in_flight.track(&TestBatch);
+        assert!(guard.is_some());
+        assert_eq!(in_flight.pending().len(), 1);
+
+        drop(guard);
+        assert!(in_flight.pending().is_empty());
+
+        in_flight.closed.store(true, Ordering::SeqCst);
+        assert!(in_flight.track(&TestBatch).is_none());
+        assert!(in_flight.pending().is_empty());
+    }
+
+    #[test]
+    fn size_limit() {
+        let limit = SizeLimit::default();
+        let now = Instant::now();
+        assert!(!limit.exceeded_by(1_000_000, now));
+
+        limit.learn(1000, now);
+        assert_eq!(limit.max_bytes(now), 999);
+        assert!(limit.exceeded_by(1000, now));
+
+        // Larger rejected payloads don't raise the limit.
+        limit.learn(2000, now);
+        assert_eq!(limit.max_bytes(now), 999);
+
+        // The limit is forgotten after a while.
+        let later = now + SIZE_LIMIT_TTL;
+        assert!(!limit.exceeded_by(1_000_000, later));
+
+        limit.learn(2000, later);
+        assert_eq!(limit.max_bytes(later), 1999);
+    }
+
+    #[test]
+    fn request_id() -> Result<()> {
+        let batch = Box::new(TestBatch);
+
+        let client = ClientBuilder::new("api-key").build()?;
+        assert_eq!(client.request_id(&*batch), batch.uuid());
+        assert_eq!(client.request_id(&*batch), client.request_id(&*batch));
+
+        let client = ClientBuilder::new("api-key")
+            .request_id_per_attempt(true)
+            .build()?;
+        assert_ne!(client.request_id(&*batch), batch.uuid());
+        assert_ne!(client.request_id(&*batch), client.request_id(&*batch));
+
+        let client = ClientBuilder::new("api-key")
+            .request_id_generator(|| "custom".to_string())
+            .build()?;
+        assert_eq!(client.request_id(&*batch), "custom");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn tower_service() -> Result<()> {
+        use crate::span::SpanBatch;
+        use tower_service::Service;
+
+        let mut client = ClientBuilder::new("api-key").dry_run(true).build()?;
+
+        let batch: Box<dyn Sendable> = Box::new(SpanBatch::new());
+        let result = futures::executor::block_on(client.call(batch));
+        assert_eq!(result, Ok(SendResult::Sent));
+
+        let result = futures::executor::block_on(client.call(SpanBatch::new()));
+        assert_eq!(result, Ok(SendResult::Sent));
+
+        // Failures are visible to middleware.
+        futures::executor::block_on(client.shutdown(Duration::from_secs(0)));
+
+        let result = futures::executor::block_on(client.call(SpanBatch::new()));
+        assert_eq!(result, Ok(SendResult::Dropped));
+
+        Ok(())
+    }
+
+    #[test]
+    fn redact_api_key() {
+        assert_eq!(super::redact_api_key(""), "****");
+        assert_eq!(super::redact_api_key("12345678"), "****");
+        assert_eq!(super::redact_api_key("0123456789abcdef"), "****cdef");
+    }
+
+    #[test]
+    fn debug_redacts_api_key() -> Result<()> {
+        let key = "0123456789abcdef";
+        let builder = ClientBuilder::new(key);
+
+        let output = format!("{:?}", builder);
+        assert!(!output.contains(key), "key leaked in {}", output);
+        assert!(output.contains("****cdef"), "key missing in {}", output);
+
+        let client = builder.build()?;
+
+        let output = format!("{:?}", client);
+        assert!(!output.contains(key), "key leaked in {}", output);
+        assert!(output.contains("****cdef"), "key missing in {}", output);
+
+        Ok(())
+    }
+
     #[test]
     fn builder_default() {
         let b = ClientBuilder::new("0000");
 
-        assert_eq!(b.api_key, "0000");
+        assert_eq!(b.api_key.as_str(), "0000");
         assert_eq!(b.backoff_factor, Duration::from_secs(5));
+        assert_eq!(b.backoff_max, None);
         assert_eq!(b.retries_max, 8);
+        assert_eq!(b.retry_budget, None);
         assert_eq!(b.endpoint_traces.host, "trace-api.newrelic.com");
         assert_eq!(b.endpoint_traces.port, None);
-        assert_eq!(b.product_info, None);
-        assert_eq!(b.use_tls, true);
+        assert_eq!(b.data_format_traces, DataFormat::default());
+        assert!(b.product_info.is_empty());
+        assert_eq!(b.user_agent, None);
+        assert!(b.use_tls);
+        assert!(!b.http2_only);
+        assert_eq!(b.pool_max_idle_per_host, usize::MAX);
+        assert_eq!(b.pool_idle_timeout, Some(Duration::from_secs(90)));
+        assert!(b.resolve_overrides.is_empty());
+        assert!(b.resolver.is_none());
+        assert!(!b.local_agent);
+        assert!(!b.dry_run);
+        assert!(!b.skip_empty_batches);
+        assert_eq!(b.dedup_window, 0);
+        assert_eq!(b.max_spans_per_batch, 2000);
+        assert_eq!(b.max_concurrent_requests, 4);
+        assert!(!b.stream_payloads);
+        assert!(!b.request_id_per_attempt);
+        assert!(b.request_id_generator.is_none());
+        assert!(b.response_hook.is_none());
+        assert!(b.timing_hook.is_none());
+        assert_eq!(b.path_traces, None);
+        assert_eq!(b.service_name, None);
+        assert!(!b.enrich_attributes);
     }
 
     #[test]
     fn builder_setters() {
         let b = ClientBuilder::new("0000")
             .backoff_factor(Duration::from_secs(10))
+            .backoff_max(Duration::from_secs(30))
             .retries_max(10)
+            .retry_budget(Duration::from_secs(60))
             .endpoint_traces("127.0.0.1", Some(8080))
-            .product_info("Test", "1.0");
+            .path_traces("/newrelic/trace")
+            .data_format_traces("test", "2")
+            .product_info("Test", "1.0")
+            .http2_only(true)
+            .pool_max_idle_per_host(1)
+            .pool_idle_timeout(None)
+            .dry_run(true)
+            .skip_empty_batches(true)
+            .dedup_window(10)
+            .max_spans_per_batch(100)
+            .max_concurrent_requests(2)
+            .stream_payloads(true)
+            .request_id_per_attempt(true);
 
-        assert_eq!(b.api_key, "0000");
+        assert_eq!(b.api_key.as_str(), "0000");
         assert_eq!(b.backoff_factor, Duration::from_secs(10));
+        assert_eq!(b.backoff_max, Some(Duration::from_secs(30)));
         assert_eq!(b.retries_max, 10);
+        assert_eq!(b.retry_budget, Some(Duration::from_secs(60)));
         assert_eq!(b.endpoint_traces.host, "127.0.0.1");
         assert_eq!(b.endpoint_traces.port, Some(8080));
+        assert_eq!(b.path_traces, Some("newrelic/trace".to_string()));
+        assert_eq!(b.data_format_traces.format, "test");
+        assert_eq!(b.data_format_traces.version, "2");
         assert_eq!(
             b.product_info,
-            Some(("Test".to_string(), "1.0".to_string()))
+            vec![("Test".to_string(), "1.0".to_string())]
         );
+        assert!(b.http2_only);
+        assert_eq!(b.pool_max_idle_per_host, 1);
+        assert_eq!(b.pool_idle_timeout, None);
+        assert!(b.dry_run);
+        assert!(b.skip_empty_batches);
+        assert_eq!(b.dedup_window, 10);
+        assert_eq!(b.max_spans_per_batch, 100);
+        assert_eq!(b.max_concurrent_requests, 2);
+        assert!(b.stream_payloads);
+        assert!(b.request_id_per_attempt);
     }
 
     #[test]
@@ -922,7 +4577,7 @@ mod tests {
             seq,
             vec![0, 5, 10, 20, 40, 80, 160, 320]
                 .into_iter()
-                .map(|d| Duration::from_secs(d))
+                .map(Duration::from_secs)
                 .collect::<Vec<Duration>>()
         );
     }
@@ -934,6 +4589,16 @@ mod tests {
         assert_eq!(seq, vec![]);
     }
 
+    #[test]
+    fn build_no_retry() -> Result<()> {
+        // Without retries, batches are still sent once.
+        let client = ClientBuilder::new("api-key").retries_max(0).build()?;
+
+        assert_eq!(client.backoff_sequence, vec![Duration::from_secs(0)]);
+
+        Ok(())
+    }
+
     #[test]
     fn backoff_sequence_custom() {
         let seq = ClientBuilder::new("")
@@ -945,7 +4610,24 @@ mod tests {
             seq,
             vec![0, 2, 4, 8, 16, 32]
                 .into_iter()
-                .map(|d| Duration::from_secs(d))
+                .map(Duration::from_secs)
+                .collect::<Vec<Duration>>()
+        );
+    }
+
+    #[test]
+    fn backoff_sequence_max() {
+        let seq = ClientBuilder::new("")
+            .backoff_factor(Duration::from_secs(1))
+            .backoff_max(Duration::from_secs(5))
+            .retries_max(6)
+            .get_backoff_sequence();
+
+        assert_eq!(
+            seq,
+            vec![0, 1, 2, 4, 5, 5]
+                .into_iter()
+                .map(Duration::from_secs)
                 .collect::<Vec<Duration>>()
         );
     }
@@ -968,4 +4650,27 @@ mod tests {
             format!("NewRelic-Rust-TelemetrySDK/{} Doc/1.0", VERSION)
         );
     }
+
+    #[test]
+    fn user_agent_header_products() {
+        let header = ClientBuilder::new("api-key")
+            .product_info("Doc", "1.0")
+            .product_info("Test", "2.0")
+            .get_user_agent_header();
+
+        assert_eq!(
+            header,
+            format!("NewRelic-Rust-TelemetrySDK/{} Doc/1.0 Test/2.0", VERSION)
+        );
+    }
+
+    #[test]
+    fn user_agent_header_override() {
+        let header = ClientBuilder::new("api-key")
+            .product_info("Doc", "1.0")
+            .user_agent("Custom/3.0")
+            .get_user_agent_header();
+
+        assert_eq!(header, "Custom/3.0");
+    }
 }
@@ -0,0 +1,124 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use super::resolver::Resolver;
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+#[cfg(all(unix, feature = "uds"))]
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(all(unix, feature = "uds"))]
+use {std::path::PathBuf, tokio::net::UnixStream};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Opens connections to ingest endpoints.
+///
+/// By default, connections are made via TCP, with or without TLS. With the
+/// `uds` feature, connections can be made to a Unix domain socket instead,
+/// ignoring the host of the request URI.
+#[derive(Clone)]
+pub enum Connector {
+    Tcp(HttpsConnector<HttpConnector<Resolver>>),
+    #[cfg(all(unix, feature = "uds"))]
+    Uds(Arc<PathBuf>),
+}
+
+/// A connection opened by a `Connector`.
+pub enum Stream {
+    Tcp(MaybeHttpsStream<TcpStream>),
+    #[cfg(all(unix, feature = "uds"))]
+    Uds(UnixStream),
+}
+
+impl Service<Uri> for Connector {
+    type Response = Stream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Stream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        match self {
+            Connector::Tcp(https) => https.poll_ready(cx),
+            #[cfg(all(unix, feature = "uds"))]
+            Connector::Uds(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            Connector::Tcp(https) => {
+                let connecting = https.call(uri);
+                Box::pin(async move { Ok(Stream::Tcp(connecting.await?)) })
+            }
+            #[cfg(all(unix, feature = "uds"))]
+            Connector::Uds(path) => {
+                let path = Arc::clone(path);
+                Box::pin(async move { Ok(Stream::Uds(UnixStream::connect(&*path).await?)) })
+            }
+        }
+    }
+}
+
+impl Connection for Stream {
+    fn connected(&self) -> Connected {
+        match self {
+            Stream::Tcp(stream) => stream.connected(),
+            #[cfg(all(unix, feature = "uds"))]
+            Stream::Uds(_) => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(all(unix, feature = "uds"))]
+            Stream::Uds(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(all(unix, feature = "uds"))]
+            Stream::Uds(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(all(unix, feature = "uds"))]
+            Stream::Uds(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(all(unix, feature = "uds"))]
+            Stream::Uds(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::Stream;
+use std::io::{self, Write};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// The number of uncompressed bytes fed to the encoder at a time.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// A stream of gzip compressed chunks of a payload.
+///
+/// The payload is compressed lazily while the request body is being sent, so
+/// only the compressed output of a single chunk is buffered at any time,
+/// rather than the whole compressed payload.
+pub(crate) struct GzipStream {
+    raw: Vec<u8>,
+    offset: usize,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl GzipStream {
+    pub(crate) fn new(raw: Vec<u8>) -> Self {
+        GzipStream {
+            raw,
+            offset: 0,
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+
+    // Compresses chunks of the payload until the encoder produced output, or
+    // the whole payload was consumed. Returns `None` once the stream is done.
+    fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let encoder = self.encoder.as_mut()?;
+
+        while self.offset < self.raw.len() {
+            let end = self.raw.len().min(self.offset + CHUNK_BYTES);
+
+            if let Err(e) = encoder.write_all(&self.raw[self.offset..end]) {
+                self.encoder = None;
+                return Some(Err(e));
+            }
+            self.offset = end;
+
+            let output = mem::take(encoder.get_mut());
+            if !output.is_empty() {
+                return Some(Ok(output));
+            }
+        }
+
+        // The payload was consumed, flush the remaining output and the gzip
+        // trailer.
+        self.encoder.take().map(GzEncoder::finish)
+    }
+}
+
+impl Stream for GzipStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().next_chunk())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use flate2::read::GzDecoder;
+    use futures::executor::block_on_stream;
+    use std::io::Read;
+
+    fn decode(chunks: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let encoded = chunks.concat();
+        let mut decoded = vec![];
+        GzDecoder::new(&encoded[..]).read_to_end(&mut decoded)?;
+
+        Ok(decoded)
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let chunks = block_on_stream(GzipStream::new(vec![])).collect::<io::Result<Vec<_>>>()?;
+
+        assert_eq!(chunks.len(), 1);
+        assert!(decode(&chunks)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunked() -> Result<()> {
+        // Pseudo random data, which doesn't compress well, spanning several
+        // chunks.
+        let raw: Vec<u8> = (0..5 * CHUNK_BYTES as u64)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+
+        let chunks =
+            block_on_stream(GzipStream::new(raw.clone())).collect::<io::Result<Vec<_>>>()?;
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| !c.is_empty()));
+        assert_eq!(decode(&chunks)?, raw);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use super::{enrich, ClientBuilder, TelemetryClient};
+use crate::attribute::Value;
+use crate::span::SpanBatch;
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc;
+use futures::future::{self, Either};
+use futures::{SinkExt, StreamExt};
+use hyper::http::uri::PathAndQuery;
+use log::{debug, info, warn};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tonic::codec::ProstCodec;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::Request;
+
+// The gRPC method of the trace observer accepting a stream of spans.
+const RECORD_SPAN_PATH: &str = "/com.newrelic.trace.v1.IngestService/RecordSpan";
+
+// The maximum number of spans queued for streaming.
+const SPAN_QUEUE_MAX: usize = 10_000;
+
+// Attributes that are sent as intrinsics rather than as user attributes.
+const INTRINSICS: [&str; 4] = ["name", "duration.ms", "parent.id", "service.name"];
+
+// Messages of the Infinite Tracing protocol.
+mod proto {
+    use std::collections::HashMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Span {
+        #[prost(string, tag = "1")]
+        pub trace_id: String,
+
+        #[prost(map = "string, message", tag = "2")]
+        pub intrinsics: HashMap<String, AttributeValue>,
+
+        #[prost(map = "string, message", tag = "3")]
+        pub user_attributes: HashMap<String, AttributeValue>,
+
+        #[prost(map = "string, message", tag = "4")]
+        pub agent_attributes: HashMap<String, AttributeValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AttributeValue {
+        #[prost(oneof = "attribute_value::Value", tags = "1, 2, 3, 4")]
+        pub value: Option<attribute_value::Value>,
+    }
+
+    pub mod attribute_value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            String(String),
+
+            #[prost(bool, tag = "2")]
+            Bool(bool),
+
+            #[prost(int64, tag = "3")]
+            Int(i64),
+
+            #[prost(double, tag = "4")]
+            Double(f64),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RecordStatus {
+        #[prost(uint64, tag = "1")]
+        pub messages_seen: u64,
+    }
+}
+
+impl From<&Value> for proto::AttributeValue {
+    fn from(value: &Value) -> Self {
+        use proto::attribute_value::Value as V;
+
+        // Integers that don't fit into the protocol's 64 bit signed integers
+        // are sent as floating point numbers.
+        let value = match value {
+            Value::Int(i) => V::Int(*i),
+            Value::UInt(u) if *u <= i64::MAX as u64 => V::Int(*u as i64),
+            Value::UInt(u) => V::Double(*u as f64),
+            Value::Int128(i) if i64::MIN as i128 <= *i && *i <= i64::MAX as i128 => {
+                V::Int(*i as i64)
+            }
+            Value::Int128(i) => V::Double(*i as f64),
+            Value::UInt128(u) if *u <= i64::MAX as u128 => V::Int(*u as i64),
+            Value::UInt128(u) => V::Double(*u as f64),
+            Value::Str(s) => V::String(s.clone()),
+            Value::Float(f) => V::Double(*f),
+            Value::Bool(b) => V::Bool(*b),
+        };
+
+        proto::AttributeValue { value: Some(value) }
+    }
+}
+
+// Converts the spans of a batch into messages of the Infinite Tracing
+// protocol.
+//
+// Common attributes of the batch are added to each span, unless the span has
+// an attribute with the same key.
+fn to_proto(batch: &SpanBatch) -> Vec<proto::Span> {
+    use proto::attribute_value::Value as V;
+
+    batch
+        .spans
+        .iter()
+        .map(|span| {
+            let mut message = proto::Span {
+                trace_id: span.trace_id.clone(),
+                ..Default::default()
+            };
+
+            let intrinsics = vec![
+                ("id", V::String(span.id.clone())),
+                ("trace.id", V::String(span.trace_id.clone())),
+                ("timestamp", V::Int(span.timestamp as i64)),
+            ];
+
+            for (key, value) in intrinsics {
+                message.intrinsics.insert(
+                    key.to_string(),
+                    proto::AttributeValue { value: Some(value) },
+                );
+            }
+
+            for (key, value) in batch.attributes.iter().chain(span.attributes.iter()) {
+                let attributes = if INTRINSICS.contains(&key.as_ref()) {
+                    &mut message.intrinsics
+                } else {
+                    &mut message.user_attributes
+                };
+
+                attributes.insert(key.to_string(), value.into());
+            }
+
+            message
+        })
+        .collect()
+}
+
+/// Streams spans to a New Relic Infinite Tracing trace observer.
+///
+/// Spans are queued and streamed to the trace observer via gRPC by a
+/// background task. If the stream is closed, it is reopened according to the
+/// backoff sequence configured via the `ClientBuilder`. Spans that are in
+/// flight while the stream is closed are lost.
+///
+/// The API key is sent as `license_key` metadata, as required by trace
+/// observers.
+///
+/// Dropping the client closes the stream.
+pub struct Client {
+    spans: mpsc::Sender<proto::Span>,
+    endpoint: String,
+    enrichment: Vec<(String, Value)>,
+    dry_run: bool,
+}
+
+impl Client {
+    /// Constructs a `Client` from a `ClientBuilder`.
+    ///
+    /// This must be called from within a tokio runtime, which runs the task
+    /// streaming spans to the trace observer.
+    pub fn new(builder: ClientBuilder) -> Result<Self> {
+        builder.validate()?;
+
+        let observer = builder
+            .trace_observer
+            .as_ref()
+            .ok_or_else(|| anyhow!("no trace observer configured"))?;
+        let uri = observer.uri(builder.use_tls)?;
+        let runtime = Handle::try_current()?;
+
+        let mut endpoint = Endpoint::from_shared(uri.to_string())?;
+        if builder.use_tls {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+        }
+
+        let (tx, rx) = mpsc::channel(SPAN_QUEUE_MAX);
+
+        if !builder.dry_run {
+            let worker = Worker {
+                channel: runtime.enter(|| endpoint.connect_lazy())?,
+                api_key: AsciiMetadataValue::from_str(&builder.get_api_key())?,
+                backoff_sequence: builder.get_backoff_sequence(),
+                backoff_factor: builder.backoff_factor,
+            };
+
+            runtime.spawn(worker.run(rx));
+        }
+
+        Ok(Client {
+            spans: tx,
+            endpoint: uri.to_string(),
+            enrichment: builder.get_enrichment_attributes(),
+            dry_run: builder.dry_run,
+        })
+    }
+
+    /// Queues the spans of a span batch for streaming.
+    ///
+    /// This never blocks. If the queue is full, spans are dropped.
+    pub fn send_spans(&self, mut batch: SpanBatch) {
+        enrich(&mut batch, &self.enrichment);
+
+        if self.dry_run {
+            info!("dry run, not streaming {} to {}", batch, self.endpoint);
+            return;
+        }
+
+        let mut sender = self.spans.clone();
+        let mut dropped = 0;
+
+        for span in to_proto(&batch) {
+            if sender.try_send(span).is_err() {
+                dropped += 1;
+            }
+        }
+
+        if dropped > 0 {
+            warn!("back pressure, dropping {} spans", dropped);
+        }
+    }
+}
+
+impl TelemetryClient for Client {
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Client::send_spans(self, batch);
+        Box::pin(future::ready(()))
+    }
+}
+
+// Streams queued spans to the trace observer, reconnecting if the stream is
+// closed.
+struct Worker {
+    channel: Channel,
+    api_key: AsciiMetadataValue,
+    backoff_sequence: Vec<Duration>,
+    backoff_factor: Duration,
+}
+
+impl Worker {
+    async fn run(self, mut spans: mpsc::Receiver<proto::Span>) {
+        let mut attempt = 0;
+
+        loop {
+            match self.stream(&mut spans).await {
+                Ok(true) => return,
+                Ok(false) => {
+                    // Observers closing every stream right away must not
+                    // cause a tight reconnect loop.
+                    attempt = 1;
+                    let delay = self.reconnect_delay(attempt);
+                    info!(
+                        "span stream closed by trace observer, reconnecting in {:?}",
+                        delay
+                    );
+
+                    tokio::time::delay_for(delay).await;
+                }
+                Err(e) => {
+                    let delay = self.reconnect_delay(attempt);
+                    warn!(
+                        "span stream to trace observer closed due to {}, reconnecting in {:?}",
+                        e, delay
+                    );
+
+                    tokio::time::delay_for(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // Returns the delay before reconnecting after the given number of failed
+    // attempts.
+    //
+    // The stream is reopened indefinitely. Once the backoff sequence is
+    // exhausted, its last value is used. Only the first reconnect may be
+    // immediate, later ones wait at least the backoff factor.
+    fn reconnect_delay(&self, attempt: usize) -> Duration {
+        let delay = self
+            .backoff_sequence
+            .get(attempt)
+            .or_else(|| self.backoff_sequence.last())
+            .copied()
+            .unwrap_or(self.backoff_factor);
+
+        match attempt {
+            0 => delay,
+            _ => delay.max(self.backoff_factor),
+        }
+    }
+
+    // Opens a stream and forwards queued spans to it.
+    //
+    // Returns `Ok(true)` if all senders were dropped and the stream was
+    // closed, and `Ok(false)` if the trace observer closed the stream
+    // gracefully.
+    async fn stream(&self, spans: &mut mpsc::Receiver<proto::Span>) -> Result<bool> {
+        let (mut sender, receiver) = mpsc::channel(0);
+
+        let mut request = Request::new(receiver);
+        request
+            .metadata_mut()
+            .insert("license_key", self.api_key.clone());
+
+        let mut grpc = tonic::client::Grpc::new(self.channel.clone());
+        grpc.ready().await?;
+
+        let receive = async {
+            let mut statuses = grpc
+                .streaming(
+                    request,
+                    PathAndQuery::from_static(RECORD_SPAN_PATH),
+                    ProstCodec::<proto::Span, proto::RecordStatus>::default(),
+                )
+                .await?
+                .into_inner();
+
+            while let Some(status) = statuses.message().await? {
+                debug!("trace observer has seen {} spans", status.messages_seen);
+            }
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let forward = async move {
+            while let Some(span) = spans.next().await {
+                sender.send(span).await?;
+            }
+
+            // Closing the request stream makes the trace observer close the
+            // response stream.
+            drop(sender);
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        futures::pin_mut!(receive, forward);
+
+        match future::select(receive, forward).await {
+            Either::Left((result, _)) => result.map(|_| false),
+            Either::Right((Ok(()), receive)) => receive.await.map(|_| true),
+            Either::Right((Err(e), _)) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::attribute_value::Value as V;
+    use super::{proto, to_proto, Worker};
+    use crate::attribute::Value;
+    use crate::client::ClientBuilder;
+    use crate::span::{Span, SpanBatch};
+    use anyhow::Result;
+    use futures::channel::mpsc;
+    use futures::{future, SinkExt, Stream};
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tonic::body::BoxBody;
+    use tonic::codec::{ProstCodec, Streaming};
+    use tonic::codegen::{http, BoxFuture, Never, Service};
+    use tonic::transport::{NamedService, Server};
+    use tonic::Status;
+
+    fn value(v: V) -> proto::AttributeValue {
+        proto::AttributeValue { value: Some(v) }
+    }
+
+    #[test]
+    fn attribute_values() {
+        let values = vec![
+            (Value::Int(-1), V::Int(-1)),
+            (Value::UInt(1), V::Int(1)),
+            (Value::UInt(u64::MAX), V::Double(u64::MAX as f64)),
+            (Value::Int128(-1), V::Int(-1)),
+            (Value::Int128(i128::MIN), V::Double(i128::MIN as f64)),
+            (Value::UInt128(1), V::Int(1)),
+            (Value::UInt128(u128::MAX), V::Double(u128::MAX as f64)),
+            (Value::Str("s".to_string()), V::String("s".to_string())),
+            (Value::Float(1.5), V::Double(1.5)),
+            (Value::Bool(true), V::Bool(true)),
+        ];
+
+        for (input, expected) in values {
+            assert_eq!(proto::AttributeValue::from(&input), value(expected));
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)
+            .name("span")
+            .attribute("key", "span")
+            .attribute("override", true)]
+        .into();
+        let batch = batch.attribute("common", 1).attribute("override", false);
+
+        let spans = to_proto(&batch);
+        assert_eq!(spans.len(), 1);
+
+        let span = &spans[0];
+        assert_eq!(span.trace_id, "tid1");
+
+        assert_eq!(span.intrinsics.len(), 4);
+        assert_eq!(span.intrinsics["id"], value(V::String("id1".to_string())));
+        assert_eq!(
+            span.intrinsics["trace.id"],
+            value(V::String("tid1".to_string()))
+        );
+        assert_eq!(span.intrinsics["timestamp"], value(V::Int(1000)));
+        assert_eq!(
+            span.intrinsics["name"],
+            value(V::String("span".to_string()))
+        );
+
+        assert_eq!(span.user_attributes.len(), 3);
+        assert_eq!(
+            span.user_attributes["key"],
+            value(V::String("span".to_string()))
+        );
+        assert_eq!(span.user_attributes["common"], value(V::Int(1)));
+        assert_eq!(span.user_attributes["override"], value(V::Bool(true)));
+
+        assert!(span.agent_attributes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconnect_delay() -> Result<()> {
+        let builder = ClientBuilder::new("api-key")
+            .retries_max(3)
+            .backoff_factor(Duration::from_secs(1));
+
+        let worker = Worker {
+            channel: tonic::transport::Endpoint::from_static("http://localhost").connect_lazy()?,
+            api_key: tonic::metadata::AsciiMetadataValue::from_static(""),
+            backoff_sequence: builder.get_backoff_sequence(),
+            backoff_factor: builder.backoff_factor,
+        };
+
+        assert_eq!(worker.reconnect_delay(0), Duration::from_secs(0));
+        assert_eq!(worker.reconnect_delay(2), Duration::from_secs(2));
+        assert_eq!(worker.reconnect_delay(10), Duration::from_secs(2));
+
+        let worker = Worker {
+            backoff_sequence: vec![],
+            ..worker
+        };
+        assert_eq!(worker.reconnect_delay(0), Duration::from_secs(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnect_delay_without_retries() -> Result<()> {
+        let builder = ClientBuilder::new("api-key")
+            .retries_max(1)
+            .backoff_factor(Duration::from_secs(1));
+
+        let worker = Worker {
+            channel: tonic::transport::Endpoint::from_static("http://localhost").connect_lazy()?,
+            api_key: tonic::metadata::AsciiMetadataValue::from_static(""),
+            backoff_sequence: builder.get_backoff_sequence(),
+            backoff_factor: builder.backoff_factor,
+        };
+
+        // Only the first reconnect is immediate.
+        assert_eq!(worker.reconnect_delay(0), Duration::from_secs(0));
+        assert_eq!(worker.reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(worker.reconnect_delay(10), Duration::from_secs(1));
+
+        Ok(())
+    }
+
+    // A trace observer recording streamed spans and the license keys they
+    // were sent with.
+    #[derive(Clone, Default)]
+    struct TraceObserver {
+        spans: Arc<Mutex<Vec<proto::Span>>>,
+        license_keys: Arc<Mutex<Vec<String>>>,
+    }
+
+    type StatusStream =
+        Pin<Box<dyn Stream<Item = Result<proto::RecordStatus, Status>> + Send + Sync>>;
+
+    impl NamedService for TraceObserver {
+        const NAME: &'static str = "com.newrelic.trace.v1.IngestService";
+    }
+
+    impl Service<http::Request<hyper::Body>> for TraceObserver {
+        type Response = http::Response<BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<hyper::Body>) -> Self::Future {
+            let observer = self.clone();
+
+            Box::pin(async move {
+                let codec = ProstCodec::<proto::RecordStatus, proto::Span>::default();
+                Ok(tonic::server::Grpc::new(codec)
+                    .streaming(observer, request)
+                    .await)
+            })
+        }
+    }
+
+    impl Service<tonic::Request<Streaming<proto::Span>>> for TraceObserver {
+        type Response = tonic::Response<StatusStream>;
+        type Error = Status;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: tonic::Request<Streaming<proto::Span>>) -> Self::Future {
+            if let Some(key) = request.metadata().get("license_key") {
+                let key = key.to_str().unwrap_or_default().to_string();
+                self.license_keys.lock().unwrap().push(key);
+            }
+
+            let spans = self.spans.clone();
+            let mut input = request.into_inner();
+            let (mut tx, rx) = mpsc::channel(0);
+
+            tokio::spawn(async move {
+                let mut seen = 0;
+
+                while let Ok(Some(span)) = input.message().await {
+                    spans.lock().unwrap().push(span);
+                    seen += 1;
+
+                    let status = proto::RecordStatus {
+                        messages_seen: seen,
+                    };
+                    if tx.send(Ok(status)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let statuses: StatusStream = Box::pin(rx);
+            Box::pin(future::ok(tonic::Response::new(statuses)))
+        }
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn stream_spans() -> Result<()> {
+        let observer = TraceObserver::default();
+        let mut listener = TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>()?).await?;
+        let port = listener.local_addr()?.port();
+        let server = Server::builder().add_service(observer.clone());
+
+        tokio::spawn(async move { server.serve_with_incoming(listener.incoming()).await });
+
+        let client = ClientBuilder::new("0123456789")
+            .trace_observer("127.0.0.1", Some(port))
+            .tls(false)
+            .build_infinite_tracing()?;
+
+        client.send_spans(vec![Span::new("id1", "tid1", 1000)].into());
+
+        for _ in 0..50 {
+            if !observer.spans.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+
+        let spans = observer.spans.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].trace_id, "tid1");
+
+        let license_keys = observer.license_keys.lock().unwrap();
+        assert_eq!(*license_keys, vec!["0123456789".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_without_trace_observer() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            assert!(ClientBuilder::new("api-key")
+                .build_infinite_tracing()
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn build_without_runtime() {
+        let builder = ClientBuilder::new("api-key").trace_observer("localhost", None);
+
+        assert!(builder.build_infinite_tracing().is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run() -> Result<()> {
+        let client = ClientBuilder::new("api-key")
+            .trace_observer("localhost", None)
+            .dry_run(true)
+            .build_infinite_tracing()?;
+
+        client.send_spans(vec![Span::new("id1", "tid1", 1000)].into());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use super::{Client, ClientBuilder, SendResult, ShutdownReport, TelemetryClient};
+use crate::span::SpanBatch;
+use anyhow::Result;
+use futures::future;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A client sending each batch to several accounts or regions.
+///
+/// Each account is configured via its own `ClientBuilder`, with its own API
+/// key, endpoint and retry settings. Batches are sent to all accounts in
+/// parallel, which is useful to report the same data to several customers or
+/// to migrate between accounts.
+///
+/// ```
+/// # use anyhow::Result;
+/// # use newrelic_telemetry::{ClientBuilder, MultiClient, SendResult, SpanBatch};
+/// # #[tokio::main]
+/// # async fn main() -> Result<()> {
+/// let client = MultiClient::new(vec![
+///     ClientBuilder::new("us-api-key").dry_run(true),
+///     ClientBuilder::new("eu-api-key")
+///         .endpoint_traces("trace-api.eu.newrelic.com", None)
+///         .dry_run(true),
+/// ])?;
+///
+/// let results = client.send_spans(SpanBatch::new()).await;
+/// assert_eq!(results, vec![SendResult::Sent, SendResult::Sent]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MultiClient {
+    clients: Vec<Client>,
+}
+
+impl MultiClient {
+    /// Constructs a `MultiClient` with a client for each `ClientBuilder`.
+    ///
+    /// Fails if any of the builders is invalid.
+    pub fn new<I>(builders: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = ClientBuilder>,
+    {
+        let clients = builders
+            .into_iter()
+            .map(Client::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(MultiClient { clients })
+    }
+
+    /// Returns the clients of the accounts, in the order of their builders.
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    /// Sends a span batch to all accounts.
+    ///
+    /// Every account receives a copy of the batch with its own uuid, derived
+    /// from the uuid of the batch and the position of the account, so request
+    /// ids are unique per account but stay the same when a batch is replayed.
+    /// The first account receives the batch unchanged. The returned results
+    /// are in the order of the clients, a failure to send to one account
+    /// doesn't affect the others.
+    pub async fn send_spans(&self, batch: SpanBatch) -> Vec<SendResult> {
+        let batches: Vec<SpanBatch> = (1..self.clients.len())
+            .map(|account| {
+                let mut copy = batch.clone();
+                copy.set_uuid(&format!("{}-{}", batch.uuid(), account));
+                copy
+            })
+            .collect();
+
+        let sends = self
+            .clients
+            .iter()
+            .zip(std::iter::once(batch).chain(batches))
+            .map(|(client, batch)| client.send_spans_arc(Arc::new(batch)));
+
+        future::join_all(sends).await
+    }
+
+    /// Shuts down the clients of all accounts.
+    ///
+    /// This waits for all clients in parallel, so the timeout applies to all
+    /// of them at once. The returned report lists the abandoned batches of
+    /// all clients.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let reports =
+            future::join_all(self.clients.iter().map(|client| client.shutdown(timeout))).await;
+
+        ShutdownReport {
+            abandoned: reports.into_iter().flat_map(|r| r.abandoned).collect(),
+        }
+    }
+}
+
+impl TelemetryClient for MultiClient {
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(async move {
+            MultiClient::send_spans(self, batch).await;
+        })
+    }
+}
@@ -0,0 +1,123 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::service::Service;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::{self, Future};
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::vec;
+
+/// Resolves host names to addresses, overriding the system resolver.
+pub type CustomResolver = Arc<dyn Fn(&str) -> Option<Vec<IpAddr>> + Send + Sync>;
+
+/// An error resolving a host name.
+///
+/// Resolution errors are wrapped, so they can be told apart from other
+/// connection errors.
+#[derive(Debug)]
+pub struct ResolveError(io::Error);
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot resolve host name: {}", self.0)
+    }
+}
+
+impl Error for ResolveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Resolves host names of ingest endpoints.
+///
+/// Static overrides take precedence over the custom resolver, which takes
+/// precedence over the system resolver.
+#[derive(Clone)]
+pub struct Resolver {
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    custom: Option<CustomResolver>,
+    system: GaiResolver,
+}
+
+impl Resolver {
+    /// Creates a resolver from static overrides and a custom resolver.
+    pub fn new(overrides: HashMap<String, Vec<IpAddr>>, custom: Option<CustomResolver>) -> Self {
+        Resolver {
+            overrides: Arc::new(overrides),
+            custom,
+            system: GaiResolver::new(),
+        }
+    }
+
+    // Returns the addresses of a host, if the system resolver is overridden
+    // for it.
+    fn lookup(&self, host: &str) -> Option<Vec<IpAddr>> {
+        if let Some(addrs) = self.overrides.get(host) {
+            return Some(addrs.clone());
+        }
+
+        self.custom.as_ref().and_then(|resolve| resolve(host))
+    }
+}
+
+impl Service<Name> for Resolver {
+    type Response = vec::IntoIter<IpAddr>;
+    type Error = ResolveError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, ResolveError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ResolveError>> {
+        self.system.poll_ready(cx).map_err(ResolveError)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        if let Some(addrs) = self.lookup(name.as_str()) {
+            return Box::pin(future::ready(Ok(addrs.into_iter())));
+        }
+
+        let lookup = self.system.call(name);
+        Box::pin(async move {
+            let addrs = lookup.await.map_err(ResolveError)?;
+            Ok(addrs.collect::<Vec<IpAddr>>().into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn resolve(resolver: &Resolver, host: &str) -> Option<Vec<IpAddr>> {
+        resolver.lookup(Name::from_str(host).unwrap().as_str())
+    }
+
+    #[test]
+    fn lookup() {
+        let local = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+        let mut overrides = HashMap::new();
+        overrides.insert("static.example.com".to_string(), vec![local]);
+
+        let custom: CustomResolver = Arc::new(move |host| match host {
+            "static.example.com" | "custom.example.com" => Some(vec![other]),
+            _ => None,
+        });
+
+        let resolver = Resolver::new(overrides, Some(custom));
+
+        assert_eq!(resolve(&resolver, "static.example.com"), Some(vec![local]));
+        assert_eq!(resolve(&resolver, "custom.example.com"), Some(vec![other]));
+        assert_eq!(resolve(&resolver, "example.com"), None);
+    }
+}
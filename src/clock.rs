@@ -0,0 +1,238 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time.
+///
+/// Components reading the current time, like samplers and clients, take a
+/// `Clock`. By default, the `SystemClock` is used; tests can use a
+/// `ManualClock` instead, to get deterministic timestamps and to simulate the
+/// passing of time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current wall clock time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns the current monotonic time, used to measure durations.
+    fn instant(&self) -> Instant;
+
+    /// Returns the current wall clock time in milliseconds since the epoch.
+    ///
+    /// This is the unit of span timestamps.
+    fn timestamp(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
+
+    fn timestamp(&self) -> u64 {
+        (**self).timestamp()
+    }
+}
+
+/// A clock reading the time of the system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only advances when told to.
+///
+/// ```
+/// # use newrelic_telemetry::clock::{Clock, ManualClock};
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+/// assert_eq!(clock.timestamp(), 1_600_000_000_000);
+///
+/// clock.advance(Duration::from_millis(500));
+/// assert_eq!(clock.timestamp(), 1_600_000_000_500);
+/// ```
+#[derive(Debug)]
+pub struct ManualClock {
+    time: SystemTime,
+    instant: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the given wall clock time.
+    pub fn new(time: SystemTime) -> Self {
+        ManualClock {
+            time,
+            instant: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Advances the clock by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        if let Ok(mut elapsed) = self.elapsed.lock() {
+            *elapsed += duration;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.elapsed.lock() {
+            Ok(elapsed) => *elapsed,
+            Err(_) => Duration::from_secs(0),
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.time + self.elapsed()
+    }
+
+    fn instant(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+}
+
+/// A clock shifting the wall clock time of another clock by an offset.
+///
+/// This corrects the skew of a local clock, for example based on the time
+/// reported by a server. Only the wall clock time is shifted, the monotonic
+/// time is left unchanged.
+///
+/// ```
+/// # use newrelic_telemetry::clock::{Clock, ManualClock, OffsetClock};
+/// # use std::time::UNIX_EPOCH;
+/// let clock = OffsetClock::new(ManualClock::new(UNIX_EPOCH));
+/// clock.set_offset_ms(1500);
+///
+/// assert_eq!(clock.offset_ms(), 1500);
+/// assert_eq!(clock.timestamp(), 1500);
+/// ```
+#[derive(Debug)]
+pub struct OffsetClock {
+    clock: Arc<dyn Clock>,
+    offset_ms: AtomicI64,
+}
+
+impl OffsetClock {
+    /// Creates a clock shifting the given clock, initially without offset.
+    pub fn new<C: Clock + 'static>(clock: C) -> Self {
+        OffsetClock {
+            clock: Arc::new(clock),
+            offset_ms: AtomicI64::new(0),
+        }
+    }
+
+    /// Returns the offset in milliseconds.
+    pub fn offset_ms(&self) -> i64 {
+        self.offset_ms.load(Ordering::SeqCst)
+    }
+
+    /// Sets the offset in milliseconds, which may be negative.
+    pub fn set_offset_ms(&self, offset: i64) {
+        self.offset_ms.store(offset, Ordering::SeqCst);
+    }
+
+    /// Returns the offset in milliseconds of the given reference time from
+    /// the time of the underlying clock.
+    ///
+    /// This is the offset making the clock match the reference time.
+    pub fn offset_to(&self, reference: SystemTime) -> i64 {
+        let now = self.clock.now();
+
+        match reference.duration_since(now) {
+            Ok(ahead) => ahead.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        }
+    }
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> SystemTime {
+        let offset = self.offset_ms();
+        let shift = Duration::from_millis(offset.unsigned_abs());
+
+        if offset >= 0 {
+            self.clock.now() + shift
+        } else {
+            self.clock.now() - shift
+        }
+    }
+
+    fn instant(&self) -> Instant {
+        self.clock.instant()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_clock() {
+        let clock = SystemClock;
+
+        let before = SystemTime::now();
+        let now = clock.now();
+        assert!(now >= before);
+        assert!(clock.timestamp() >= 1_600_000_000_000);
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let start = clock.instant();
+
+        assert_eq!(clock.now(), UNIX_EPOCH);
+        assert_eq!(clock.timestamp(), 0);
+
+        clock.advance(Duration::from_secs(2));
+
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(2));
+        assert_eq!(clock.instant() - start, Duration::from_secs(2));
+
+        // Shared clocks advance together.
+        let shared: Arc<dyn Clock> = clock.clone();
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(shared.timestamp(), 2005);
+    }
+
+    #[test]
+    fn offset_clock() {
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        let clock = OffsetClock::new(ManualClock::new(start));
+        let instant = clock.instant();
+
+        assert_eq!(clock.offset_to(start + Duration::from_millis(1500)), 1500);
+        assert_eq!(clock.offset_to(start - Duration::from_millis(1500)), -1500);
+
+        clock.set_offset_ms(1500);
+        assert_eq!(clock.timestamp(), 1_001_500);
+        assert_eq!(clock.instant(), instant);
+
+        // The offset is relative to the unshifted clock.
+        assert_eq!(clock.offset_to(start), 0);
+
+        clock.set_offset_ms(-1500);
+        assert_eq!(clock.timestamp(), 998_500);
+    }
+}
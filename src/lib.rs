@@ -4,15 +4,69 @@
 ///
 pub mod attribute;
 
+pub mod build_info;
+
+mod sendable;
+pub use sendable::{Priority, Sendable};
+
+#[cfg(feature = "otlp")]
+mod otlp;
 pub mod span;
-pub use span::{Span, SpanBatch};
+pub use span::{RecordError, Span, SpanBatch, SpanBuilder, SpanError};
+
+/// Metadata about the environment an application runs in.
+///
+/// Metadata is exposed as attribute maps, which can be attached to batches as
+/// common attributes.
+#[cfg(any(
+    feature = "host-metadata",
+    feature = "container-metadata",
+    feature = "cloud-metadata"
+))]
+pub mod metadata;
+
+/// A JSON Lines representation of batches for offline spooling.
+///
+/// Batches exported to a writer can be stored, for example in air-gapped
+/// environments, and imported later to be sent via a client. Each line holds
+/// one batch as a JSON object:
+///
+/// ```text
+/// {"version":1,"type":"spans","uuid":"...","batch":{"spans":[...],"common":{...}}}
+/// ```
+///
+/// The `batch` object has the same format as the payloads sent to the Trace
+/// API. Lines of unknown types or versions are rejected on import.
+pub mod spool;
+
+/// Buffering of spans into batches of bounded size.
+pub mod buffer;
+pub use buffer::SpanBuffer;
+
+/// Sources of the current time.
+pub mod clock;
+pub use clock::Clock;
+
+pub mod sampler;
+pub use sampler::{AdaptiveSampler, ProbabilitySampler, Sampler, TraceIdRatioSampler};
 
 #[cfg(feature = "client")]
 mod client;
 #[cfg(feature = "client")]
-pub use client::{Client, ClientBuilder};
+pub use client::{
+    Client, ClientBuilder, ConfigError, ConnectivityError, KeyProvider, MultiClient, ResponseError,
+    SendResult, SendTiming, ShutdownReport, TelemetryClient, TransportFailure,
+};
+
+#[cfg(feature = "test-support")]
+pub mod testing;
 
 #[cfg(feature = "blocking")]
 pub mod blocking {
     pub use super::client::blocking::Client;
 }
+
+#[cfg(feature = "infinite-tracing")]
+pub mod infinite_tracing {
+    pub use super::client::infinite_tracing::Client;
+}
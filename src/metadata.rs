@@ -0,0 +1,18 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+#[cfg(feature = "host-metadata")]
+pub mod host;
+#[cfg(feature = "host-metadata")]
+pub use host::HostMetadata;
+
+#[cfg(feature = "container-metadata")]
+pub mod container;
+#[cfg(feature = "container-metadata")]
+pub use container::ContainerMetadata;
+
+#[cfg(feature = "cloud-metadata")]
+pub mod cloud;
+#[cfg(feature = "cloud-metadata")]
+pub use cloud::CloudMetadata;
@@ -0,0 +1,373 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use anyhow::{anyhow, Result};
+use hyper::client::HttpConnector;
+use hyper::{Body, Method, Request};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// The link-local address of the instance metadata services of AWS, GCP and
+// Azure.
+const METADATA_HOST: &str = "169.254.169.254";
+
+// The time a metadata service has to respond.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+type HttpClient = hyper::Client<HttpConnector>;
+
+/// Metadata about the cloud instance an application runs on.
+///
+/// Metadata is detected by querying the instance metadata services of AWS,
+/// GCP and Azure, like New Relic agents do.
+///
+/// ```no_run
+/// # use newrelic_telemetry::metadata::CloudMetadata;
+/// # use newrelic_telemetry::SpanBatch;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut batch = SpanBatch::new();
+///
+/// if let Some(metadata) = CloudMetadata::detect().await {
+///     for (key, value) in metadata.attributes() {
+///         batch.set_attribute(&key, value);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CloudMetadata {
+    /// The cloud provider, one of `aws`, `gcp` or `azure`.
+    pub provider: String,
+
+    /// The region of the instance.
+    pub region: Option<String>,
+
+    /// The availability zone of the instance.
+    pub availability_zone: Option<String>,
+
+    /// The cloud account id, project id or subscription id.
+    pub account_id: Option<String>,
+
+    /// The id of the instance.
+    pub instance_id: Option<String>,
+
+    /// The type or size of the instance.
+    pub instance_type: Option<String>,
+}
+
+impl CloudMetadata {
+    /// Detects the cloud provider and gathers metadata about the instance.
+    ///
+    /// All providers are queried concurrently. Returns `None` if no instance
+    /// metadata service responds within one second.
+    pub async fn detect() -> Option<Self> {
+        Self::detect_at(METADATA_HOST).await
+    }
+
+    // Detects the cloud provider by querying metadata services at the given
+    // host.
+    async fn detect_at(host: &str) -> Option<Self> {
+        let client = HttpClient::new();
+
+        let (aws, gcp, azure) = futures::join!(
+            with_timeout(aws(&client, host)),
+            with_timeout(gcp(&client, host)),
+            with_timeout(azure(&client, host)),
+        );
+
+        aws.or(gcp).or(azure).ok()
+    }
+
+    /// Returns the metadata as attribute map.
+    ///
+    /// The attributes reported are `cloud.provider`, `cloud.region`,
+    /// `cloud.availability_zone`, `cloud.account.id`, `instance.id` and
+    /// `instance.type`. Values that are unknown are omitted.
+    pub fn attributes(&self) -> HashMap<String, Value> {
+        let mut attributes = HashMap::new();
+        attributes.insert("cloud.provider".to_string(), self.provider.as_str().into());
+
+        let values = vec![
+            ("cloud.region", &self.region),
+            ("cloud.availability_zone", &self.availability_zone),
+            ("cloud.account.id", &self.account_id),
+            ("instance.id", &self.instance_id),
+            ("instance.type", &self.instance_type),
+        ];
+
+        for (key, value) in values {
+            if let Some(value) = value {
+                attributes.insert(key.to_string(), value.as_str().into());
+            }
+        }
+
+        attributes
+    }
+}
+
+async fn with_timeout<F>(future: F) -> Result<CloudMetadata>
+where
+    F: std::future::Future<Output = Result<CloudMetadata>>,
+{
+    tokio::time::timeout(METADATA_TIMEOUT, future).await?
+}
+
+// Sends a request to a metadata service and returns the response body.
+async fn fetch(client: &HttpClient, request: Request<Body>) -> Result<String> {
+    let response = client.request(request).await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("metadata service responded {}", response.status()));
+    }
+
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(String::from_utf8(body.to_vec())?)
+}
+
+// Returns a string field of a JSON document.
+fn field(document: &serde_json::Value, key: &str) -> Option<String> {
+    document[key].as_str().map(|s| s.to_string())
+}
+
+// Returns the last segment of a path like `projects/1/zones/us-central1-a`.
+fn last_segment(path: Option<String>) -> Option<String> {
+    path.and_then(|p| p.rsplit('/').next().map(|s| s.to_string()))
+}
+
+// Queries the AWS instance metadata service, using an IMDSv2 session token.
+async fn aws(client: &HttpClient, host: &str) -> Result<CloudMetadata> {
+    let token = fetch(
+        client,
+        Request::builder()
+            .method(Method::PUT)
+            .uri(format!("http://{}/latest/api/token", host))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+            .body(Body::empty())?,
+    )
+    .await?;
+
+    let document = fetch(
+        client,
+        Request::builder()
+            .uri(format!(
+                "http://{}/latest/dynamic/instance-identity/document",
+                host
+            ))
+            .header("X-aws-ec2-metadata-token", token.trim())
+            .body(Body::empty())?,
+    )
+    .await?;
+
+    parse_aws(&document)
+}
+
+fn parse_aws(document: &str) -> Result<CloudMetadata> {
+    let document: serde_json::Value = serde_json::from_str(document)?;
+
+    Ok(CloudMetadata {
+        provider: "aws".to_string(),
+        region: field(&document, "region"),
+        availability_zone: field(&document, "availabilityZone"),
+        account_id: field(&document, "accountId"),
+        instance_id: field(&document, "instanceId"),
+        instance_type: field(&document, "instanceType"),
+    })
+}
+
+// Queries the GCP instance metadata service.
+async fn gcp(client: &HttpClient, host: &str) -> Result<CloudMetadata> {
+    let document = fetch(
+        client,
+        Request::builder()
+            .uri(format!(
+                "http://{}/computeMetadata/v1/?recursive=true",
+                host
+            ))
+            .header("Metadata-Flavor", "Google")
+            .body(Body::empty())?,
+    )
+    .await?;
+
+    parse_gcp(&document)
+}
+
+fn parse_gcp(document: &str) -> Result<CloudMetadata> {
+    let document: serde_json::Value = serde_json::from_str(document)?;
+    let instance = &document["instance"];
+
+    if !instance.is_object() {
+        return Err(anyhow!("missing instance metadata"));
+    }
+
+    let zone = last_segment(field(instance, "zone"));
+    let region = zone
+        .as_ref()
+        .and_then(|z| z.rfind('-').map(|i| z[..i].to_string()));
+
+    Ok(CloudMetadata {
+        provider: "gcp".to_string(),
+        region,
+        availability_zone: zone,
+        account_id: field(&document["project"], "projectId"),
+        instance_id: instance["id"]
+            .as_u64()
+            .map(|id| id.to_string())
+            .or_else(|| field(instance, "id")),
+        instance_type: last_segment(field(instance, "machineType")),
+    })
+}
+
+// Queries the Azure instance metadata service.
+async fn azure(client: &HttpClient, host: &str) -> Result<CloudMetadata> {
+    let document = fetch(
+        client,
+        Request::builder()
+            .uri(format!(
+                "http://{}/metadata/instance/compute?api-version=2017-08-01",
+                host
+            ))
+            .header("Metadata", "true")
+            .body(Body::empty())?,
+    )
+    .await?;
+
+    parse_azure(&document)
+}
+
+fn parse_azure(document: &str) -> Result<CloudMetadata> {
+    let document: serde_json::Value = serde_json::from_str(document)?;
+
+    if !document.is_object() {
+        return Err(anyhow!("invalid compute metadata"));
+    }
+
+    Ok(CloudMetadata {
+        provider: "azure".to_string(),
+        region: field(&document, "location"),
+        availability_zone: field(&document, "zone").filter(|z| !z.is_empty()),
+        account_id: field(&document, "subscriptionId"),
+        instance_id: field(&document, "vmId"),
+        instance_type: field(&document, "vmSize"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_aws, parse_azure, parse_gcp, CloudMetadata};
+    use crate::attribute::Value;
+    use anyhow::Result;
+
+    #[test]
+    fn aws() -> Result<()> {
+        let metadata = parse_aws(
+            r#"{
+                "accountId": "123456789012",
+                "availabilityZone": "us-east-1a",
+                "instanceId": "i-1234567890abcdef0",
+                "instanceType": "t2.micro",
+                "region": "us-east-1"
+            }"#,
+        )?;
+
+        assert_eq!(metadata.provider, "aws");
+        assert_eq!(metadata.region, Some("us-east-1".to_string()));
+        assert_eq!(metadata.availability_zone, Some("us-east-1a".to_string()));
+        assert_eq!(metadata.account_id, Some("123456789012".to_string()));
+        assert_eq!(
+            metadata.instance_id,
+            Some("i-1234567890abcdef0".to_string())
+        );
+        assert_eq!(metadata.instance_type, Some("t2.micro".to_string()));
+
+        assert!(parse_aws("not json").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn gcp() -> Result<()> {
+        let metadata = parse_gcp(
+            r#"{
+                "instance": {
+                    "id": 4520031799277581759,
+                    "machineType": "projects/260890654058/machineTypes/n1-standard-1",
+                    "zone": "projects/260890654058/zones/us-central1-a"
+                },
+                "project": {
+                    "projectId": "my-project"
+                }
+            }"#,
+        )?;
+
+        assert_eq!(metadata.provider, "gcp");
+        assert_eq!(metadata.region, Some("us-central1".to_string()));
+        assert_eq!(
+            metadata.availability_zone,
+            Some("us-central1-a".to_string())
+        );
+        assert_eq!(metadata.account_id, Some("my-project".to_string()));
+        assert_eq!(
+            metadata.instance_id,
+            Some("4520031799277581759".to_string())
+        );
+        assert_eq!(metadata.instance_type, Some("n1-standard-1".to_string()));
+
+        assert!(parse_gcp("{}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn azure() -> Result<()> {
+        let metadata = parse_azure(
+            r#"{
+                "location": "westeurope",
+                "name": "vm",
+                "subscriptionId": "8d10da13-8125-4ba9-a717-bf7490507b3d",
+                "vmId": "13f56399-bd52-4150-9748-7190aae1ff21",
+                "vmSize": "Standard_D2s_v3",
+                "zone": ""
+            }"#,
+        )?;
+
+        assert_eq!(metadata.provider, "azure");
+        assert_eq!(metadata.region, Some("westeurope".to_string()));
+        assert_eq!(metadata.availability_zone, None);
+        assert_eq!(
+            metadata.instance_id,
+            Some("13f56399-bd52-4150-9748-7190aae1ff21".to_string())
+        );
+        assert_eq!(metadata.instance_type, Some("Standard_D2s_v3".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn attributes() {
+        let metadata = CloudMetadata {
+            provider: "aws".to_string(),
+            region: Some("us-east-1".to_string()),
+            ..Default::default()
+        };
+
+        let attributes = metadata.attributes();
+
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["cloud.provider"], Value::from("aws"));
+        assert_eq!(attributes["cloud.region"], Value::from("us-east-1"));
+    }
+
+    #[tokio::test]
+    async fn detect_unavailable() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let host = format!("127.0.0.1:{}", listener.local_addr()?.port());
+        drop(listener);
+
+        assert_eq!(CloudMetadata::detect_at(&host).await, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+// The namespace file mounted into pods with a service account.
+const NAMESPACE_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Metadata about the container and Kubernetes pod an application runs in.
+///
+/// Kubernetes metadata is read from the environment variables New Relic
+/// agents use, which are typically populated via the downward API:
+///  * `NEW_RELIC_METADATA_KUBERNETES_CLUSTER_NAME`
+///  * `NEW_RELIC_METADATA_KUBERNETES_NODE_NAME`
+///  * `NEW_RELIC_METADATA_KUBERNETES_NAMESPACE_NAME`
+///  * `NEW_RELIC_METADATA_KUBERNETES_POD_NAME`
+///  * `NEW_RELIC_METADATA_KUBERNETES_CONTAINER_NAME`
+///  * `NEW_RELIC_METADATA_KUBERNETES_CONTAINER_IMAGE_NAME`
+///
+/// Within a pod, the namespace and pod name fall back to the service account
+/// namespace and the host name. The container id is read from the cgroup and
+/// mount information of the current process.
+///
+/// ```
+/// # use newrelic_telemetry::metadata::ContainerMetadata;
+/// # use newrelic_telemetry::SpanBatch;
+/// let metadata = ContainerMetadata::collect();
+///
+/// let mut batch = SpanBatch::new();
+/// for (key, value) in metadata.attributes() {
+///     batch.set_attribute(&key, value);
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContainerMetadata {
+    /// The name of the Kubernetes cluster.
+    pub cluster_name: Option<String>,
+
+    /// The name of the Kubernetes node.
+    pub node_name: Option<String>,
+
+    /// The Kubernetes namespace of the pod.
+    pub namespace_name: Option<String>,
+
+    /// The name of the Kubernetes pod.
+    pub pod_name: Option<String>,
+
+    /// The name of the container.
+    pub container_name: Option<String>,
+
+    /// The name of the container image.
+    pub image_name: Option<String>,
+
+    /// The id of the container.
+    pub container_id: Option<String>,
+}
+
+impl ContainerMetadata {
+    /// Gathers metadata about the container and pod.
+    pub fn collect() -> Self {
+        let mut metadata = Self::from_env(|name| env::var(name).ok());
+
+        if env::var("KUBERNETES_SERVICE_HOST").is_ok() {
+            if metadata.namespace_name.is_none() {
+                metadata.namespace_name = fs::read_to_string(NAMESPACE_PATH)
+                    .ok()
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty());
+            }
+
+            if metadata.pod_name.is_none() {
+                metadata.pod_name = env::var("HOSTNAME").ok().filter(|s| !s.is_empty());
+            }
+        }
+
+        metadata.container_id = fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|s| find_container_id(&s))
+            .or_else(|| {
+                fs::read_to_string("/proc/self/mountinfo")
+                    .ok()
+                    .and_then(|s| find_container_id(&s))
+            });
+
+        metadata
+    }
+
+    // Reads Kubernetes metadata via the given environment lookup.
+    fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+        let get = |name: &str| {
+            var(&format!("NEW_RELIC_METADATA_KUBERNETES_{}", name)).filter(|s| !s.is_empty())
+        };
+
+        ContainerMetadata {
+            cluster_name: get("CLUSTER_NAME"),
+            node_name: get("NODE_NAME"),
+            namespace_name: get("NAMESPACE_NAME"),
+            pod_name: get("POD_NAME"),
+            container_name: get("CONTAINER_NAME"),
+            image_name: get("CONTAINER_IMAGE_NAME"),
+            container_id: None,
+        }
+    }
+
+    /// Returns the metadata as attribute map.
+    ///
+    /// Keys follow the OpenTelemetry semantic conventions (`k8s.cluster.name`,
+    /// `k8s.node.name`, `k8s.namespace.name`, `k8s.pod.name`,
+    /// `k8s.container.name`, `container.image.name` and `container.id`).
+    /// Values that are unknown are omitted.
+    pub fn attributes(&self) -> HashMap<String, Value> {
+        let values = vec![
+            ("k8s.cluster.name", &self.cluster_name),
+            ("k8s.node.name", &self.node_name),
+            ("k8s.namespace.name", &self.namespace_name),
+            ("k8s.pod.name", &self.pod_name),
+            ("k8s.container.name", &self.container_name),
+            ("container.image.name", &self.image_name),
+            ("container.id", &self.container_id),
+        ];
+
+        values
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|v| (key.to_string(), v.as_str().into())))
+            .collect()
+    }
+}
+
+// Finds a container id in the content of `/proc/self/cgroup` or
+// `/proc/self/mountinfo`.
+//
+// Container runtimes use 64 character hex ids, which appear as path segments,
+// optionally with a prefix like `docker-` or `cri-containerd-` and a `.scope`
+// suffix.
+fn find_container_id(content: &str) -> Option<String> {
+    content
+        .split(|c: char| c == '/' || c == '-' || c == '.' || c == ':' || c.is_whitespace())
+        .find(|token| token.len() == 64 && token.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|token| token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_container_id, ContainerMetadata};
+    use crate::attribute::Value;
+    use std::collections::HashMap;
+
+    const ID: &str = "3c1f8f8c0a9e4f6c2f77e2b1f3a9d8e4c5b6a7980f1e2d3c4b5a69788796a5b4";
+
+    #[test]
+    fn container_id() {
+        let cgroup_v1 = format!("12:memory:/docker/{}\n11:cpu:/docker/{}\n", ID, ID);
+        assert_eq!(find_container_id(&cgroup_v1), Some(ID.to_string()));
+
+        let systemd = format!(
+            "0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-{}.scope\n",
+            ID
+        );
+        assert_eq!(find_container_id(&systemd), Some(ID.to_string()));
+
+        let mountinfo = format!(
+            "1 2 8:1 /var/lib/docker/containers/{}/hostname /etc/hostname rw\n",
+            ID
+        );
+        assert_eq!(find_container_id(&mountinfo), Some(ID.to_string()));
+
+        assert_eq!(find_container_id("0::/\n"), None);
+    }
+
+    #[test]
+    fn from_env() {
+        let mut env = HashMap::new();
+        env.insert("NEW_RELIC_METADATA_KUBERNETES_CLUSTER_NAME", "cluster");
+        env.insert("NEW_RELIC_METADATA_KUBERNETES_POD_NAME", "pod");
+        env.insert("NEW_RELIC_METADATA_KUBERNETES_NODE_NAME", "");
+
+        let metadata = ContainerMetadata::from_env(|name| env.get(name).map(|s| s.to_string()));
+
+        assert_eq!(metadata.cluster_name, Some("cluster".to_string()));
+        assert_eq!(metadata.pod_name, Some("pod".to_string()));
+        assert_eq!(metadata.node_name, None);
+        assert_eq!(metadata.namespace_name, None);
+    }
+
+    #[test]
+    fn attributes() {
+        let metadata = ContainerMetadata {
+            namespace_name: Some("default".to_string()),
+            container_id: Some(ID.to_string()),
+            ..Default::default()
+        };
+
+        let attributes = metadata.attributes();
+
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["k8s.namespace.name"], Value::from("default"));
+        assert_eq!(attributes["container.id"], Value::from(ID));
+    }
+}
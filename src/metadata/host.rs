@@ -0,0 +1,194 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::thread;
+
+/// Metadata about the host and its operating system.
+///
+/// Metadata is gathered once via `collect`, so it can be attached to any
+/// number of batches without touching the file system again. Values that
+/// cannot be determined on the current platform are `None` and omitted from
+/// the attribute map.
+///
+/// ```
+/// # use newrelic_telemetry::metadata::HostMetadata;
+/// # use newrelic_telemetry::SpanBatch;
+/// let metadata = HostMetadata::collect();
+///
+/// let mut batch = SpanBatch::new();
+/// for (key, value) in metadata.attributes() {
+///     batch.set_attribute(&key, value);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostMetadata {
+    /// The operating system type, e.g. `linux`.
+    pub os_type: String,
+
+    /// The version of the operating system kernel.
+    pub os_version: Option<String>,
+
+    /// A human readable description of the operating system.
+    pub os_description: Option<String>,
+
+    /// The CPU architecture, e.g. `x86_64`.
+    pub arch: String,
+
+    /// The number of CPUs available to the process.
+    pub cpu_count: Option<usize>,
+
+    /// The total memory of the host in bytes.
+    pub memory_total: Option<u64>,
+
+    /// The id of the current boot of the host.
+    pub boot_id: Option<String>,
+}
+
+impl HostMetadata {
+    /// Gathers metadata about the host.
+    pub fn collect() -> Self {
+        HostMetadata {
+            os_type: env::consts::OS.to_string(),
+            os_version: read_trimmed("/proc/sys/kernel/osrelease"),
+            os_description: fs::read_to_string("/etc/os-release")
+                .ok()
+                .and_then(|s| parse_os_release(&s)),
+            arch: env::consts::ARCH.to_string(),
+            cpu_count: thread::available_parallelism().ok().map(|n| n.get()),
+            memory_total: fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|s| parse_meminfo(&s)),
+            boot_id: read_trimmed("/proc/sys/kernel/random/boot_id"),
+        }
+    }
+
+    /// Returns the metadata as attribute map.
+    ///
+    /// Keys follow the OpenTelemetry semantic conventions where available
+    /// (`os.type`, `os.version`, `os.description`, `host.arch`), and use the
+    /// `host.` prefix otherwise (`host.cpu.count`, `host.memory.total`,
+    /// `host.boot.id`).
+    pub fn attributes(&self) -> HashMap<String, Value> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("os.type".to_string(), self.os_type.as_str().into());
+        attributes.insert("host.arch".to_string(), self.arch.as_str().into());
+
+        if let Some(version) = &self.os_version {
+            attributes.insert("os.version".to_string(), version.as_str().into());
+        }
+
+        if let Some(description) = &self.os_description {
+            attributes.insert("os.description".to_string(), description.as_str().into());
+        }
+
+        if let Some(count) = self.cpu_count {
+            attributes.insert("host.cpu.count".to_string(), (count as u64).into());
+        }
+
+        if let Some(memory) = self.memory_total {
+            attributes.insert("host.memory.total".to_string(), memory.into());
+        }
+
+        if let Some(boot_id) = &self.boot_id {
+            attributes.insert("host.boot.id".to_string(), boot_id.as_str().into());
+        }
+
+        attributes
+    }
+}
+
+// Reads a file and returns its trimmed content, unless it is empty.
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// Extracts the pretty name of the operating system from the content of
+// `/etc/os-release`.
+fn parse_os_release(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim().trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+}
+
+// Extracts the total memory in bytes from the content of `/proc/meminfo`.
+fn parse_meminfo(content: &str) -> Option<u64> {
+    let line = content
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?;
+    let kilobytes = line
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_meminfo, parse_os_release, HostMetadata};
+    use crate::attribute::Value;
+
+    #[test]
+    fn os_release() {
+        let content = "NAME=\"Debian GNU/Linux\"\nPRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\n";
+        assert_eq!(
+            parse_os_release(content),
+            Some("Debian GNU/Linux 12 (bookworm)".to_string())
+        );
+
+        assert_eq!(parse_os_release("NAME=Linux\n"), None);
+        assert_eq!(parse_os_release("PRETTY_NAME=\"\"\n"), None);
+    }
+
+    #[test]
+    fn meminfo() {
+        let content = "MemTotal:        6147400 kB\nMemFree:          326256 kB\n";
+        assert_eq!(parse_meminfo(content), Some(6147400 * 1024));
+
+        assert_eq!(parse_meminfo("MemFree: 1 kB\n"), None);
+        assert_eq!(parse_meminfo("MemTotal: many kB\n"), None);
+    }
+
+    #[test]
+    fn attributes() {
+        let metadata = HostMetadata {
+            os_type: "linux".to_string(),
+            os_version: Some("5.10".to_string()),
+            os_description: None,
+            arch: "x86_64".to_string(),
+            cpu_count: Some(4),
+            memory_total: Some(1024),
+            boot_id: None,
+        };
+
+        let attributes = metadata.attributes();
+
+        assert_eq!(attributes.len(), 5);
+        assert_eq!(attributes["os.type"], Value::from("linux"));
+        assert_eq!(attributes["os.version"], Value::from("5.10"));
+        assert_eq!(attributes["host.arch"], Value::from("x86_64"));
+        assert_eq!(attributes["host.cpu.count"], Value::UInt(4));
+        assert_eq!(attributes["host.memory.total"], Value::UInt(1024));
+    }
+
+    #[test]
+    fn collect() {
+        let metadata = HostMetadata::collect();
+
+        assert_eq!(metadata.os_type, std::env::consts::OS);
+        assert!(metadata.cpu_count.unwrap_or(1) > 0);
+    }
+}
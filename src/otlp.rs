@@ -0,0 +1,378 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use crate::span::{Span, SpanBatch};
+use anyhow::{anyhow, Result};
+use prost::Message;
+use std::convert::TryFrom;
+
+// The name of the instrumentation scope of exported spans.
+const SCOPE_NAME: &str = "newrelic-telemetry";
+
+// The version of the instrumentation scope of exported spans.
+const SCOPE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Nanoseconds per millisecond, as OTLP timestamps are in nanoseconds.
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+// Messages of the OTLP trace export protocol.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportTraceServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_spans: Vec<ResourceSpans>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceSpans {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+
+        #[prost(message, repeated, tag = "2")]
+        pub scope_spans: Vec<ScopeSpans>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScopeSpans {
+        #[prost(message, optional, tag = "1")]
+        pub scope: Option<InstrumentationScope>,
+
+        #[prost(message, repeated, tag = "2")]
+        pub spans: Vec<Span>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InstrumentationScope {
+        #[prost(string, tag = "1")]
+        pub name: String,
+
+        #[prost(string, tag = "2")]
+        pub version: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Span {
+        #[prost(bytes, tag = "1")]
+        pub trace_id: Vec<u8>,
+
+        #[prost(bytes, tag = "2")]
+        pub span_id: Vec<u8>,
+
+        #[prost(bytes, tag = "4")]
+        pub parent_span_id: Vec<u8>,
+
+        #[prost(string, tag = "5")]
+        pub name: String,
+
+        #[prost(fixed64, tag = "7")]
+        pub start_time_unix_nano: u64,
+
+        #[prost(fixed64, tag = "8")]
+        pub end_time_unix_nano: u64,
+
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            String(String),
+
+            #[prost(bool, tag = "2")]
+            Bool(bool),
+
+            #[prost(int64, tag = "3")]
+            Int(i64),
+
+            #[prost(double, tag = "4")]
+            Double(f64),
+        }
+    }
+}
+
+impl From<&Value> for proto::AnyValue {
+    fn from(value: &Value) -> Self {
+        use proto::any_value::Value as V;
+
+        // Integers that don't fit into 64 bit signed integers are sent as
+        // floating point numbers.
+        let value = match value {
+            Value::Int(i) => V::Int(*i),
+            Value::UInt(u) if *u <= i64::MAX as u64 => V::Int(*u as i64),
+            Value::UInt(u) => V::Double(*u as f64),
+            Value::Int128(i) if i64::MIN as i128 <= *i && *i <= i64::MAX as i128 => {
+                V::Int(*i as i64)
+            }
+            Value::Int128(i) => V::Double(*i as f64),
+            Value::UInt128(u) if *u <= i64::MAX as u128 => V::Int(*u as i64),
+            Value::UInt128(u) => V::Double(*u as f64),
+            Value::Str(s) => V::String(s.clone()),
+            Value::Float(f) => V::Double(*f),
+            Value::Bool(b) => V::Bool(*b),
+        };
+
+        proto::AnyValue { value: Some(value) }
+    }
+}
+
+fn key_value(key: &str, value: &Value) -> proto::KeyValue {
+    proto::KeyValue {
+        key: key.to_string(),
+        value: Some(value.into()),
+    }
+}
+
+// Decodes a hex encoded id into the given number of bytes.
+//
+// Shorter ids are padded with leading zeros.
+fn decode_id(id: &str, len: usize) -> Result<Vec<u8>> {
+    if id.is_empty() || id.len() > len * 2 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!(
+            "invalid id {}, expected {} hex encoded bytes",
+            id,
+            len
+        ));
+    }
+
+    let padded = format!("{:0>width$}", id, width = len * 2);
+
+    (0..len)
+        .map(|i| Ok(u8::from_str_radix(&padded[i * 2..i * 2 + 2], 16)?))
+        .collect()
+}
+
+// Converts a span into an OTLP span.
+//
+// The `name`, `parent.id` and `duration.ms` attributes are mapped to the
+// respective fields of the OTLP span, `service.name` is a resource attribute.
+// Spans whose start or end time cannot be represented in nanoseconds are
+// rejected.
+fn encode_span(span: &Span) -> Result<proto::Span> {
+    let start = span.timestamp.checked_mul(NANOS_PER_MILLI).ok_or_else(|| {
+        anyhow!(
+            "timestamp {} of span {} out of range",
+            span.timestamp,
+            span.id
+        )
+    })?;
+
+    let duration = match span.attributes.get("duration.ms") {
+        Some(Value::UInt(d)) => d.checked_mul(NANOS_PER_MILLI),
+        Some(Value::UInt128(d)) => u64::try_from(*d)
+            .ok()
+            .and_then(|d| d.checked_mul(NANOS_PER_MILLI)),
+        Some(Value::Int(d)) if *d > 0 => (*d as u64).checked_mul(NANOS_PER_MILLI),
+        Some(Value::Float(d)) if *d > 0.0 => Some((*d * NANOS_PER_MILLI as f64) as u64),
+        _ => Some(0),
+    };
+    let end = duration
+        .and_then(|duration| start.checked_add(duration))
+        .ok_or_else(|| anyhow!("duration of span {} out of range", span.id))?;
+
+    let mut message = proto::Span {
+        trace_id: decode_id(&span.trace_id, 16)?,
+        span_id: decode_id(&span.id, 8)?,
+        start_time_unix_nano: start,
+        end_time_unix_nano: end,
+        ..Default::default()
+    };
+
+    for (key, value) in span.attributes.iter() {
+        match (key.as_ref(), value) {
+            ("name", Value::Str(name)) => message.name = name.clone(),
+            ("parent.id", Value::Str(parent_id)) => {
+                message.parent_span_id = decode_id(parent_id, 8)?
+            }
+            ("duration.ms", _) | ("service.name", _) => (),
+            _ => message.attributes.push(key_value(key, value)),
+        }
+    }
+
+    Ok(message)
+}
+
+// Encodes a span batch as OTLP export request in protobuf format.
+//
+// Common attributes of the batch become resource attributes, ordered by key.
+// Spans are grouped into one resource per `service.name`, which can be set on
+// spans or as common attribute.
+pub(crate) fn encode_spans(batch: &SpanBatch) -> Result<Vec<u8>> {
+    let mut services: Vec<(Option<&Value>, Vec<proto::Span>)> = vec![];
+
+    for span in batch.spans.iter() {
+        let service = span
+            .attributes
+            .get("service.name")
+            .or_else(|| batch.attributes.get("service.name"));
+        let message = encode_span(span)?;
+
+        match services.iter_mut().find(|(s, _)| *s == service) {
+            Some((_, spans)) => spans.push(message),
+            None => services.push((service, vec![message])),
+        }
+    }
+
+    let resource_spans = services
+        .into_iter()
+        .map(|(service, spans)| {
+            let mut attributes: Vec<proto::KeyValue> = batch
+                .attributes
+                .iter()
+                .filter(|(key, _)| key.as_ref() != "service.name")
+                .map(|(key, value)| key_value(key, value))
+                .collect();
+
+            if let Some(service) = service {
+                attributes.push(key_value("service.name", service));
+            }
+
+            proto::ResourceSpans {
+                resource: Some(proto::Resource { attributes }),
+                scope_spans: vec![proto::ScopeSpans {
+                    scope: Some(proto::InstrumentationScope {
+                        name: SCOPE_NAME.to_string(),
+                        version: SCOPE_VERSION.to_string(),
+                    }),
+                    spans,
+                }],
+            }
+        })
+        .collect();
+
+    let request = proto::ExportTraceServiceRequest { resource_spans };
+
+    let mut buf = Vec::with_capacity(request.encoded_len());
+    request.encode(&mut buf)?;
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::any_value::Value as V;
+    use super::{decode_id, encode_spans, proto};
+    use crate::span::{Span, SpanBatch};
+    use anyhow::Result;
+    use prost::Message;
+    use std::time::Duration;
+
+    fn string(s: &str) -> Option<proto::AnyValue> {
+        Some(proto::AnyValue {
+            value: Some(V::String(s.to_string())),
+        })
+    }
+
+    #[test]
+    fn ids() -> Result<()> {
+        assert_eq!(decode_id("0102", 2)?, vec![1, 2]);
+        assert_eq!(decode_id("a", 2)?, vec![0, 10]);
+        assert_eq!(decode_id("FFff", 2)?, vec![255, 255]);
+
+        assert!(decode_id("", 2).is_err());
+        assert!(decode_id("010203", 2).is_err());
+        assert!(decode_id("xy", 2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn spans() -> Result<()> {
+        let batch: SpanBatch = vec![
+            Span::new("1", "2", 1000)
+                .name("span")
+                .parent_id("3")
+                .duration(Duration::from_millis(10))
+                .attribute("key", "value"),
+            Span::new("4", "2", 2000).service_name("other"),
+        ]
+        .into();
+        let batch = batch
+            .attribute("host", "localhost")
+            .attribute("service.name", "service");
+
+        let request = proto::ExportTraceServiceRequest::decode(&encode_spans(&batch)?[..])?;
+        assert_eq!(request.resource_spans.len(), 2);
+
+        let resource = request.resource_spans[0].resource.as_ref().unwrap();
+        assert_eq!(resource.attributes.len(), 2);
+        assert_eq!(resource.attributes[0].key, "host");
+        assert_eq!(resource.attributes[1].key, "service.name");
+        assert_eq!(resource.attributes[1].value, string("service"));
+
+        let scope_spans = &request.resource_spans[0].scope_spans[0];
+        assert_eq!(
+            scope_spans.scope.as_ref().unwrap().name,
+            "newrelic-telemetry"
+        );
+
+        let span = &scope_spans.spans[0];
+        assert_eq!(span.trace_id, [&[0; 15][..], &[2]].concat());
+        assert_eq!(span.span_id, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(span.parent_span_id, vec![0, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(span.name, "span");
+        assert_eq!(span.start_time_unix_nano, 1_000_000_000);
+        assert_eq!(span.end_time_unix_nano, 1_010_000_000);
+        assert_eq!(span.attributes.len(), 1);
+        assert_eq!(span.attributes[0].key, "key");
+        assert_eq!(span.attributes[0].value, string("value"));
+
+        let resource = request.resource_spans[1].resource.as_ref().unwrap();
+        assert_eq!(resource.attributes[1].value, string("other"));
+
+        let span = &request.resource_spans[1].scope_spans[0].spans[0];
+        assert_eq!(span.span_id, vec![0, 0, 0, 0, 0, 0, 0, 4]);
+        assert_eq!(span.end_time_unix_nano, span.start_time_unix_nano);
+        assert!(span.attributes.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+
+        assert!(encode_spans(&batch).is_err());
+    }
+
+    #[test]
+    fn out_of_range_times() {
+        let span = || Span::new("1", "2", 1000);
+
+        let batch: SpanBatch = vec![Span::new("1", "2", u64::MAX)].into();
+        assert!(encode_spans(&batch).is_err());
+
+        let batch: SpanBatch = vec![span().attribute("duration.ms", u64::MAX)].into();
+        assert!(encode_spans(&batch).is_err());
+
+        let batch: SpanBatch = vec![span().attribute("duration.ms", u128::MAX)].into();
+        assert!(encode_spans(&batch).is_err());
+
+        let batch: SpanBatch = vec![span().attribute("duration.ms", f64::MAX)].into();
+        assert!(encode_spans(&batch).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::clock::{Clock, SystemClock};
+use crate::span::Span;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Decides whether the spans of a trace are recorded.
+///
+/// Samplers base their decision on the trace id, so either all spans of a
+/// trace are kept or all of them are dropped.
+pub trait Sampler: Send + Sync {
+    /// Returns `true` if spans of the trace with the given id should be
+    /// recorded.
+    fn should_sample(&self, trace_id: &str) -> bool;
+
+    /// Returns `true` if the given span should be recorded.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{ProbabilitySampler, Sampler, Span, SpanBatch};
+    /// let sampler = ProbabilitySampler::new(1.0);
+    /// let span = Span::new("e9f54a2c322d7578", "1b1bf29379951c1d", 1000);
+    ///
+    /// let mut batch = SpanBatch::new();
+    /// if sampler.sample(&span) {
+    ///     batch.record(span);
+    /// }
+    /// ```
+    fn sample(&self, span: &Span) -> bool {
+        self.should_sample(&span.trace_id)
+    }
+}
+
+/// A head sampler keeping a fixed fraction of traces.
+///
+/// The decision is derived from a hash of the trace id, so it is consistent
+/// for all spans of a trace within a process.
+///
+/// ```
+/// # use newrelic_telemetry::{ProbabilitySampler, Sampler};
+/// let sampler = ProbabilitySampler::new(0.1);
+///
+/// let keep = sampler.should_sample("1b1bf29379951c1d");
+/// assert_eq!(keep, sampler.should_sample("1b1bf29379951c1d"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbabilitySampler {
+    probability: f64,
+}
+
+impl ProbabilitySampler {
+    /// Creates a sampler keeping the given fraction of traces.
+    ///
+    /// The probability is clamped to the range `[0.0, 1.0]`.
+    pub fn new(probability: f64) -> Self {
+        ProbabilitySampler {
+            probability: if probability.is_nan() {
+                0.0
+            } else {
+                probability.clamp(0.0, 1.0)
+            },
+        }
+    }
+
+    /// Returns the fraction of traces kept by this sampler.
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+}
+
+impl Sampler for ProbabilitySampler {
+    fn should_sample(&self, trace_id: &str) -> bool {
+        sample_hash(hash_trace_id(trace_id), self.probability)
+    }
+}
+
+/// A sampler keeping a fixed ratio of traces, consistently across services.
+///
+/// Unlike `ProbabilitySampler`, the decision only depends on the trace id and
+/// the ratio, using a hash that is stable across processes, platforms and
+/// versions of this crate. Independent services configured with the same
+/// ratio therefore keep or drop the same traces, preserving complete
+/// distributed traces.
+///
+/// For hexadecimal trace ids of at least 16 digits, the lower 64 bits of the
+/// id are compared against the ratio threshold. Other trace ids, including
+/// shorter hexadecimal ids, are hashed with FNV-1a and mixed.
+///
+/// ```
+/// # use newrelic_telemetry::{Sampler, TraceIdRatioSampler};
+/// let sampler = TraceIdRatioSampler::new(0.5);
+///
+/// assert!(sampler.should_sample("1b1bf29379951c1d"));
+/// assert!(!sampler.should_sample("bb1bf29379951c1d"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceIdRatioSampler {
+    ratio: f64,
+}
+
+impl TraceIdRatioSampler {
+    /// Creates a sampler keeping the given ratio of traces.
+    ///
+    /// The ratio is clamped to the range `[0.0, 1.0]`.
+    pub fn new(ratio: f64) -> Self {
+        TraceIdRatioSampler {
+            ratio: ProbabilitySampler::new(ratio).probability(),
+        }
+    }
+
+    /// Returns the ratio of traces kept by this sampler.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+}
+
+impl Sampler for TraceIdRatioSampler {
+    fn should_sample(&self, trace_id: &str) -> bool {
+        sample_hash(stable_trace_id_hash(trace_id), self.ratio)
+    }
+}
+
+/// A sampler targeting a fixed number of sampled traces per period.
+///
+/// The first period samples the first `target` traces. In each following
+/// period, the sampling probability is derived from the number of traces seen
+/// during the previous period, so bursty workloads keep a stable data rate.
+/// Once `target` traces were sampled in a period, the probability decays
+/// further for the remainder of that period.
+///
+/// The sampler counts every decision, so it should be consulted once per
+/// trace, e.g. when the root span is created.
+///
+/// ```
+/// # use newrelic_telemetry::{AdaptiveSampler, Sampler};
+/// # use std::time::Duration;
+/// let sampler = AdaptiveSampler::new(10, Duration::from_secs(60));
+///
+/// assert!(sampler.should_sample("1b1bf29379951c1d"));
+/// ```
+#[derive(Debug)]
+pub struct AdaptiveSampler {
+    target: u64,
+    period: Duration,
+    clock: Arc<dyn Clock>,
+    state: Mutex<AdaptiveState>,
+}
+
+#[derive(Debug)]
+struct AdaptiveState {
+    period_start: Instant,
+    first_period: bool,
+    seen: u64,
+    sampled: u64,
+    probability: f64,
+}
+
+impl AdaptiveSampler {
+    /// Creates a sampler targeting `target` sampled traces per `period`.
+    pub fn new(target: u64, period: Duration) -> Self {
+        Self::with_clock(target, period, SystemClock)
+    }
+
+    /// Creates a sampler reading the current time from the given clock.
+    pub fn with_clock<C: Clock + 'static>(target: u64, period: Duration, clock: C) -> Self {
+        let period_start = clock.instant();
+
+        AdaptiveSampler {
+            target,
+            period,
+            clock: Arc::new(clock),
+            state: Mutex::new(AdaptiveState {
+                period_start,
+                first_period: true,
+                seen: 0,
+                sampled: 0,
+                probability: 1.0,
+            }),
+        }
+    }
+
+    /// Returns the sampling probability of the current period.
+    pub fn probability(&self) -> f64 {
+        match self.state.lock() {
+            Ok(state) => state.probability,
+            Err(_) => 0.0,
+        }
+    }
+
+    // Decides whether the trace with the given id is sampled at the given
+    // point in time.
+    fn decide(&self, trace_id: &str, now: Instant) -> bool {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(_) => return false,
+        };
+
+        if now.duration_since(state.period_start) >= self.period {
+            state.probability = if state.seen == 0 {
+                1.0
+            } else {
+                (self.target as f64 / state.seen as f64).min(1.0)
+            };
+            state.period_start = now;
+            state.first_period = false;
+            state.seen = 0;
+            state.sampled = 0;
+        }
+
+        state.seen += 1;
+
+        let sampled = if state.first_period {
+            state.sampled < self.target
+        } else if state.sampled < self.target {
+            sample_hash(hash_trace_id(trace_id), state.probability)
+        } else {
+            let decay = self.target as f64 / (state.sampled + 1) as f64;
+            sample_hash(hash_trace_id(trace_id), state.probability * decay)
+        };
+
+        if sampled {
+            state.sampled += 1;
+        }
+
+        sampled
+    }
+}
+
+impl Default for AdaptiveSampler {
+    /// Creates a sampler targeting 10 sampled traces per minute.
+    fn default() -> Self {
+        Self::new(10, Duration::from_secs(60))
+    }
+}
+
+impl Sampler for AdaptiveSampler {
+    fn should_sample(&self, trace_id: &str) -> bool {
+        self.decide(trace_id, self.clock.instant())
+    }
+}
+
+// Hashes a trace id into a 64 bit value.
+fn hash_trace_id(trace_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    trace_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Maps a trace id to a 64 bit value which is stable across processes.
+//
+// Hexadecimal trace ids of at least 16 digits are used as they are, as they
+// are expected to be random already. Only the lower 64 bits are considered.
+// Shorter ids would only cover a small part of the value space, so they are
+// hashed like other ids.
+fn stable_trace_id_hash(trace_id: &str) -> u64 {
+    let len = trace_id.len();
+    if len >= 16 && trace_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        if let Some(Ok(value)) = trace_id
+            .get(len - 16..)
+            .map(|low| u64::from_str_radix(low, 16))
+        {
+            return value;
+        }
+    }
+
+    // FNV-1a, followed by the SplitMix64 finalizer to spread similar ids
+    // across the whole value space.
+    let hash = trace_id.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+// Decides whether a hash value falls into the given fraction of the 64 bit
+// value space.
+fn sample_hash(hash: u64, probability: f64) -> bool {
+    if probability >= 1.0 {
+        true
+    } else if probability <= 0.0 {
+        false
+    } else {
+        (hash as f64) < probability * (u64::MAX as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        stable_trace_id_hash, AdaptiveSampler, ProbabilitySampler, Sampler, TraceIdRatioSampler,
+    };
+    use crate::clock::ManualClock;
+    use crate::span::Span;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    fn trace_ids(count: usize) -> Vec<String> {
+        (0..count).map(|n| format!("trace_id{}", n)).collect()
+    }
+
+    #[test]
+    fn probability_clamped() {
+        assert_eq!(ProbabilitySampler::new(-1.0).probability(), 0.0);
+        assert_eq!(ProbabilitySampler::new(2.0).probability(), 1.0);
+        assert_eq!(ProbabilitySampler::new(f64::NAN).probability(), 0.0);
+        assert_eq!(ProbabilitySampler::new(0.25).probability(), 0.25);
+    }
+
+    #[test]
+    fn probability_bounds() {
+        let never = ProbabilitySampler::new(0.0);
+        let always = ProbabilitySampler::new(1.0);
+
+        for id in trace_ids(1000) {
+            assert!(!never.should_sample(&id));
+            assert!(always.should_sample(&id));
+        }
+    }
+
+    #[test]
+    fn probability_consistent() {
+        let sampler = ProbabilitySampler::new(0.5);
+
+        for id in trace_ids(1000) {
+            let first = sampler.should_sample(&id);
+            assert_eq!(first, sampler.should_sample(&id));
+            assert_eq!(first, sampler.sample(&Span::new("id", &id, 1)));
+        }
+    }
+
+    #[test]
+    fn probability_ratio() {
+        let sampler = ProbabilitySampler::new(0.1);
+        let kept = trace_ids(10000)
+            .iter()
+            .filter(|id| sampler.should_sample(id))
+            .count();
+
+        assert!(kept > 800 && kept < 1200, "kept {} of 10000", kept);
+    }
+
+    #[test]
+    fn adaptive_first_period() {
+        let sampler = AdaptiveSampler::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+
+        let kept = trace_ids(100)
+            .iter()
+            .filter(|id| sampler.decide(id, now))
+            .count();
+
+        assert_eq!(kept, 10);
+        assert_eq!(sampler.probability(), 1.0);
+    }
+
+    #[test]
+    fn adaptive_following_period() {
+        let period = Duration::from_secs(60);
+        let sampler = AdaptiveSampler::new(100, period);
+        let start = Instant::now();
+
+        for id in trace_ids(1000) {
+            sampler.decide(&id, start);
+        }
+
+        let next = start + period;
+        let kept = trace_ids(1000)
+            .iter()
+            .filter(|id| sampler.decide(id, next))
+            .count();
+
+        assert_eq!(sampler.probability(), 0.1);
+        assert!(kept > 50 && kept < 150, "kept {} of 1000", kept);
+    }
+
+    #[test]
+    fn adaptive_idle_period() {
+        let period = Duration::from_secs(60);
+        let sampler = AdaptiveSampler::new(10, period);
+        let start = Instant::now();
+
+        for id in trace_ids(100) {
+            sampler.decide(&id, start);
+        }
+
+        sampler.decide("trace", start + period);
+        assert_eq!(sampler.probability(), 0.1);
+
+        // A quiet period raises the probability again.
+        sampler.decide("trace", start + period * 3);
+        assert_eq!(sampler.probability(), 1.0);
+    }
+
+    #[test]
+    fn adaptive_clock() {
+        let period = Duration::from_secs(60);
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let sampler = AdaptiveSampler::with_clock(10, period, clock.clone());
+
+        for id in trace_ids(100) {
+            sampler.should_sample(&id);
+        }
+        assert_eq!(sampler.probability(), 1.0);
+
+        clock.advance(period);
+        sampler.should_sample("trace");
+        assert_eq!(sampler.probability(), 0.1);
+    }
+
+    #[test]
+    fn ratio_stable_hash() {
+        assert_eq!(stable_trace_id_hash("1b1bf29379951c1d"), 0x1b1bf29379951c1d);
+        assert_eq!(
+            stable_trace_id_hash("4bf92f3577b34da6a3ce929d0e0e4736"),
+            0xa3ce929d0e0e4736
+        );
+
+        // Short and non hexadecimal ids are hashed.
+        assert_ne!(stable_trace_id_hash("abc123"), 0xabc123);
+        assert_ne!(stable_trace_id_hash("12345"), 0x12345);
+        assert_eq!(stable_trace_id_hash(""), 0xf52a15e9a9b5e89b);
+        assert_eq!(stable_trace_id_hash("z"), 0x58854b0a643a458a);
+        assert_eq!(stable_trace_id_hash("trace"), stable_trace_id_hash("trace"));
+    }
+
+    #[test]
+    fn ratio_bounds() {
+        let never = TraceIdRatioSampler::new(0.0);
+        let always = TraceIdRatioSampler::new(1.0);
+
+        for id in trace_ids(1000) {
+            assert!(!never.should_sample(&id));
+            assert!(always.should_sample(&id));
+        }
+
+        assert!(!never.should_sample("ffffffffffffffff"));
+        assert!(always.should_sample("ffffffffffffffff"));
+    }
+
+    #[test]
+    fn ratio_threshold() {
+        let sampler = TraceIdRatioSampler::new(0.25);
+
+        assert!(sampler.should_sample("0000000000000000"));
+        assert!(sampler.should_sample("3fff000000000000"));
+        assert!(!sampler.should_sample("4001000000000000"));
+        assert!(!sampler.should_sample("ffffffffffffffff"));
+    }
+
+    #[test]
+    fn ratio_consistent() {
+        // Two independently created samplers agree on every trace.
+        let first = TraceIdRatioSampler::new(0.3);
+        let second = TraceIdRatioSampler::new(0.3);
+
+        let ids = trace_ids(10000);
+        for id in &ids {
+            assert_eq!(first.should_sample(id), second.should_sample(id));
+        }
+
+        let kept = ids.iter().filter(|id| first.should_sample(id)).count();
+        assert!(kept > 2700 && kept < 3300, "kept {} of 10000", kept);
+    }
+
+    #[test]
+    fn ratio_short_hex_ids() {
+        let sampler = TraceIdRatioSampler::new(0.3);
+
+        let kept = (0..10000)
+            .map(|n| format!("{:x}", n))
+            .filter(|id| sampler.should_sample(id))
+            .count();
+        assert!(kept > 2700 && kept < 3300, "kept {} of 10000", kept);
+    }
+}
@@ -0,0 +1,187 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Priority of a batch
+///
+/// When back pressure forces the blocking client to drop batches, batches of
+/// lower priority are dropped first. Batches of the same priority are dropped
+/// in the order they arrived, newest first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Types that can be sent to a New Relic ingest API
+///
+/// New Relic ingest APIs currently accept batches of traces, metrics, events
+/// or logs.
+pub trait Sendable: std::fmt::Display + Send {
+    /// Return the uuid for the `Sendable`
+    ///
+    /// This method returns a version 4 UUID string which enables the ingest
+    /// service to identify duplicate requests. It is sent as the
+    /// `x-request-id` header and stays the same across all retries of an
+    /// unchanged payload; only splitting a `Sendable` assigns new uuids.
+    fn uuid(&self) -> &str;
+
+    /// Return the type of data contained in the `Sendable`
+    ///
+    /// This identifies the kind of a batch in log messages, for example when
+    /// it is dropped.
+    fn data_type(&self) -> &'static str {
+        "unknown"
+    }
+
+    /// Return the number of items contained in the `Sendable`
+    ///
+    /// Items are spans, metrics, events or log messages, depending on the
+    /// data type. This allows skipping empty batches, and reporting how many
+    /// items were lost when a batch is dropped.
+    fn len(&self) -> usize;
+
+    /// Return `true` if the `Sendable` doesn't contain any items
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the priority of the `Sendable`
+    ///
+    /// Lower priority data is dropped first under back pressure.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    /// Return a hash of the payload of the `Sendable`
+    ///
+    /// Unlike the uuid, the hash only depends on the content, so identical
+    /// payloads have the same hash. It is used to skip retransmits of
+    /// identical payloads and to correlate payloads in log messages. `None`
+    /// is returned if no payload can be created.
+    fn content_hash(&self) -> Option<u64> {
+        let payload = self.marshall().ok()?;
+
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    // Create a payload
+    //
+    // This method creates a JSON payload representing the contents of the
+    // `Sendable` object, conforming to the requirements of a related ingest
+    // API (traces, metrics, events or logs).
+    fn marshall(&self) -> Result<String>;
+
+    // Create an OTLP payload
+    //
+    // This method creates a protobuf encoded OTLP export request representing
+    // the contents of the `Sendable` object. Types that cannot be exported via
+    // OTLP return an error.
+    #[cfg(feature = "otlp")]
+    fn marshall_otlp(&self) -> Result<Vec<u8>> {
+        Err(anyhow::anyhow!("{} cannot be exported via OTLP", self))
+    }
+
+    // Split a `Sendable`
+    //
+    // New Relic ingest APIs reject payloads that are too large. In that case,
+    // a 413 response code is sent, the payload must be split and sent again
+    // (see [the specification](https://github.com/newrelic/newrelic-telemetry-sdk-specs/blob/master/communication.md#response-codes)
+    // for further details).
+    //
+    // This method removes half of the content of the `Sendable` object and
+    // puts it into a second `Sendable` object, which is returned. As both
+    // halves carry a different payload than the original, both get a new
+    // uuid.
+    fn split(&mut self) -> Box<dyn Sendable>;
+
+    // Split a `Sendable` into chunks of a maximum size
+    //
+    // Like `split`, this is used when a payload is too large. Instead of
+    // halving the content, it is partitioned into chunks whose estimated
+    // serialized size is below the given number of bytes, which takes fewer
+    // round trips for large payloads or payloads with a few large items. The
+    // `Sendable` keeps the first chunk, the other chunks are returned. All
+    // chunks get a new uuid.
+    //
+    // An empty vector is returned if the content cannot be split any further.
+    // The default implementation halves the content via `split`.
+    fn split_by_size(&mut self, _max_bytes: usize) -> Vec<Box<dyn Sendable>> {
+        vec![self.split()]
+    }
+
+    // Return an estimate of the size of the payload
+    //
+    // The estimate is in the same unit as the maximum size passed to
+    // `split_by_size`. It is used to remember the size of payloads rejected
+    // as too large, so later payloads of that size are split before they are
+    // sent. The default implementation returns `None`, which disables this.
+    fn estimated_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    // Remove the largest attribute of a `Sendable` that cannot be split
+    //
+    // This is used as a last resort when a payload is too large but cannot be
+    // split any further. The name of the removed attribute is returned, or
+    // `None` if there is nothing left to remove. The default implementation
+    // removes nothing.
+    fn trim(&mut self) -> Option<String> {
+        None
+    }
+}
+
+// Serializes a value to a JSON string.
+//
+// With the `simd-json` feature enabled, the serializer of `simd-json` is used
+// instead of `serde_json`, which speeds up marshalling of large batches.
+pub(crate) fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    #[cfg(feature = "simd-json")]
+    let json = simd_json::serde::to_string(value)?;
+
+    #[cfg(not(feature = "simd-json"))]
+    let json = serde_json::to_string(value)?;
+
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_json;
+    use crate::attribute::Value;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn json_values() -> Result<()> {
+        let mut values = BTreeMap::new();
+        values.insert("bool", Value::Bool(true));
+        values.insert("float", Value::Float(1.5));
+        values.insert("int", Value::Int(-5));
+        values.insert(
+            "int128",
+            Value::Int128(-170141183460469231731687303715884105728),
+        );
+        values.insert("str", Value::Str("\"quoted\"".to_string()));
+        values.insert(
+            "uint128",
+            Value::UInt128(340282366920938463463374607431768211455),
+        );
+
+        assert_eq!(
+            to_json(&values)?,
+            r#"{"bool":true,"float":1.5,"int":-5,"int128":-170141183460469231731687303715884105728,"str":"\"quoted\"","uint128":340282366920938463463374607431768211455}"#
+        );
+
+        Ok(())
+    }
+}
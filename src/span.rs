@@ -2,27 +2,116 @@
 /// Copyright 2020 New Relic Corporation. All rights reserved.
 /// SPDX-License-Identifier: Apache-2.0
 ///
-use crate::attribute::Value;
-use crate::client::Sendable;
+use crate::attribute::{IntoAttributes, Key, Value};
+use crate::sendable::{to_json, Priority, Sendable};
 use anyhow::Result;
+#[cfg(feature = "client")]
+use flate2::{write::GzEncoder, Compression};
 use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+
+mod attributes;
+use attributes::Attributes;
 use std::fmt;
+#[cfg(feature = "client")]
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
 
+// The earliest timestamp accepted by checked span constructors, which is
+// 2000-01-01T00:00:00Z in milliseconds.
+const TIMESTAMP_MIN: u64 = 946_684_800_000;
+
+// The latest timestamp accepted by checked span constructors, which is
+// 2100-01-01T00:00:00Z in milliseconds.
+const TIMESTAMP_MAX: u64 = 4_102_444_800_000;
+
+/// Errors of checked span constructors and setters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpanError {
+    /// The span id is empty.
+    EmptyId,
+
+    /// The trace id is empty.
+    EmptyTraceId,
+
+    /// The timestamp is not a plausible number of milliseconds since the
+    /// epoch, e.g. because it is given in seconds or nanoseconds.
+    InvalidTimestamp(u64),
+}
+
+impl fmt::Display for SpanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpanError::EmptyId => write!(f, "empty span id"),
+            SpanError::EmptyTraceId => write!(f, "empty trace id"),
+            SpanError::InvalidTimestamp(timestamp) => write!(
+                f,
+                "invalid timestamp {}, expected milliseconds since the epoch",
+                timestamp
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpanError {}
+
+/// Errors of recording spans into a batch via `SpanBatch::record_checked`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordError {
+    /// The span is invalid.
+    InvalidSpan(SpanError),
+
+    /// The batch already holds the maximum number of spans.
+    TooManySpans(usize),
+
+    /// Recording the span would exceed the maximum estimated payload size of
+    /// the batch, given in bytes.
+    TooLarge(usize),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::InvalidSpan(e) => write!(f, "invalid span: {}", e),
+            RecordError::TooManySpans(max) => {
+                write!(f, "batch holds the maximum of {} spans", max)
+            }
+            RecordError::TooLarge(max) => {
+                write!(f, "batch would exceed the maximum of {} bytes", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<SpanError> for RecordError {
+    fn from(e: SpanError) -> Self {
+        RecordError::InvalidSpan(e)
+    }
+}
+
+// The default maximum payload size of a span batch enforced by
+// `SpanBatch::record_checked`, which is the payload limit of the Trace API.
+const BATCH_BYTES_MAX: usize = 1_000_000;
+
 /// Represents a distributed tracing span.
 #[derive(serde::Serialize, Clone, Debug, PartialEq)]
 pub struct Span {
-    id: String,
+    pub(crate) id: String,
 
     #[serde(rename = "trace.id")]
-    trace_id: String,
+    pub(crate) trace_id: String,
 
-    timestamp: u64,
+    pub(crate) timestamp: u64,
 
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
-    attributes: HashMap<String, Value>,
+    // Attributes are kept ordered by key, so payloads are serialized
+    // deterministically.
+    #[serde(skip_serializing_if = "Attributes::is_empty")]
+    pub(crate) attributes: Attributes,
 }
 
 impl Span {
@@ -31,11 +120,38 @@ impl Span {
         Span {
             id: id.to_string(),
             trace_id: trace_id.to_string(),
-            timestamp: timestamp,
-            attributes: HashMap::new(),
+            timestamp,
+            attributes: Attributes::default(),
         }
     }
 
+    /// Create a new span, validating the identifier, trace id and timestamp.
+    ///
+    /// Unlike `new`, this rejects empty ids and timestamps which are not
+    /// milliseconds since the epoch between the years 2000 and 2100, which
+    /// would be rejected by the Trace API.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{Span, SpanError};
+    /// assert!(Span::try_new("id", "trace_id", 1_600_000_000_000).is_ok());
+    /// assert_eq!(
+    ///     Span::try_new("", "trace_id", 1_600_000_000_000),
+    ///     Err(SpanError::EmptyId)
+    /// );
+    /// ```
+    pub fn try_new(id: &str, trace_id: &str, timestamp: u64) -> Result<Span, SpanError> {
+        Span::new(id, trace_id, timestamp)
+            .try_id(id)?
+            .try_trace_id(trace_id)?
+            .try_timestamp(timestamp)
+    }
+
+    /// Create a builder for a span, which requires the identifier, trace id
+    /// and timestamp to be set before the span can be built.
+    pub fn builder() -> SpanBuilder<Missing, Missing, Missing> {
+        SpanBuilder::new()
+    }
+
     /// Set a unique identifier for this span. This is a required field.
     pub fn id(mut self, id: &str) -> Self {
         self.id = id.to_string();
@@ -46,6 +162,12 @@ impl Span {
         self.id = id.to_string();
     }
 
+    /// Set a unique identifier for this span, rejecting empty identifiers.
+    pub fn try_id(self, id: &str) -> Result<Self, SpanError> {
+        check_id(id)?;
+        Ok(self.id(id))
+    }
+
     /// Set a unique identifier shared by all spans within a single trace.
     /// This is a required field.
     pub fn trace_id(mut self, trace_id: &str) -> Self {
@@ -57,6 +179,12 @@ impl Span {
         self.trace_id = trace_id.to_string();
     }
 
+    /// Set the trace id of this span, rejecting empty trace ids.
+    pub fn try_trace_id(self, trace_id: &str) -> Result<Self, SpanError> {
+        check_trace_id(trace_id)?;
+        Ok(self.trace_id(trace_id))
+    }
+
     /// Set the start time of the span. This is a required field.
     pub fn timestamp(mut self, timestamp: u64) -> Self {
         self.timestamp = timestamp;
@@ -67,74 +195,447 @@ impl Span {
         self.timestamp = timestamp;
     }
 
+    /// Set the start time of the span, rejecting timestamps which are not
+    /// milliseconds since the epoch between the years 2000 and 2100.
+    pub fn try_timestamp(self, timestamp: u64) -> Result<Self, SpanError> {
+        check_timestamp(timestamp)?;
+        Ok(self.timestamp(timestamp))
+    }
+
     /// Set the name of this span.
     pub fn name(self, name: &str) -> Self {
-        self.attribute("name", name)
+        self.attribute_static("name", name)
     }
 
     pub fn set_name(&mut self, name: &str) {
-        self.set_attribute("name", name);
+        self.set_attribute_static("name", name);
     }
 
     /// Set the duration (in milliseconds) of this span.
     pub fn duration(self, duration: Duration) -> Self {
-        self.attribute("duration.ms", duration.as_millis())
+        self.attribute_static("duration.ms", duration.as_millis())
     }
 
     pub fn set_duration(&mut self, duration: Duration) {
-        self.set_attribute("duration.ms", duration.as_millis());
+        self.set_attribute_static("duration.ms", duration.as_millis());
+    }
+
+    /// Set the duration (in microseconds) of this span.
+    ///
+    /// The duration is sent as fractional number of milliseconds, so spans
+    /// shorter than a millisecond don't have a duration of zero.
+    pub fn duration_us(self, duration_us: u64) -> Self {
+        self.duration_f64(duration_us as f64 / 1000.0)
+    }
+
+    pub fn set_duration_us(&mut self, duration_us: u64) {
+        self.set_duration_f64(duration_us as f64 / 1000.0);
+    }
+
+    /// Set the duration (in fractional milliseconds) of this span.
+    pub fn duration_f64(self, duration_ms: f64) -> Self {
+        self.attribute_static("duration.ms", duration_ms)
+    }
+
+    pub fn set_duration_f64(&mut self, duration_ms: f64) {
+        self.set_attribute_static("duration.ms", duration_ms);
     }
 
     /// Set the id of the previous caller of this span.
     pub fn parent_id(self, parent_id: &str) -> Self {
-        self.attribute("parent.id", parent_id)
+        self.attribute_static("parent.id", parent_id)
     }
 
     pub fn set_parent_id(&mut self, parent_id: &str) {
-        self.set_attribute("parent.id", parent_id);
+        self.set_attribute_static("parent.id", parent_id);
     }
 
     /// Set the name of the service that created this span.
     pub fn service_name(self, service_name: &str) -> Self {
-        self.attribute("service.name", service_name)
+        self.attribute_static("service.name", service_name)
     }
 
     pub fn set_service_name(&mut self, service_name: &str) {
-        self.set_attribute("service.name", service_name);
+        self.set_attribute_static("service.name", service_name);
+    }
+
+    /// Set the instrumentation that created this span.
+    ///
+    /// This sets the `instrumentation.provider`, `instrumentation.name` and
+    /// `instrumentation.version` attributes, which identify the framework or
+    /// library a span originates from.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::Span;
+    /// let span = Span::new("id", "trace_id", 1_600_000_000_000)
+    ///     .instrumentation("opentelemetry", "hyper", "0.13.9");
+    /// ```
+    pub fn instrumentation(mut self, provider: &str, name: &str, version: &str) -> Self {
+        self.set_instrumentation(provider, name, version);
+        self
+    }
+
+    pub fn set_instrumentation(&mut self, provider: &str, name: &str, version: &str) {
+        self.set_attribute_static("instrumentation.provider", provider);
+        self.set_attribute_static("instrumentation.name", name);
+        self.set_attribute_static("instrumentation.version", version);
     }
 
     /// Set an attribute on the span.
     pub fn attribute<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
-        self.attributes.insert(key.to_string(), value.into());
+        self.set_attribute(key, value);
+        self
+    }
+
+    /// Set an attribute with a key known at compile time on the span.
+    ///
+    /// Unlike `attribute`, this doesn't allocate a copy of the key, which
+    /// saves allocations on hot instrumentation paths.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::Span;
+    /// let span = Span::new("id", "trace_id", 1000).attribute_static("http.method", "GET");
+    /// ```
+    pub fn attribute_static<T: Into<Value>>(mut self, key: &'static str, value: T) -> Self {
+        self.set_attribute_static(key, value);
+        self
+    }
+
+    pub fn set_attribute_static<T: Into<Value>>(&mut self, key: &'static str, value: T) {
+        self.attributes.insert(Key::Borrowed(key), value.into());
+    }
+
+    /// Set attributes from key value pairs on the span, replacing existing
+    /// attributes of the same name.
+    ///
+    /// Keys given as `String` are moved into the span without copying, and
+    /// no intermediate map is built.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::Span;
+    /// # use std::collections::HashMap;
+    /// let mut tags = HashMap::new();
+    /// tags.insert("region".to_string(), "eu");
+    ///
+    /// let span = Span::new("id", "trace_id", 1000).attributes(tags);
+    /// ```
+    pub fn attributes<I, K, V>(mut self, attributes: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        self.set_attributes(attributes);
+        self
+    }
+
+    pub fn set_attributes<I, K, V>(&mut self, attributes: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        self.attributes.extend(
+            attributes
+                .into_iter()
+                .map(|(key, value)| (Key::Owned(key.into()), value.into())),
+        );
+    }
+
+    /// Set a set of attributes on the span, replacing existing attributes
+    /// of the same name.
+    pub fn with_attributes<A: IntoAttributes>(mut self, attributes: A) -> Self {
+        self.set_attributes(attributes.into_attributes());
         self
     }
 
     pub fn set_attribute<T: Into<Value>>(&mut self, key: &str, value: T) {
-        self.attributes.insert(key.to_string(), value.into());
+        self.attributes
+            .insert(Key::Owned(key.to_string()), value.into());
+    }
+
+    /// Get the value of an attribute of the span.
+    pub fn get_attribute(&self, key: &str) -> Option<&Value> {
+        self.attributes.get(key)
+    }
+
+    /// Remove an attribute from the span, returning its value.
+    pub fn remove_attribute(&mut self, key: &str) -> Option<Value> {
+        self.attributes.remove(key)
+    }
+
+    /// Get the number of attributes of the span.
+    pub fn attribute_count(&self) -> usize {
+        self.attributes.len()
+    }
+
+    // Validates the id, trace id and timestamp of the span.
+    fn validate(&self) -> Result<(), SpanError> {
+        check_id(&self.id)?;
+        check_trace_id(&self.trace_id)?;
+        check_timestamp(self.timestamp)
+    }
+
+    // Returns an estimate of the size of the span serialized as JSON.
+    pub(crate) fn estimated_size(&self) -> usize {
+        // The size of the keys, punctuation and timestamp.
+        let fixed = 64;
+
+        fixed + self.id.len() + self.trace_id.len() + estimated_attributes_size(&self.attributes)
+    }
+}
+
+/// Marks a required field of a `SpanBuilder` which is not set yet.
+#[derive(Debug)]
+pub enum Missing {}
+
+/// Marks a required field of a `SpanBuilder` which is set.
+#[derive(Debug)]
+pub enum Present {}
+
+/// Builds a span, enforcing the required fields at compile time.
+///
+/// The type parameters track whether the identifier, trace id and timestamp
+/// are set. `build` is only available once all of them are set, so a missing
+/// required field is a compile error instead of a span rejected by the Trace
+/// API. Each required field can be set only once.
+///
+/// ```
+/// # use newrelic_telemetry::Span;
+/// let span = Span::builder()
+///     .id("id")
+///     .trace_id("trace_id")
+///     .timestamp(1_600_000_000_000)
+///     .name("name")
+///     .build();
+///
+/// assert_eq!(span, Span::new("id", "trace_id", 1_600_000_000_000).name("name"));
+/// ```
+///
+/// Building a span without a timestamp doesn't compile:
+///
+/// ```compile_fail
+/// # use newrelic_telemetry::Span;
+/// let span = Span::builder().id("id").trace_id("trace_id").build();
+/// ```
+#[derive(Debug)]
+pub struct SpanBuilder<I, T, S> {
+    span: Span,
+    state: PhantomData<(I, T, S)>,
+}
+
+impl SpanBuilder<Missing, Missing, Missing> {
+    /// Create a builder without any fields set.
+    pub fn new() -> Self {
+        SpanBuilder {
+            span: Span::new("", "", 0),
+            state: PhantomData,
+        }
+    }
+}
+
+impl Default for SpanBuilder<Missing, Missing, Missing> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S> SpanBuilder<Missing, T, S> {
+    /// Set a unique identifier for the span. This is a required field.
+    pub fn id(self, id: &str) -> SpanBuilder<Present, T, S> {
+        SpanBuilder {
+            span: self.span.id(id),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<I, S> SpanBuilder<I, Missing, S> {
+    /// Set a unique identifier shared by all spans within a single trace.
+    /// This is a required field.
+    pub fn trace_id(self, trace_id: &str) -> SpanBuilder<I, Present, S> {
+        SpanBuilder {
+            span: self.span.trace_id(trace_id),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<I, T> SpanBuilder<I, T, Missing> {
+    /// Set the start time of the span. This is a required field.
+    pub fn timestamp(self, timestamp: u64) -> SpanBuilder<I, T, Present> {
+        SpanBuilder {
+            span: self.span.timestamp(timestamp),
+            state: PhantomData,
+        }
     }
 }
 
-fn serialize_attributes<S>(attrs: &HashMap<String, Value>, s: S) -> Result<S::Ok, S::Error>
+impl<I, T, S> SpanBuilder<I, T, S> {
+    /// Set the name of the span.
+    pub fn name(mut self, name: &str) -> Self {
+        self.span = self.span.name(name);
+        self
+    }
+
+    /// Set the duration (in milliseconds) of the span.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.span = self.span.duration(duration);
+        self
+    }
+
+    /// Set the id of the previous caller of the span.
+    pub fn parent_id(mut self, parent_id: &str) -> Self {
+        self.span = self.span.parent_id(parent_id);
+        self
+    }
+
+    /// Set the name of the service that created the span.
+    pub fn service_name(mut self, service_name: &str) -> Self {
+        self.span = self.span.service_name(service_name);
+        self
+    }
+
+    /// Set the instrumentation that created the span.
+    pub fn instrumentation(mut self, provider: &str, name: &str, version: &str) -> Self {
+        self.span = self.span.instrumentation(provider, name, version);
+        self
+    }
+
+    /// Set an attribute on the span.
+    pub fn attribute<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        self.span = self.span.attribute(key, value);
+        self
+    }
+}
+
+impl SpanBuilder<Present, Present, Present> {
+    /// Build the span.
+    pub fn build(self) -> Span {
+        self.span
+    }
+
+    /// Build the span, validating the identifier, trace id and timestamp like
+    /// `Span::try_new`.
+    pub fn try_build(self) -> Result<Span, SpanError> {
+        self.span.validate()?;
+        Ok(self.span)
+    }
+}
+
+fn check_id(id: &str) -> Result<(), SpanError> {
+    if id.is_empty() {
+        return Err(SpanError::EmptyId);
+    }
+
+    Ok(())
+}
+
+fn check_trace_id(trace_id: &str) -> Result<(), SpanError> {
+    if trace_id.is_empty() {
+        return Err(SpanError::EmptyTraceId);
+    }
+
+    Ok(())
+}
+
+fn check_timestamp(timestamp: u64) -> Result<(), SpanError> {
+    if !(TIMESTAMP_MIN..TIMESTAMP_MAX).contains(&timestamp) {
+        return Err(SpanError::InvalidTimestamp(timestamp));
+    }
+
+    Ok(())
+}
+
+// The attribute listing the attributes removed from a span by trimming.
+const TRIMMED_ATTRIBUTES: &str = "truncated.attributes";
+
+// Attributes which are never removed when trimming spans.
+const TRIM_PROTECTED: &[&str] = &[
+    "name",
+    "parent.id",
+    "service.name",
+    "duration.ms",
+    TRIMMED_ATTRIBUTES,
+];
+
+// Adds the key of a removed attribute to the comma separated list of trimmed
+// attributes of a span.
+fn record_trimmed(span: &mut Span, key: &str) {
+    let trimmed = match span.attributes.get(TRIMMED_ATTRIBUTES) {
+        Some(Value::Str(trimmed)) => format!("{},{}", trimmed, key),
+        _ => key.to_string(),
+    };
+
+    span.attributes
+        .insert(Key::Borrowed(TRIMMED_ATTRIBUTES), Value::Str(trimmed));
+}
+
+// Returns the key of the attribute with the largest estimated size, leaving
+// out protected attributes.
+fn largest_attribute<'a, A>(attributes: A) -> Option<String>
+where
+    A: IntoIterator<Item = (&'a Key, &'a Value)>,
+{
+    attributes
+        .into_iter()
+        .filter(|(key, _)| !TRIM_PROTECTED.contains(&key.as_ref()))
+        .max_by_key(|(key, value)| key.len() + value.estimated_size())
+        .map(|(key, _)| key.to_string())
+}
+
+// Returns an estimate of the size of attributes serialized as JSON.
+fn estimated_attributes_size<'a, A>(attributes: A) -> usize
+where
+    A: IntoIterator<Item = (&'a Key, &'a Value)>,
+{
+    attributes
+        .into_iter()
+        .map(|(key, value)| key.len() + 4 + value.estimated_size())
+        .sum()
+}
+
+fn serialize_attributes<S>(attrs: &Arc<BTreeMap<Key, Value>>, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    let mut wrapper: HashMap<String, &HashMap<String, Value>> = HashMap::new();
+    let mut wrapper: BTreeMap<String, &BTreeMap<Key, Value>> = BTreeMap::new();
     wrapper.insert("attributes".to_string(), attrs);
     wrapper.serialize(s)
 }
 
+fn attributes_are_empty(attrs: &Arc<BTreeMap<Key, Value>>) -> bool {
+    attrs.is_empty()
+}
+
 /// Encapsulates a collection of spans and the common data they share
-#[derive(serde::Serialize, Debug, PartialEq)]
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
 pub struct SpanBatch {
     #[serde(skip_serializing)]
-    uuid: String,
+    pub(crate) uuid: String,
 
-    spans: Vec<Span>,
+    pub(crate) spans: Vec<Span>,
 
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    // Common attributes are shared between batches created by splitting, and
+    // only copied when modified. Like span attributes, they are ordered by
+    // key.
+    #[serde(skip_serializing_if = "attributes_are_empty")]
     #[serde(serialize_with = "serialize_attributes")]
     #[serde(rename = "common")]
-    attributes: HashMap<String, Value>,
+    pub(crate) attributes: Arc<BTreeMap<Key, Value>>,
+
+    // The estimated serialized size of the recorded spans.
+    #[serde(skip_serializing)]
+    spans_size: usize,
+
+    // Limits enforced by `record_checked`.
+    #[serde(skip_serializing)]
+    max_spans: Option<usize>,
+    #[serde(skip_serializing)]
+    max_bytes: usize,
+
+    #[serde(skip_serializing)]
+    priority: Priority,
 }
 
 impl From<Vec<Span>> for SpanBatch {
@@ -150,21 +651,225 @@ impl From<Vec<Span>> for SpanBatch {
     }
 }
 
+impl Default for SpanBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SpanBatch {
     /// Creates an empty `SpanBatch`.
     pub fn new() -> Self {
         SpanBatch {
             uuid: Uuid::new_v4().to_string(),
             spans: vec![],
-            attributes: HashMap::new(),
+            attributes: Arc::new(BTreeMap::new()),
+            spans_size: 0,
+            max_spans: None,
+            max_bytes: BATCH_BYTES_MAX,
+            priority: Priority::Normal,
         }
     }
 
+    /// Creates an empty `SpanBatch` with space for at least the given number
+    /// of spans.
+    ///
+    /// Recording up to that many spans doesn't reallocate the span storage of
+    /// the batch.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut batch = Self::new();
+        batch.reserve(capacity);
+        batch
+    }
+
+    /// Reserves space for at least the given number of additional spans.
+    pub fn reserve(&mut self, additional: usize) {
+        self.spans.reserve(additional);
+    }
+
+    /// Returns the number of spans the batch can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.spans.capacity()
+    }
+
     /// Adds the provided span to the batch.
     pub fn record(&mut self, span: Span) {
+        self.spans_size += span.estimated_size();
         self.spans.push(span);
     }
 
+    /// Adds the provided span to the batch, enforcing the limits of the batch.
+    ///
+    /// The span is validated like by `Span::try_new`. It is rejected if the
+    /// batch already holds the maximum number of spans, or if it would exceed
+    /// the maximum estimated payload size of the batch. This gives immediate
+    /// feedback, instead of a rejection by the ingest endpoint.
+    ///
+    /// By default, the number of spans isn't limited, and the payload size is
+    /// limited to 1MB.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{RecordError, Span, SpanBatch};
+    /// let mut batch = SpanBatch::new().max_spans(1);
+    ///
+    /// let span = Span::new("id1", "trace_id", 1_600_000_000_000);
+    /// assert_eq!(batch.record_checked(span.clone()), Ok(()));
+    /// assert_eq!(batch.record_checked(span), Err(RecordError::TooManySpans(1)));
+    /// ```
+    pub fn record_checked(&mut self, span: Span) -> Result<(), RecordError> {
+        span.validate()?;
+
+        if let Some(max) = self.max_spans {
+            if self.spans.len() >= max {
+                return Err(RecordError::TooManySpans(max));
+            }
+        }
+
+        if self.estimated_json_bytes() + span.estimated_size() > self.max_bytes {
+            return Err(RecordError::TooLarge(self.max_bytes));
+        }
+
+        self.record(span);
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of spans accepted by `record_checked`.
+    pub fn max_spans(mut self, max: usize) -> Self {
+        self.max_spans = Some(max);
+        self
+    }
+
+    /// Sets the maximum estimated payload size in bytes accepted by
+    /// `record_checked`.
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = max;
+        self
+    }
+
+    // Creates a batch with a new uuid holding the given spans, sharing the
+    // common attributes, limits and priority of this batch.
+    pub(crate) fn with_spans(&self, spans: Vec<Span>) -> SpanBatch {
+        SpanBatch {
+            uuid: Uuid::new_v4().to_string(),
+            spans_size: spans.iter().map(Span::estimated_size).sum(),
+            spans,
+            attributes: Arc::clone(&self.attributes),
+            max_spans: self.max_spans,
+            max_bytes: self.max_bytes,
+            priority: self.priority,
+        }
+    }
+
+    // Splits the batch into batches of at most the given number of spans,
+    // each with a new uuid and the common attributes of this batch.
+    #[cfg(feature = "client")]
+    pub(crate) fn chunks(mut self, max_spans: usize) -> Vec<SpanBatch> {
+        let mut chunks = vec![];
+
+        while !self.spans.is_empty() {
+            let rest = self.spans.split_off(self.spans.len().min(max_spans));
+            let spans = std::mem::replace(&mut self.spans, rest);
+            chunks.push(self.with_spans(spans));
+        }
+
+        chunks
+    }
+
+    /// Returns an estimate of the size of the batch serialized as JSON.
+    ///
+    /// The estimate is kept up to date while spans are recorded, so it is
+    /// cheap to get. It is an upper bound of the actual size, which allows
+    /// flushing batches before they exceed the 1MB payload limit of the Trace
+    /// API.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{Span, SpanBatch};
+    /// let mut batch = SpanBatch::new();
+    /// batch.record(Span::new("id", "trace_id", 1000));
+    ///
+    /// assert!(batch.estimated_json_bytes() < 1_000_000);
+    /// ```
+    pub fn estimated_json_bytes(&self) -> usize {
+        // The size of the keys and punctuation.
+        let fixed = 48;
+
+        fixed + self.spans_size + estimated_attributes_size(self.attributes.iter())
+    }
+
+    /// Returns the size of the batch serialized as JSON and compressed.
+    ///
+    /// This is the size of the payload sent to the Trace API. Unlike
+    /// `estimated_json_bytes`, it is computed on each call by serializing and
+    /// compressing the batch, which is expensive for large batches.
+    ///
+    /// ```
+    /// # use anyhow::Result;
+    /// # use newrelic_telemetry::{Span, SpanBatch};
+    /// # fn main() -> Result<()> {
+    /// let batch: SpanBatch = vec![Span::new("id", "trace_id", 1000)].into();
+    ///
+    /// println!("payload size: {} bytes", batch.compressed_bytes()?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "client")]
+    pub fn compressed_bytes(&self) -> Result<usize> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(self.marshall()?.as_bytes())?;
+
+        Ok(encoder.finish()?.len())
+    }
+
+    /// Sets the priority of the span batch. Returns `self` and can be chained.
+    ///
+    /// When back pressure forces the blocking client to drop batches, batches
+    /// of lower priority are dropped first. Batches created by splitting keep
+    /// the priority.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::{Priority, Sendable, SpanBatch};
+    /// let batch = SpanBatch::new().with_priority(Priority::High);
+    ///
+    /// assert_eq!(batch.priority(), Priority::High);
+    /// ```
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.set_priority(priority);
+        self
+    }
+
+    /// Sets the priority of the span batch.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Returns the uuid of the span batch.
+    ///
+    /// The uuid is sent as request id, which enables the ingest endpoint to
+    /// recognize duplicate requests.
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// Sets the uuid of the span batch.
+    ///
+    /// This allows batches which are replayed, e.g. from a spool, to reuse
+    /// the request id of their original attempt, so the ingest endpoint can
+    /// deduplicate them. The uuid must be a valid HTTP header value.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::SpanBatch;
+    /// let original = SpanBatch::new();
+    ///
+    /// let mut replayed = SpanBatch::new();
+    /// replayed.set_uuid(original.uuid());
+    ///
+    /// assert_eq!(replayed.uuid(), original.uuid());
+    /// ```
+    pub fn set_uuid(&mut self, uuid: &str) {
+        self.uuid = uuid.to_string();
+    }
+
     /// Sets an attribute on the span batch. Returns `self` and can be chained
     /// for concise addition of multiple attributes.
     pub fn attribute<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
@@ -172,9 +877,38 @@ impl SpanBatch {
         self
     }
 
+    /// Sets a set of common attributes on the span batch, replacing existing
+    /// attributes of the same name. Returns `self` and can be chained.
+    ///
+    /// ```
+    /// # use newrelic_telemetry::SpanBatch;
+    /// let batch = SpanBatch::new().with_common(vec![("host", "host1"), ("region", "eu")]);
+    ///
+    /// assert_eq!(batch.common_attributes().len(), 2);
+    /// ```
+    pub fn with_common<A: IntoAttributes>(mut self, attributes: A) -> Self {
+        Arc::make_mut(&mut self.attributes).extend(
+            attributes
+                .into_attributes()
+                .into_iter()
+                .map(|(key, value)| (Key::Owned(key), value)),
+        );
+        self
+    }
+
     /// Sets an attribute on the span batch.
     pub fn set_attribute<T: Into<Value>>(&mut self, key: &str, value: T) {
-        self.attributes.insert(key.to_string(), value.into());
+        Arc::make_mut(&mut self.attributes).insert(Key::Owned(key.to_string()), value.into());
+    }
+
+    /// Returns the common attributes of the span batch, ordered by key.
+    pub fn common_attributes(&self) -> &BTreeMap<Key, Value> {
+        &self.attributes
+    }
+
+    /// Returns the spans recorded in the span batch.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
     }
 }
 
@@ -183,10 +917,28 @@ impl Sendable for SpanBatch {
         &self.uuid
     }
 
+    fn data_type(&self) -> &'static str {
+        "spans"
+    }
+
+    fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
     /// Returns the span batch encoded as a json string in the format expected
     /// by the New Relic Telemetry API
     fn marshall(&self) -> Result<String> {
-        Ok(serde_json::to_string(&vec![self])?)
+        to_json(&vec![self])
+    }
+
+    /// Returns the span batch encoded as OTLP export request
+    #[cfg(feature = "otlp")]
+    fn marshall_otlp(&self) -> Result<Vec<u8>> {
+        crate::otlp::encode_spans(self)
     }
 
     /// Splits the batch in half.  This is mostly used when the API service
@@ -195,11 +947,134 @@ impl Sendable for SpanBatch {
         let new_batch_size: usize = self.spans.len() / 2;
         self.uuid = Uuid::new_v4().to_string();
 
-        Box::new(SpanBatch {
-            uuid: Uuid::new_v4().to_string(),
-            spans: self.spans.drain(new_batch_size..).collect(),
-            attributes: self.attributes.clone(),
-        })
+        let spans: Vec<Span> = self.spans.drain(new_batch_size..).collect();
+        let batch = self.with_spans(spans);
+        self.spans_size -= batch.spans_size;
+
+        Box::new(batch)
+    }
+
+    /// Partitions the spans into chunks of the given maximum estimated size.
+    ///
+    /// Chunks are at most half the size of the batch, so splitting always
+    /// makes progress. Spans exceeding the maximum size on their own are put
+    /// into a chunk of their own. A batch with less than two spans cannot be
+    /// split.
+    fn split_by_size(&mut self, max_bytes: usize) -> Vec<Box<dyn Sendable>> {
+        if self.spans.len() < 2 {
+            return vec![];
+        }
+
+        let overhead = self.estimated_json_bytes() - self.spans_size;
+        let target = max_bytes
+            .saturating_sub(overhead)
+            .min(self.spans_size.div_ceil(2));
+
+        let mut chunks: Vec<Vec<Span>> = vec![vec![]];
+        let mut chunk_size = 0;
+
+        for span in self.spans.drain(..) {
+            let size = span.estimated_size();
+
+            if chunk_size + size > target && chunk_size > 0 {
+                chunks.push(vec![]);
+                chunk_size = 0;
+            }
+
+            chunk_size += size;
+            if let Some(chunk) = chunks.last_mut() {
+                chunk.push(span);
+            }
+        }
+
+        let mut chunks = chunks.into_iter();
+
+        self.uuid = Uuid::new_v4().to_string();
+        self.spans = chunks.next().unwrap_or_default();
+        self.spans_size = self.spans.iter().map(Span::estimated_size).sum();
+
+        chunks
+            .map(|spans| Box::new(self.with_spans(spans)) as Box<dyn Sendable>)
+            .collect()
+    }
+
+    fn estimated_bytes(&self) -> Option<usize> {
+        Some(self.estimated_json_bytes())
+    }
+
+    /// Removes the largest attribute of a batch holding a single span.
+    ///
+    /// Attributes of the span are removed first, then common attributes. The
+    /// id, trace id, timestamp, name, parent id, service name and duration of
+    /// the span are kept. The keys of removed attributes are listed in the
+    /// `truncated.attributes` attribute of the span, separated by commas.
+    fn trim(&mut self) -> Option<String> {
+        if self.spans.len() != 1 {
+            return None;
+        }
+
+        let span = &mut self.spans[0];
+
+        let key = match largest_attribute(&span.attributes) {
+            Some(key) => {
+                span.attributes.remove(key.as_str());
+                key
+            }
+            None => {
+                let key = largest_attribute(self.attributes.iter())?;
+                Arc::make_mut(&mut self.attributes).remove(key.as_str());
+                key
+            }
+        };
+
+        record_trimmed(span, &key);
+        self.spans_size = span.estimated_size();
+        Some(key)
+    }
+}
+
+/// A shared span batch is copied only when it has to be modified, for example
+/// when it is split because the payload is too large.
+impl Sendable for Arc<SpanBatch> {
+    fn uuid(&self) -> &str {
+        SpanBatch::uuid(self)
+    }
+
+    fn data_type(&self) -> &'static str {
+        SpanBatch::data_type(self)
+    }
+
+    fn len(&self) -> usize {
+        SpanBatch::len(self)
+    }
+
+    fn priority(&self) -> Priority {
+        SpanBatch::priority(self)
+    }
+
+    fn marshall(&self) -> Result<String> {
+        SpanBatch::marshall(self)
+    }
+
+    #[cfg(feature = "otlp")]
+    fn marshall_otlp(&self) -> Result<Vec<u8>> {
+        SpanBatch::marshall_otlp(self)
+    }
+
+    fn split(&mut self) -> Box<dyn Sendable> {
+        Arc::make_mut(self).split()
+    }
+
+    fn split_by_size(&mut self, max_bytes: usize) -> Vec<Box<dyn Sendable>> {
+        Arc::make_mut(self).split_by_size(max_bytes)
+    }
+
+    fn estimated_bytes(&self) -> Option<usize> {
+        SpanBatch::estimated_bytes(self)
+    }
+
+    fn trim(&mut self) -> Option<String> {
+        Arc::make_mut(self).trim()
     }
 }
 
@@ -216,10 +1091,11 @@ impl fmt::Display for SpanBatch {
 
 #[cfg(test)]
 mod tests {
-    use super::{Sendable, Span, SpanBatch};
-    use crate::attribute::Value;
+    use super::{Priority, RecordError, Sendable, Span, SpanBatch, SpanError};
+    use crate::attribute::{Key, Value};
     use anyhow::Result;
     use serde_json::json;
+    use std::sync::Arc;
     use std::time::Duration;
 
     macro_rules! assert_json_eq {
@@ -271,6 +1147,83 @@ mod tests {
         assert_eq!(span.timestamp, 3);
     }
 
+    #[test]
+    fn span_try_new() {
+        let span = Span::try_new("id1", "traceId1", 1_600_000_000_000);
+        assert_eq!(span, Ok(Span::new("id1", "traceId1", 1_600_000_000_000)));
+
+        assert_eq!(
+            Span::try_new("", "traceId1", 1_600_000_000_000),
+            Err(SpanError::EmptyId)
+        );
+        assert_eq!(
+            Span::try_new("id1", "", 1_600_000_000_000),
+            Err(SpanError::EmptyTraceId)
+        );
+
+        // Timestamps in seconds and nanoseconds are rejected.
+        for timestamp in [0, 1_600_000_000, 1_600_000_000_000_000_000] {
+            assert_eq!(
+                Span::try_new("id1", "traceId1", timestamp),
+                Err(SpanError::InvalidTimestamp(timestamp))
+            );
+        }
+    }
+
+    #[test]
+    fn span_try_setters() {
+        let span = Span::new("id1", "traceId1", 1);
+
+        assert_eq!(span.clone().try_id(""), Err(SpanError::EmptyId));
+        assert_eq!(span.clone().try_id("id2").map(|s| s.id), Ok("id2".into()));
+        assert_eq!(span.clone().try_trace_id(""), Err(SpanError::EmptyTraceId));
+        assert_eq!(
+            span.clone().try_timestamp(2),
+            Err(SpanError::InvalidTimestamp(2))
+        );
+        assert_eq!(
+            span.try_timestamp(1_600_000_000_000).map(|s| s.timestamp),
+            Ok(1_600_000_000_000)
+        );
+    }
+
+    #[test]
+    fn span_set_duration() {
+        let mut span = Span::new("id1", "traceId1", 1);
+
+        span.set_duration(Duration::from_micros(1500));
+        assert_eq!(span.attributes.get("duration.ms"), Some(&Value::UInt128(1)));
+
+        span.set_duration_us(250);
+        assert_eq!(
+            span.attributes.get("duration.ms"),
+            Some(&Value::Float(0.25))
+        );
+
+        span = span.duration_f64(1.5);
+        assert_eq!(span.attributes.get("duration.ms"), Some(&Value::Float(1.5)));
+
+        let json_span = json!({"id": "id1", "trace.id": "traceId1", "timestamp": 1, "attributes": {"duration.ms": 1.5}});
+        assert_eq!(json!(span), json_span);
+    }
+
+    #[test]
+    fn span_get_remove_attribute() {
+        let mut span = Span::new("id1", "traceId1", 1)
+            .name("name")
+            .attribute("user.email", "user@example.com");
+        assert_eq!(span.attribute_count(), 2);
+        assert_eq!(span.get_attribute("name"), Some(&Value::from("name")));
+
+        assert_eq!(
+            span.remove_attribute("user.email"),
+            Some(Value::from("user@example.com"))
+        );
+        assert_eq!(span.remove_attribute("user.email"), None);
+        assert_eq!(span.get_attribute("user.email"), None);
+        assert_eq!(span.attribute_count(), 1);
+    }
+
     #[test]
     fn span_to_json() {
         // Check span JSON serialization with empty attribute hashmap.
@@ -341,6 +1294,22 @@ mod tests {
             span.attributes.get("service.name"),
             Some(&Value::Str(String::from("serviceName2")))
         );
+
+        // Test instrumentation attributes
+        span.set_instrumentation("provider", "name", "1.0");
+        span = span.instrumentation("provider2", "name2", "2.0");
+        assert_eq!(
+            span.attributes.get("instrumentation.provider"),
+            Some(&Value::Str(String::from("provider2")))
+        );
+        assert_eq!(
+            span.attributes.get("instrumentation.name"),
+            Some(&Value::Str(String::from("name2")))
+        );
+        assert_eq!(
+            span.attributes.get("instrumentation.version"),
+            Some(&Value::Str(String::from("2.0")))
+        );
     }
 
     #[test]
@@ -388,14 +1357,14 @@ mod tests {
         assert_eq!(span.attributes.get("attr.int"), Some(&Value::Int(val_i64)));
 
         // Test Float attribute
-        let val_f32: f32 = 3.14;
+        let val_f32: f32 = 1.5;
         span.set_attribute("attr.float", val_f32);
         assert_eq!(
             span.attributes.get("attr.float"),
             Some(&Value::Float(val_f32 as f64))
         );
 
-        let val_f64: f64 = 6.28;
+        let val_f64: f64 = 2.5;
         span = span.attribute("attr.float", val_f64);
         assert_eq!(
             span.attributes.get("attr.float"),
@@ -438,11 +1407,361 @@ mod tests {
         assert_eq!(batch.spans[0], Span::new("id0", "trace_id0", 1));
 
         // confirm the uuid for the second batch is not the same as the first
-        // and that the first remains unchanged
+        // and that the first one got a new uuid as well
         assert_ne!(uuid, second_uuid);
         assert_ne!(uuid, batch.uuid());
     }
 
+    #[test]
+    fn spanbatch_to_json_ordered() -> Result<()> {
+        let span = Span::new("id0", "trace_id0", 1)
+            .attribute("b", 2)
+            .attribute("c", 3)
+            .attribute("a", 1);
+        let batch = SpanBatch::from(vec![span])
+            .attribute("z", 1)
+            .attribute("y", 2);
+
+        assert_eq!(
+            batch.marshall()?,
+            r#"[{"spans":[{"id":"id0","trace.id":"trace_id0","timestamp":1,"attributes":{"a":1,"b":2,"c":3}}],"common":{"attributes":{"y":2,"z":1}}}]"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn spanbatch_record_checked() {
+        let mut batch = SpanBatch::new().max_spans(2);
+        let span = Span::new("id0", "trace_id0", 1_600_000_000_000);
+
+        assert_eq!(
+            batch.record_checked(Span::new("", "trace_id0", 1_600_000_000_000)),
+            Err(RecordError::InvalidSpan(SpanError::EmptyId))
+        );
+        assert_eq!(batch.record_checked(span.clone()), Ok(()));
+        assert_eq!(batch.record_checked(span.clone()), Ok(()));
+        assert_eq!(
+            batch.record_checked(span),
+            Err(RecordError::TooManySpans(2))
+        );
+        assert_eq!(batch.spans.len(), 2);
+    }
+
+    #[test]
+    fn spanbatch_record_checked_size() {
+        let span = Span::new("id0", "trace_id0", 1_600_000_000_000).attribute("attr", "value");
+        let mut batch = SpanBatch::new().max_bytes(200);
+
+        assert_eq!(batch.record_checked(span.clone()), Ok(()));
+        assert_eq!(
+            batch.record_checked(span.clone()),
+            Err(RecordError::TooLarge(200))
+        );
+
+        // Estimated sizes are an upper bound of serialized sizes.
+        let batch = SpanBatch::from(vec![span; 10]).attribute("common", 1);
+        assert!(batch.estimated_json_bytes() >= batch.marshall().unwrap().len());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn spanbatch_compressed_bytes() -> Result<()> {
+        let batch = SpanBatch::from(span_vec(100));
+        let json_bytes = batch.marshall()?.len();
+
+        // Payloads of similar spans compress well.
+        let compressed_bytes = batch.compressed_bytes()?;
+        assert!(compressed_bytes > 0);
+        assert!(compressed_bytes < json_bytes / 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn span_with_attributes() {
+        let span = Span::new("id", "trace_id", 1000)
+            .attribute("a", 0)
+            .with_attributes(vec![("a", 1), ("b", 2)]);
+
+        assert_eq!(
+            span,
+            Span::new("id", "trace_id", 1000)
+                .attribute("a", 1)
+                .attribute("b", 2)
+        );
+    }
+
+    #[test]
+    fn span_attribute_static() {
+        let mut span = Span::new("id", "trace_id", 1000)
+            .name("name")
+            .attribute_static("static", 1);
+        span.set_attribute("owned", 2);
+
+        let keys: Vec<&Key> = span.attributes.iter().map(|(key, _)| key).collect();
+        assert!(matches!(keys[0], Key::Borrowed("name")));
+        assert!(matches!(keys[1], Key::Owned(_)));
+        assert!(matches!(keys[2], Key::Borrowed("static")));
+
+        assert_eq!(span.get_attribute("static"), Some(&Value::Int(1)));
+        assert_eq!(span.get_attribute("owned"), Some(&Value::Int(2)));
+    }
+
+    #[test]
+    fn span_attributes() {
+        let mut span = Span::new("id", "trace_id", 1000)
+            .attribute("a", 0)
+            .attributes(vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        span.set_attributes(vec![("c", true)]);
+
+        assert_eq!(
+            span,
+            Span::new("id", "trace_id", 1000)
+                .attribute("a", 1)
+                .attribute("b", 2)
+                .attribute("c", true)
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn spanbatch_chunks() {
+        let batch = SpanBatch::from(span_vec(5)).attribute("attr", 1);
+        let uuid = batch.uuid.clone();
+
+        let chunks = batch.chunks(2);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].spans(), &span_vec(5)[0..2]);
+        assert_eq!(chunks[1].spans(), &span_vec(5)[2..4]);
+        assert_eq!(chunks[2].spans(), &span_vec(5)[4..]);
+
+        for chunk in &chunks {
+            assert_ne!(chunk.uuid, uuid);
+            assert_eq!(chunk.common_attributes().get("attr"), Some(&Value::Int(1)));
+            assert_eq!(
+                chunk.spans_size,
+                chunk.with_spans(chunk.spans.clone()).spans_size
+            );
+        }
+    }
+
+    #[test]
+    fn spanbatch_capacity() {
+        let mut batch = SpanBatch::with_capacity(10);
+        assert!(batch.capacity() >= 10);
+        assert!(batch.spans().is_empty());
+
+        batch.reserve(20);
+        assert!(batch.capacity() >= 20);
+    }
+
+    #[test]
+    fn spanbatch_with_common() {
+        let batch = SpanBatch::new()
+            .attribute("a", 0)
+            .with_common(vec![("a", 1), ("b", 2)]);
+
+        assert_eq!(batch.common_attributes().get("a"), Some(&Value::Int(1)));
+        assert_eq!(batch.common_attributes().get("b"), Some(&Value::Int(2)));
+    }
+
+    #[test]
+    fn span_builder() {
+        let span = Span::builder()
+            .name("name")
+            .timestamp(1_600_000_000_000)
+            .trace_id("trace_id")
+            .id("id")
+            .duration(Duration::from_millis(10))
+            .parent_id("parent_id")
+            .service_name("service")
+            .attribute("attr", 1)
+            .build();
+
+        assert_eq!(
+            span,
+            Span::new("id", "trace_id", 1_600_000_000_000)
+                .name("name")
+                .duration(Duration::from_millis(10))
+                .parent_id("parent_id")
+                .service_name("service")
+                .attribute("attr", 1)
+        );
+
+        let span = Span::builder().id("").trace_id("trace_id").timestamp(1);
+        assert_eq!(span.try_build(), Err(SpanError::EmptyId));
+    }
+
+    #[test]
+    fn spanbatch_shared() -> Result<()> {
+        let batch = Arc::new(SpanBatch::from(span_vec(4)));
+        let mut shared = Arc::clone(&batch);
+
+        assert_eq!(Sendable::uuid(&shared), batch.uuid);
+        assert_eq!(Sendable::len(&shared), 4);
+        assert_eq!(Sendable::marshall(&shared)?, batch.marshall()?);
+
+        // Splitting copies the batch, leaving the original untouched.
+        let other = shared.split();
+        assert_eq!(Sendable::len(&shared), 2);
+        assert_eq!(other.len(), 2);
+        assert_eq!(batch.len(), 4);
+        assert!(!Arc::ptr_eq(&batch, &shared));
+
+        Ok(())
+    }
+
+    #[test]
+    fn spanbatch_priority() {
+        let mut batch = SpanBatch::from(span_vec(4));
+        assert_eq!(batch.priority(), Priority::Normal);
+
+        batch.set_priority(Priority::Low);
+        assert_eq!(batch.priority(), Priority::Low);
+
+        // Split batches keep the priority.
+        let other = batch.split();
+        assert_eq!(other.priority(), Priority::Low);
+    }
+
+    #[test]
+    fn spanbatch_set_uuid() {
+        let mut batch = SpanBatch::from(span_vec(4));
+        batch.set_uuid("replayed");
+
+        assert_eq!(batch.uuid(), "replayed");
+        assert_eq!(Sendable::uuid(&batch), "replayed");
+
+        // Split batches carry a different payload and get new uuids.
+        let other = batch.split();
+        assert_ne!(batch.uuid(), "replayed");
+        assert_ne!(other.uuid(), "replayed");
+    }
+
+    #[test]
+    fn spanbatch_content_hash() {
+        let batch = SpanBatch::from(span_vec(2)).attribute("attr", 1);
+        let hash = batch.content_hash();
+
+        // The uuid isn't part of the content.
+        assert!(hash.is_some());
+        assert_eq!(
+            SpanBatch::from(span_vec(2))
+                .attribute("attr", 1)
+                .content_hash(),
+            hash
+        );
+
+        assert_ne!(SpanBatch::from(span_vec(2)).content_hash(), hash);
+        assert_ne!(
+            SpanBatch::from(span_vec(3))
+                .attribute("attr", 1)
+                .content_hash(),
+            hash
+        );
+    }
+
+    #[test]
+    fn spanbatch_getters() {
+        let batch = SpanBatch::from(span_vec(2)).attribute("attr", 1);
+
+        assert_eq!(batch.spans(), &span_vec(2)[..]);
+        assert_eq!(batch.common_attributes().len(), 1);
+        assert_eq!(batch.common_attributes().get("attr"), Some(&Value::Int(1)));
+    }
+
+    #[test]
+    fn spanbatch_identity() {
+        let batch = SpanBatch::from(span_vec(3));
+
+        assert_eq!(batch.data_type(), "spans");
+        assert_eq!(batch.len(), 3);
+    }
+
+    #[test]
+    fn spanbatch_split_by_size() {
+        let large = Span::new("large", "trace_id", 1).attribute("data", "x".repeat(1000).as_str());
+        let mut spans = vec![large.clone()];
+        spans.append(&mut span_vec(20));
+
+        let mut batch = SpanBatch::from(spans).attribute("attr", 1);
+        let uuid = batch.uuid().to_string();
+        let overhead = SpanBatch::new().attribute("attr", 1).estimated_json_bytes();
+        let max = overhead + 5 * span_vec(20)[19].estimated_size();
+
+        let chunks = batch.split_by_size(max);
+
+        // The large span is put into a chunk of its own, the remaining spans
+        // are partitioned into chunks of 5 spans.
+        assert_eq!(batch.spans, vec![large]);
+        assert_ne!(batch.uuid(), uuid);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.len() == 5));
+        assert!(chunks.iter().all(|c| c.uuid() != uuid));
+
+        // Single spans cannot be split.
+        assert!(batch.split_by_size(max).is_empty());
+    }
+
+    #[test]
+    fn spanbatch_trim() {
+        let span = Span::new("id", "trace_id", 1)
+            .name("name")
+            .attribute("small", 1)
+            .attribute("large", "x".repeat(100).as_str());
+        let mut batch = SpanBatch::from(vec![span]).attribute("common", "value");
+        let size = batch.estimated_json_bytes();
+
+        assert_eq!(batch.trim(), Some("large".to_string()));
+        assert!(batch.estimated_json_bytes() < size);
+        assert_eq!(batch.trim(), Some("small".to_string()));
+        assert_eq!(batch.trim(), Some("common".to_string()));
+        assert_eq!(batch.trim(), None);
+
+        // Required fields are kept, and removed attributes are recorded.
+        assert_eq!(
+            batch.spans()[0].get_attribute("name"),
+            Some(&Value::from("name"))
+        );
+        assert_eq!(
+            batch.spans()[0].get_attribute("truncated.attributes"),
+            Some(&Value::from("large,small,common"))
+        );
+        assert!(batch.common_attributes().is_empty());
+
+        // Batches of several spans are split instead of trimmed.
+        let mut batch = SpanBatch::from(span_vec(2));
+        assert_eq!(batch.trim(), None);
+    }
+
+    #[test]
+    fn spanbatch_split_by_size_halves() {
+        let mut batch = SpanBatch::from(span_vec(4));
+
+        let chunks = batch.split_by_size(1_000_000);
+
+        assert_eq!(batch.spans, span_vec(2));
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].len(), 2);
+    }
+
+    #[test]
+    fn spanbatch_split_shares_attributes() {
+        let mut batch = SpanBatch::from(span_vec(4)).attribute("attr", 1);
+        let attributes = batch.attributes.clone();
+
+        let _second_batch = batch.split();
+        assert!(Arc::ptr_eq(&batch.attributes, &attributes));
+
+        // Modifying the attributes of a split batch copies them.
+        batch.set_attribute("attr", 2);
+        assert!(!Arc::ptr_eq(&batch.attributes, &attributes));
+        assert_eq!(attributes.get("attr"), Some(&Value::Int(1)));
+        assert_eq!(batch.attributes.get("attr"), Some(&Value::Int(2)));
+    }
+
     #[test]
     fn spanbatch_to_json() -> Result<()> {
         // Check span JSON serialization with empty attribute hashmap.
@@ -505,14 +1824,14 @@ mod tests {
         assert_eq!(batch.attributes.get("attr.int"), Some(&Value::Int(val_i64)));
 
         // Test Float attribute
-        let val_f32: f32 = 3.14;
+        let val_f32: f32 = 1.5;
         batch.set_attribute("attr.float", val_f32);
         assert_eq!(
             batch.attributes.get("attr.float"),
             Some(&Value::Float(val_f32 as f64))
         );
 
-        let val_f64: f64 = 6.28;
+        let val_f64: f64 = 2.5;
         batch = batch.attribute("attr.float", val_f64);
         assert_eq!(
             batch.attributes.get("attr.float"),
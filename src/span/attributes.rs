@@ -0,0 +1,130 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::{Key, Value};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::slice;
+
+// The number of attributes stored inline with the `smallvec` feature, which
+// covers most spans without allocating.
+#[cfg(feature = "smallvec")]
+const INLINE_ATTRIBUTES: usize = 8;
+
+#[cfg(feature = "smallvec")]
+type Entries = smallvec::SmallVec<[(Key, Value); INLINE_ATTRIBUTES]>;
+
+#[cfg(not(feature = "smallvec"))]
+type Entries = Vec<(Key, Value)>;
+
+/// The attributes of a span, ordered by key.
+///
+/// Spans carry few attributes, so they are kept in a vector sorted by key
+/// rather than in a map, which takes a single allocation. With the
+/// `smallvec` feature, up to eight attributes are stored inline without
+/// allocating.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Attributes {
+    entries: Entries,
+}
+
+impl Attributes {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.position(key).ok().map(|i| &self.entries[i].1)
+    }
+
+    /// Inserts an attribute, returning the previous value of the key.
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        match self.position(&key) {
+            Ok(i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            Err(i) => {
+                self.entries.insert(i, (key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let i = self.position(key).ok()?;
+        Some(self.entries.remove(i).1)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    // Returns the index of a key, or the index it would be inserted at.
+    fn position(&self, key: &str) -> Result<usize, usize> {
+        self.entries.binary_search_by(|(k, _)| k.as_ref().cmp(key))
+    }
+}
+
+pub(crate) type Iter<'a> =
+    std::iter::Map<slice::Iter<'a, (Key, Value)>, fn(&'a (Key, Value)) -> (&'a Key, &'a Value)>;
+
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = (&'a Key, &'a Value);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl Extend<(Key, Value)> for Attributes {
+    fn extend<I: IntoIterator<Item = (Key, Value)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl Serialize for Attributes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_by_key() {
+        let mut attributes = Attributes::default();
+
+        assert_eq!(attributes.insert(Key::Borrowed("b"), Value::Int(1)), None);
+        assert_eq!(attributes.insert(Key::Borrowed("c"), Value::Int(2)), None);
+        assert_eq!(attributes.insert(Key::Borrowed("a"), Value::Int(3)), None);
+        assert_eq!(
+            attributes.insert(Key::Borrowed("b"), Value::Int(4)),
+            Some(Value::Int(1))
+        );
+
+        let keys: Vec<&str> = attributes.iter().map(|(k, _)| k.as_ref()).collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(attributes.get("b"), Some(&Value::Int(4)));
+        assert_eq!(attributes.get("d"), None);
+
+        assert_eq!(attributes.remove("a"), Some(Value::Int(3)));
+        assert_eq!(attributes.remove("a"), None);
+        assert_eq!(attributes.len(), 2);
+
+        assert_eq!(
+            serde_json::to_string(&attributes).unwrap(),
+            r#"{"b":4,"c":2}"#
+        );
+    }
+}
@@ -0,0 +1,270 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::attribute::Value;
+use crate::span::{Span, SpanBatch};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+// The version of the line format.
+const VERSION: u64 = 1;
+
+// The type of lines holding span batches.
+const TYPE_SPANS: &str = "spans";
+
+// A line holding a batch, as it is written.
+//
+// The batch is serialized directly, rather than via a `serde_json::Value`,
+// which cannot hold 128 bit integers.
+#[derive(Serialize)]
+struct ExportedLine<'a> {
+    version: u64,
+    #[serde(rename = "type")]
+    data_type: &'a str,
+    uuid: &'a str,
+    batch: &'a SpanBatch,
+}
+
+/// Writes span batches to a writer, one line per batch.
+///
+/// ```
+/// # use anyhow::Result;
+/// # use newrelic_telemetry::{spool, Span, SpanBatch};
+/// # fn main() -> Result<()> {
+/// let batch: SpanBatch = vec![Span::new("id", "trace_id", 1000)].into();
+///
+/// let mut spooled = vec![];
+/// spool::export(&mut spooled, &[batch])?;
+///
+/// let batches = spool::import(&spooled[..])?;
+/// assert_eq!(batches[0].spans(), &[Span::new("id", "trace_id", 1000)]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn export<W: Write>(writer: &mut W, batches: &[SpanBatch]) -> Result<()> {
+    for batch in batches {
+        let line = ExportedLine {
+            version: VERSION,
+            data_type: TYPE_SPANS,
+            uuid: batch.uuid(),
+            batch,
+        };
+
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads span batches written by `export` from a reader.
+///
+/// Empty lines are skipped. Batches keep their uuid, so the ingest endpoint
+/// can recognize batches which were already sent.
+pub fn import<R: BufRead>(reader: R) -> Result<Vec<SpanBatch>> {
+    let mut batches = vec![];
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let batch = parse_line(&line).with_context(|| format!("invalid line {}", index + 1))?;
+        batches.push(batch);
+    }
+
+    Ok(batches)
+}
+
+// A line holding a batch, as it is read. The batch is parsed once the
+// version and type are known.
+#[derive(Deserialize)]
+struct ImportedLine<'a> {
+    version: Option<u64>,
+    #[serde(rename = "type")]
+    data_type: Option<String>,
+    uuid: Option<String>,
+    #[serde(borrow)]
+    batch: &'a RawValue,
+}
+
+// A spooled span batch.
+//
+// Attribute values are kept as raw JSON, so 128 bit integers, which don't fit
+// into a `serde_json::Value`, can be parsed without losing precision.
+#[derive(Deserialize)]
+struct SpooledBatch<'a> {
+    #[serde(borrow)]
+    spans: Vec<SpooledSpan<'a>>,
+    #[serde(borrow, default)]
+    common: Option<SpooledAttributes<'a>>,
+}
+
+#[derive(Deserialize)]
+struct SpooledSpan<'a> {
+    id: String,
+    #[serde(rename = "trace.id")]
+    trace_id: String,
+    timestamp: u64,
+    #[serde(borrow, default)]
+    attributes: BTreeMap<String, &'a RawValue>,
+}
+
+#[derive(Deserialize)]
+struct SpooledAttributes<'a> {
+    #[serde(borrow, default)]
+    attributes: BTreeMap<String, &'a RawValue>,
+}
+
+// Parses a line holding a span batch.
+fn parse_line(line: &str) -> Result<SpanBatch> {
+    let line: ImportedLine = serde_json::from_str(line)?;
+
+    match line.version {
+        Some(VERSION) => (),
+        v => return Err(anyhow!("unsupported version {:?}", v)),
+    }
+
+    match line.data_type.as_deref() {
+        Some(TYPE_SPANS) => (),
+        t => return Err(anyhow!("unsupported type {:?}", t)),
+    }
+
+    let spooled: SpooledBatch = serde_json::from_str(line.batch.get())?;
+
+    let spans = spooled
+        .spans
+        .iter()
+        .map(parse_span)
+        .collect::<Result<Vec<Span>>>()?;
+
+    let mut batch = SpanBatch::from(spans);
+
+    if let Some(uuid) = &line.uuid {
+        batch.set_uuid(uuid);
+    }
+
+    if let Some(common) = &spooled.common {
+        for (key, value) in &common.attributes {
+            batch.set_attribute(key, parse_value(value)?);
+        }
+    }
+
+    Ok(batch)
+}
+
+// Parses a span in the format of the Trace API.
+fn parse_span(span: &SpooledSpan) -> Result<Span> {
+    let mut result = Span::new(&span.id, &span.trace_id, span.timestamp);
+
+    for (key, value) in &span.attributes {
+        result.set_attribute(key, parse_value(value)?);
+    }
+
+    Ok(result)
+}
+
+// Parses an attribute value.
+//
+// Integers are parsed as signed integers if possible, as the JSON
+// representation doesn't distinguish signed and unsigned values. Integers
+// outside of the 64 bit range are parsed as 128 bit integers.
+fn parse_value(value: &RawValue) -> Result<Value> {
+    let raw = value.get().trim();
+
+    let digits = raw.strip_prefix('-').unwrap_or(raw);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return if let Ok(i) = raw.parse::<i64>() {
+            Ok(Value::Int(i))
+        } else if let Ok(u) = raw.parse::<u64>() {
+            Ok(Value::UInt(u))
+        } else if let Ok(i) = raw.parse::<i128>() {
+            Ok(Value::Int128(i))
+        } else if let Ok(u) = raw.parse::<u128>() {
+            Ok(Value::UInt128(u))
+        } else {
+            Err(anyhow!("integer attribute value {} out of range", raw))
+        };
+    }
+
+    match serde_json::from_str(raw)? {
+        serde_json::Value::Bool(b) => Ok(Value::Bool(b)),
+        serde_json::Value::String(s) => Ok(Value::Str(s)),
+        serde_json::Value::Number(n) => Ok(Value::Float(n.as_f64().unwrap_or_default())),
+        v => Err(anyhow!("unsupported attribute value {}", v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let span = Span::new("id1", "trace_id1", 1000)
+            .name("name")
+            .attribute("int", -1)
+            .attribute("uint", u64::MAX)
+            .attribute("float", 1.5)
+            .attribute("int128", i128::MIN)
+            .attribute("uint128", u128::MAX)
+            .attribute("bool", true);
+        let batches = vec![
+            SpanBatch::from(vec![span.clone()]).attribute("common", "value"),
+            SpanBatch::from(vec![span, Span::new("id2", "trace_id2", 2000)]),
+        ];
+
+        let mut spooled = vec![];
+        export(&mut spooled, &batches)?;
+
+        assert_eq!(spooled.iter().filter(|&&b| b == b'\n').count(), 2);
+
+        let imported = import(&spooled[..])?;
+
+        assert_eq!(imported.len(), 2);
+        for (imported, batch) in imported.iter().zip(&batches) {
+            assert_eq!(imported.uuid(), batch.uuid());
+            assert_eq!(imported.spans(), batch.spans());
+            assert_eq!(imported.common_attributes(), batch.common_attributes());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn import_errors() {
+        let lines = [
+            "not json",
+            r#"{"version":2,"type":"spans","batch":{"spans":[]}}"#,
+            r#"{"version":1,"type":"metrics","batch":{}}"#,
+            r#"{"version":1,"type":"spans","batch":{}}"#,
+            r#"{"version":1,"type":"spans","batch":{"spans":[{"id":"id"}]}}"#,
+            r#"{"version":1,"type":"spans","batch":{"spans":[{"id":"id","trace.id":"t","timestamp":1,"attributes":{"a":[]}}]}}"#,
+            r#"{"version":1,"type":"spans","batch":{"spans":[{"id":"id","trace.id":"t","timestamp":1,"attributes":{"a":1000000000000000000000000000000000000000}}]}}"#,
+        ];
+
+        for line in &lines {
+            assert!(import(line.as_bytes()).is_err(), "{} is invalid", line);
+        }
+    }
+
+    #[test]
+    fn import_empty_lines() -> Result<()> {
+        let spooled = "\n{\"version\":1,\"type\":\"spans\",\"batch\":{\"spans\":[]}}\n\n";
+
+        let imported = import(spooled.as_bytes())?;
+
+        assert_eq!(imported.len(), 1);
+        assert!(imported[0].spans().is_empty());
+
+        Ok(())
+    }
+}
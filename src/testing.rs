@@ -0,0 +1,194 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use crate::client::TelemetryClient;
+use crate::sendable::Sendable;
+use crate::span::SpanBatch;
+use log::error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+mod endpoint;
+pub use endpoint::{MockEndpoint, Payload};
+
+/// A client recording calls instead of sending data.
+///
+/// `MockClient` implements `TelemetryClient` and can be used in unit tests of
+/// code that sends data to New Relic, without the need for a live HTTP
+/// endpoint.
+///
+/// ```
+/// # use newrelic_telemetry::{Span, SpanBatch, TelemetryClient};
+/// # use newrelic_telemetry::testing::MockClient;
+/// # futures::executor::block_on(async {
+/// let client = MockClient::new();
+///
+/// client.send_spans(vec![Span::new("id", "trace_id", 1000)].into()).await;
+///
+/// assert_eq!(client.span_batch_count(), 1);
+/// # });
+/// ```
+#[derive(Debug, Default)]
+pub struct MockClient {
+    span_batches: Mutex<Vec<SpanBatch>>,
+}
+
+impl MockClient {
+    /// Creates a mock client without any recorded calls.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of span batches sent via this client.
+    pub fn span_batch_count(&self) -> usize {
+        match self.span_batches.lock() {
+            Ok(batches) => batches.len(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Removes and returns all span batches sent via this client, in the
+    /// order they were sent.
+    pub fn take_span_batches(&self) -> Vec<SpanBatch> {
+        match self.span_batches.lock() {
+            Ok(mut batches) => batches.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl TelemetryClient for MockClient {
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        if let Ok(mut batches) = self.span_batches.lock() {
+            batches.push(batch);
+        }
+
+        Box::pin(async {})
+    }
+}
+
+/// A client recording marshalled payloads instead of sending them.
+///
+/// `RecordingClient` is a `MockClient` returning the JSON payloads that would
+/// have been sent to New Relic ingest APIs instead of the batches, so tests
+/// can assert exactly what telemetry was emitted without an HTTP server.
+///
+/// ```
+/// # use newrelic_telemetry::{Span, TelemetryClient};
+/// # use newrelic_telemetry::testing::RecordingClient;
+/// # use serde_json::json;
+/// # futures::executor::block_on(async {
+/// let client = RecordingClient::new();
+///
+/// client.send_spans(vec![Span::new("id", "trace_id", 1000)].into()).await;
+///
+/// assert_eq!(
+///     client.span_batches(),
+///     vec![json!({"spans": [{"id": "id", "trace.id": "trace_id", "timestamp": 1000}]})]
+/// );
+/// # });
+/// ```
+#[derive(Debug, Default)]
+pub struct RecordingClient {
+    mock: MockClient,
+}
+
+impl RecordingClient {
+    /// Creates a recording client without any recorded payloads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all recorded payloads, in the order they were sent.
+    ///
+    /// Batches which cannot be marshalled are skipped.
+    pub fn payloads(&self) -> Vec<String> {
+        let batches = match self.mock.span_batches.lock() {
+            Ok(batches) => batches,
+            Err(_) => return vec![],
+        };
+
+        batches
+            .iter()
+            .filter_map(|batch| match batch.marshall() {
+                Ok(payload) => Some(payload),
+                Err(e) => {
+                    error!("cannot marshall {}, skipping due to {}", batch, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns all recorded span batches as JSON values, in the order they
+    /// were sent.
+    pub fn span_batches(&self) -> Vec<serde_json::Value> {
+        self.payloads()
+            .iter()
+            .filter_map(|p| serde_json::from_str::<Vec<serde_json::Value>>(p).ok())
+            .flatten()
+            .collect()
+    }
+
+    /// Removes all recorded payloads.
+    pub fn clear(&self) {
+        self.mock.take_span_batches();
+    }
+}
+
+impl TelemetryClient for RecordingClient {
+    fn send_spans<'a>(&'a self, batch: SpanBatch) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        self.mock.send_spans(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockClient, RecordingClient};
+    use crate::client::TelemetryClient;
+    use crate::span::{Span, SpanBatch};
+    use futures::executor::block_on;
+    use serde_json::json;
+
+    #[test]
+    fn mock_client_records() {
+        let client = MockClient::new();
+        assert_eq!(client.span_batch_count(), 0);
+
+        block_on(client.send_spans(vec![Span::new("id0", "trace_id0", 1)].into()));
+        block_on(client.send_spans(SpanBatch::new()));
+        assert_eq!(client.span_batch_count(), 2);
+
+        let batches = client.take_span_batches();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].to_string(), "<SpanBatch spans:1 attributes:0>");
+        assert_eq!(batches[1].to_string(), "<SpanBatch spans:0 attributes:0>");
+
+        assert_eq!(client.span_batch_count(), 0);
+        assert!(client.take_span_batches().is_empty());
+    }
+
+    #[test]
+    fn recording_client_records() {
+        let client = RecordingClient::new();
+        assert!(client.payloads().is_empty());
+
+        block_on(client.send_spans(vec![Span::new("id0", "trace_id0", 1)].into()));
+        block_on(client.send_spans(SpanBatch::new().attribute("a", 1)));
+
+        assert_eq!(client.payloads().len(), 2);
+        assert_eq!(
+            client.span_batches(),
+            vec![
+                json!({"spans": [{"id": "id0", "trace.id": "trace_id0", "timestamp": 1}]}),
+                json!({"spans": [], "common": {"attributes": {"a": 1}}}),
+            ]
+        );
+
+        client.clear();
+        assert!(client.payloads().is_empty());
+        assert!(client.span_batches().is_empty());
+    }
+}
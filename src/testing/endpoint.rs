@@ -0,0 +1,319 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Server};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Builder;
+use tokio::sync::Notify;
+
+/// A payload received by a `MockEndpoint`.
+#[derive(Debug)]
+pub struct Payload {
+    /// The request headers, with lower case names.
+    pub headers: HashMap<String, String>,
+
+    /// The decompressed request body.
+    pub body: String,
+}
+
+// A scripted response.
+struct Response {
+    code: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+// The state shared by the endpoint and its request handlers.
+struct Shared {
+    payloads: Mutex<VecDeque<Payload>>,
+    responses: Mutex<VecDeque<Response>>,
+    response_added: Notify,
+    timeout_ms: AtomicU64,
+}
+
+impl Shared {
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.load(Ordering::SeqCst))
+    }
+}
+
+/// A mock New Relic ingest endpoint.
+///
+/// The endpoint runs a local HTTP server on a free port, captures all
+/// received payloads and replies with responses scripted by the test.
+///
+/// ```no_run
+/// # use anyhow::Result;
+/// # use newrelic_telemetry::testing::MockEndpoint;
+/// # use newrelic_telemetry::ClientBuilder;
+/// # fn main() -> Result<()> {
+/// let mut endpoint = MockEndpoint::new();
+/// let client = ClientBuilder::new(&endpoint.license)
+///     .endpoint_traces(&endpoint.host, Some(endpoint.port))
+///     .tls(false)
+///     .build()?;
+///
+/// // Send a batch from another thread or task, then script the response.
+/// endpoint.reply(202)?;
+///
+/// let payload = endpoint.next_payload()?;
+/// assert_eq!(payload.body, r#"[{"spans":[]}]"#);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockEndpoint {
+    /// An API key to be used with the endpoint.
+    pub license: String,
+
+    /// The host the endpoint listens on.
+    pub host: String,
+
+    /// The port the endpoint listens on.
+    pub port: u16,
+
+    server: Option<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+    chan_stop: Option<futures::channel::oneshot::Sender<()>>,
+}
+
+impl MockEndpoint {
+    /// Starts a mock endpoint on a free local port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no local port can be bound or the server cannot be started.
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared {
+            payloads: Mutex::new(VecDeque::new()),
+            responses: Mutex::new(VecDeque::new()),
+            response_added: Notify::new(),
+            timeout_ms: AtomicU64::new(5000),
+        });
+
+        // Three rounds of clones are required to get the Arc through all the
+        // closure bounds.
+        let s = shared.clone();
+        let new_service = make_service_fn(move |_conn| {
+            let s = s.clone();
+            async {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    MockEndpoint::accept_payload(req, s.clone())
+                }))
+            }
+        });
+
+        let mut runtime = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .expect("Cannot create runtime");
+
+        let (sender, receiver) = futures::channel::oneshot::channel::<()>();
+
+        let wrapped_receiver = async {
+            let _ = receiver.await;
+        };
+
+        // Binding the listener before starting the server thread ensures that
+        // no request is sent before the endpoint is listening.
+        let listener = TcpListener::bind(("127.0.0.1", 0)).expect("Cannot acquire port");
+        let port = listener.local_addr().expect("Cannot acquire port").port();
+
+        let handle = thread::spawn(move || {
+            let scope = async {
+                Server::from_tcp(listener)
+                    .expect("Cannot start server")
+                    .http1_half_close(true)
+                    .serve(new_service)
+                    .with_graceful_shutdown(wrapped_receiver)
+                    .await
+                    .expect("Server error");
+            };
+
+            runtime.block_on(scope);
+        });
+
+        MockEndpoint {
+            license: "license".to_string(),
+            host: "127.0.0.1".to_string(),
+            port,
+            server: Some(handle),
+            shared,
+            chan_stop: Some(sender),
+        }
+    }
+
+    /// Returns the time `reply` waits for a request, and requests wait for a
+    /// scripted response. It defaults to 5 seconds.
+    pub fn timeout(&self) -> Duration {
+        self.shared.timeout()
+    }
+
+    /// Sets the time `reply` waits for a request, and requests wait for a
+    /// scripted response.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.shared
+            .timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Replies to the next request with the given status code and an empty
+    /// JSON body.
+    ///
+    /// This blocks until a request consumed the response, and fails if no
+    /// request was received within the timeout.
+    pub fn reply(&self, code: u16) -> Result<()> {
+        self.reply_details(code, vec![], "{}")
+    }
+
+    /// Replies to the next request with the given status code, headers and
+    /// body.
+    ///
+    /// This blocks until a request consumed the response, and fails if no
+    /// request was received within the timeout.
+    pub fn reply_details(
+        &self,
+        code: u16,
+        headers: Vec<(String, String)>,
+        body: &str,
+    ) -> Result<()> {
+        let mut lock = self
+            .shared
+            .responses
+            .lock()
+            .map_err(|_| anyhow!("poisoned lock"))?;
+        lock.push_back(Response {
+            code,
+            headers,
+            body: body.to_string(),
+        });
+        let len = lock.len();
+        drop(lock);
+
+        self.shared.response_added.notify();
+
+        // Block until the response was consumed.
+        let deadline = Instant::now() + self.timeout();
+        loop {
+            let lock = self
+                .shared
+                .responses
+                .lock()
+                .map_err(|_| anyhow!("poisoned lock"))?;
+            if lock.len() < len {
+                return Ok(());
+            }
+            drop(lock);
+
+            if Instant::now() > deadline {
+                return Err(anyhow!("Timeout: no request received"));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Returns the oldest received payload which was not returned yet.
+    ///
+    /// Payloads are returned in the order their requests arrived.
+    pub fn next_payload(&mut self) -> Result<Payload> {
+        let mut lock = self
+            .shared
+            .payloads
+            .lock()
+            .map_err(|_| anyhow!("poisoned lock"))?;
+
+        match lock.pop_front() {
+            Some(p) => Ok(p),
+            None => Err(anyhow!("No payload received")),
+        }
+    }
+
+    async fn accept_payload(
+        req: Request<Body>,
+        shared: Arc<Shared>,
+    ) -> Result<hyper::Response<Body>> {
+        let headers: HashMap<String, String> = req
+            .headers()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_str().unwrap_or("").to_string()))
+            .collect();
+
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+
+        let mut body_decoded = String::new();
+        if headers.get("content-encoding").map(String::as_str) == Some("gzip") {
+            let mut decoder = GzDecoder::new(&body[..]);
+            let _ = decoder.read_to_string(&mut body_decoded);
+        } else {
+            body_decoded = String::from_utf8_lossy(&body).to_string();
+        }
+
+        shared
+            .payloads
+            .lock()
+            .map_err(|_| anyhow!("poisoned lock"))?
+            .push_back(Payload {
+                headers,
+                body: body_decoded,
+            });
+
+        // Wait until a response is given, as the request might arrive before
+        // the test scripted its response.
+        let deadline = Instant::now() + shared.timeout();
+        loop {
+            let response = shared
+                .responses
+                .lock()
+                .map_err(|_| anyhow!("poisoned lock"))?
+                .pop_front();
+
+            if let Some(r) = response {
+                let mut resp = hyper::Response::builder();
+
+                for (k, v) in &r.headers {
+                    resp = resp.header(k, v);
+                }
+
+                return Ok(resp.status(r.code).body(Body::from(r.body))?);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(anyhow!("No response given"));
+            }
+
+            // A response added before waiting leaves a permit, so it is not
+            // missed.
+            let _ = tokio::time::timeout(deadline - now, shared.response_added.notified()).await;
+        }
+    }
+}
+
+impl Default for MockEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MockEndpoint {
+    fn drop(&mut self) {
+        if let Some(stop) = self.chan_stop.take() {
+            if stop.send(()).is_ok() {
+                if let Some(server) = self.server.take() {
+                    let _ = server.join();
+                }
+            }
+        }
+    }
+}
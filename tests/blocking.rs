@@ -11,9 +11,12 @@ mod blocking {
     use super::common;
     use anyhow::Result;
     use common::Endpoint;
-    use newrelic_telemetry::{blocking::Client, ClientBuilder, Span, SpanBatch};
+    use newrelic_telemetry::clock::ManualClock;
+    use newrelic_telemetry::{blocking::Client, ClientBuilder, SendResult, Span, SpanBatch};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant, SystemTime};
 
     pub fn setup() -> Result<(Endpoint, Client)> {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -55,6 +58,61 @@ mod blocking {
         Ok(())
     }
 
+    #[test]
+    fn max_batch_age() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let clock = Arc::new(ManualClock::new(SystemTime::now()));
+        let client = ClientBuilder::new(&endpoint.license)
+            .clock(clock.clone())
+            .backoff_factor(Duration::from_secs(20))
+            .max_batch_age(Duration::from_secs(60))
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .build_blocking()?;
+
+        // The first batch keeps the worker busy until it gets a response.
+        client.send_spans(vec![Span::new("id1", "tid", 1000)].into());
+
+        let start = Instant::now();
+        while endpoint.next_payload().is_err() {
+            assert!(start.elapsed() < Duration::from_secs(5), "no payload");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The second batch waits in the queue for 50 seconds, which is below
+        // the maximum age.
+        client.send_spans(vec![Span::new("id2", "tid", 1000)].into());
+        clock.advance(Duration::from_secs(50));
+        endpoint.reply(202)?;
+
+        // The first retry is immediate, the second one would be after 20
+        // seconds of backoff, which exceeds the maximum age together with the
+        // time spent in the queue.
+        endpoint.reply(500)?;
+        endpoint.reply(500)?;
+
+        let start = Instant::now();
+        while client.expired_batches() == 0 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "batch not dropped"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(client.expired_batches(), 1);
+
+        client.shutdown();
+
+        let payload = endpoint.next_payload()?;
+        assert!(payload.body.contains("id2"));
+        assert!(endpoint.next_payload().is_ok(), "first attempt");
+        assert!(endpoint.next_payload().is_err(), "no further retries");
+
+        Ok(())
+    }
+
     #[test]
     fn retry_after() -> Result<()> {
         let _ = env_logger::builder()
@@ -117,6 +175,31 @@ mod blocking {
         Ok(())
     }
 
+    #[test]
+    fn data_format() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .data_format_traces("experimental", "2")
+            .tls(false)
+            .build_blocking()?;
+
+        client.send_spans(SpanBatch::new());
+        endpoint.reply(202)?;
+
+        let p = endpoint.next_payload()?;
+
+        assert_eq!(
+            p.headers.get("data-format"),
+            Some(&"experimental".to_string())
+        );
+        assert_eq!(p.headers.get("data-format-version"), Some(&"2".to_string()));
+
+        Ok(())
+    }
+
     #[test]
     fn product() -> Result<()> {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -146,7 +229,7 @@ mod blocking {
 
     #[test]
     fn drop_payload() -> Result<()> {
-        for code in vec![400, 401, 403, 404, 405, 409, 410, 411] {
+        for code in [400, 401, 403, 404, 405, 409, 410, 411] {
             let (mut endpoint, client) = setup()?;
 
             let span_batch = SpanBatch::new();
@@ -191,8 +274,6 @@ mod blocking {
     fn split_payload() -> Result<()> {
         let (mut endpoint, client) = setup()?;
 
-        let mut span_batch = SpanBatch::new();
-
         let span_batch = vec![
             Span::new("id1", "tid1", 1000),
             Span::new("id2", "tid2", 2000),
@@ -206,6 +287,9 @@ mod blocking {
         endpoint.reply(202)?;
         endpoint.reply(202)?;
 
+        // Skip the first payload that is rejected.
+        endpoint.next_payload()?;
+
         assert_json_eq!(
             &endpoint.next_payload()?.body,
             r#"
@@ -244,8 +328,162 @@ mod blocking {
             }]"#
         );
 
-        // Skip the first payload that is rejected.
-        endpoint.next_payload()?.body;
+        Ok(())
+    }
+
+    #[test]
+    fn request_id_retries() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .retries_max(2)
+            .backoff_factor(Duration::from_secs(0))
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .build_blocking()?;
+
+        client.send_spans(SpanBatch::new());
+        endpoint.reply(500)?;
+        endpoint.reply(202)?;
+
+        let first = endpoint
+            .next_payload()?
+            .headers
+            .get("x-request-id")
+            .cloned();
+        let second = endpoint
+            .next_payload()?
+            .headers
+            .get("x-request-id")
+            .cloned();
+
+        assert!(first.is_some());
+        assert_eq!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_id_split() -> Result<()> {
+        let (mut endpoint, client) = setup()?;
+
+        let span_batch = vec![
+            Span::new("id1", "tid1", 1000),
+            Span::new("id2", "tid2", 2000),
+        ]
+        .into();
+
+        client.send_spans(span_batch);
+        endpoint.reply(413)?;
+        endpoint.reply(202)?;
+        endpoint.reply(202)?;
+
+        let mut ids = vec![];
+        for _ in 0..3 {
+            ids.push(
+                endpoint
+                    .next_payload()?
+                    .headers
+                    .get("x-request-id")
+                    .cloned(),
+            );
+        }
+        ids.sort();
+        ids.dedup();
+
+        assert_eq!(ids.len(), 3);
+        assert!(ids.iter().all(Option::is_some));
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_id_generator() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let counter = AtomicUsize::new(0);
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .request_id_generator(move || {
+                format!("replay-{}", counter.fetch_add(1, Ordering::SeqCst))
+            })
+            .build_blocking()?;
+
+        client.send_spans(SpanBatch::new());
+        endpoint.reply(202)?;
+
+        assert_eq!(
+            endpoint.next_payload()?.headers.get("x-request-id"),
+            Some(&"replay-0".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_spans_sync() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            endpoint.reply(400)?;
+            Ok(endpoint)
+        });
+
+        let result = client.send_spans_sync(vec![Span::new("id1", "tid1", 1000)].into())?;
+        assert_eq!(result, SendResult::Sent);
+
+        let result = client.send_spans_sync(vec![Span::new("id2", "tid2", 1000)].into())?;
+        assert!(matches!(result, SendResult::Rejected(_)));
+
+        let mut endpoint = handle.join().expect("endpoint thread panicked")?;
+        assert!(endpoint.next_payload().is_ok(), "second batch sent");
+        assert!(endpoint.next_payload().is_ok(), "first batch sent");
+
+        client.shutdown();
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_spans_sync_async_context() -> Result<()> {
+        let (_endpoint, client) = setup()?;
+
+        let mut runtime = tokio::runtime::Runtime::new()?;
+        let result = runtime.block_on(async { client.send_spans_sync(SpanBatch::new()) });
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_handle() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()?;
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .runtime_handle(runtime.handle().clone())
+            .build_blocking()?;
+
+        client.send_spans(vec![Span::new("id1", "tid1", 1000)].into());
+        endpoint.reply(202)?;
+
+        assert_json_eq!(
+            &endpoint.next_payload()?.body,
+            r#"[{"spans": [{"id": "id1", "timestamp": 1000, "trace.id": "tid1"}]}]"#
+        );
+
+        client.shutdown();
 
         Ok(())
     }
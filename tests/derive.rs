@@ -0,0 +1,69 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+#[cfg(feature = "derive")]
+mod derive {
+    use newrelic_telemetry::attribute::{IntoAttributes, Value};
+    use std::collections::HashMap;
+
+    #[derive(IntoAttributes)]
+    struct Service {
+        #[attribute(rename = "service.name")]
+        name: String,
+        instance: Option<u32>,
+    }
+
+    #[derive(IntoAttributes)]
+    struct Request {
+        #[attribute(rename = "http.method")]
+        method: &'static str,
+        status: u32,
+        duration: f64,
+        cached: bool,
+        r#type: &'static str,
+        user: Option<&'static str>,
+        #[attribute(skip)]
+        #[allow(dead_code)]
+        body: Vec<u8>,
+        #[attribute(flatten)]
+        service: Service,
+    }
+
+    fn attributes(pairs: Vec<(&str, Value)>) -> HashMap<String, Value> {
+        pairs
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
+    #[test]
+    fn into_attributes() {
+        let request = Request {
+            method: "GET",
+            status: 200,
+            duration: 1.5,
+            cached: false,
+            r#type: "api",
+            user: None,
+            body: vec![1, 2, 3],
+            service: Service {
+                name: "service".to_string(),
+                instance: Some(1),
+            },
+        };
+
+        assert_eq!(
+            request.into_attributes(),
+            attributes(vec![
+                ("http.method", Value::from("GET")),
+                ("status", Value::from(200_u32)),
+                ("duration", Value::from(1.5)),
+                ("cached", Value::from(false)),
+                ("type", Value::from("api")),
+                ("service.name", Value::from("service")),
+                ("instance", Value::from(1_u32)),
+            ])
+        );
+    }
+}
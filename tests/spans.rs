@@ -11,9 +11,14 @@ mod client {
     use super::common;
     use anyhow::Result;
     use common::Endpoint;
-    use newrelic_telemetry::{Client, ClientBuilder, Span, SpanBatch};
+    use newrelic_telemetry::clock::ManualClock;
+    use newrelic_telemetry::{
+        Client, ClientBuilder, ConnectivityError, KeyProvider, MultiClient, ResponseError,
+        SendResult, Span, SpanBatch, TransportFailure,
+    };
+    use std::sync::{Arc, Mutex};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
     pub fn setup() -> Result<(Endpoint, Client)> {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -27,6 +32,17 @@ mod client {
         Ok((endpoint, client))
     }
 
+    #[test]
+    fn endpoint_timeout() {
+        let endpoint = Endpoint::new();
+        endpoint.set_timeout(Duration::from_millis(100));
+        assert_eq!(endpoint.timeout(), Duration::from_millis(100));
+
+        let start = Instant::now();
+        assert!(endpoint.reply(202).is_err(), "no request received");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn empty() -> Result<()> {
         let (mut endpoint, client) = setup()?;
@@ -51,6 +67,519 @@ mod client {
         Ok(())
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn skip_empty_batches() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .skip_empty_batches(true)
+            .build()?;
+
+        let result = client
+            .send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        assert!(endpoint.next_payload().is_err(), "no payload sent");
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn send_spans_arc() -> Result<()> {
+        let (mut endpoint, client) = setup()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(500)?;
+            endpoint.next_payload()?;
+            endpoint.reply(202)?;
+            endpoint.next_payload()?;
+            Ok(endpoint)
+        });
+
+        let batch = Arc::new(SpanBatch::from(vec![Span::new("id1", "tid1", 1000)]));
+
+        let result = client.send_spans_arc(Arc::clone(&batch)).await;
+        assert_eq!(result, SendResult::Sent);
+        assert_eq!(batch.spans(), &[Span::new("id1", "tid1", 1000)]);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn max_spans_per_batch() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .max_spans_per_batch(2)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            endpoint.reply(202)?;
+
+            // Chunks are sent concurrently, so they arrive in any order.
+            let mut payloads = [endpoint.next_payload()?, endpoint.next_payload()?];
+            payloads.sort_by_key(|p| p.body.len());
+
+            assert_json_eq!(
+                &payloads[0].body,
+                r#"[{"spans": [{"id": "id3", "timestamp": 1000, "trace.id": "tid1"}]}]"#
+            );
+            assert_json_eq!(
+                &payloads[1].body,
+                r#"[{"spans": [
+                    {"id": "id1", "timestamp": 1000, "trace.id": "tid1"},
+                    {"id": "id2", "timestamp": 1000, "trace.id": "tid1"}
+                ]}]"#
+            );
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![
+            Span::new("id1", "tid1", 1000),
+            Span::new("id2", "tid1", 1000),
+            Span::new("id3", "tid1", 1000),
+        ]
+        .into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn learned_size_limit() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        let batch = || -> SpanBatch {
+            vec![
+                Span::new("id1", "tid1", 1000),
+                Span::new("id2", "tid1", 1000),
+            ]
+            .into()
+        };
+
+        // The first batch is rejected as too large, and split.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(413)?;
+            endpoint.reply(202)?;
+            endpoint.reply(202)?;
+            Ok(endpoint)
+        });
+
+        let result = client
+            .send_spans_with_deadline(batch(), Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("endpoint thread panicked")?;
+        for _ in 0..3 {
+            endpoint.next_payload()?;
+        }
+
+        // A batch of the same size is split before it is sent.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            endpoint.reply(202)?;
+
+            for _ in 0..2 {
+                let payload: serde_json::Value =
+                    serde_json::from_str(&endpoint.next_payload()?.body)?;
+                assert_eq!(payload[0]["spans"].as_array().map(Vec::len), Some(1));
+            }
+
+            Ok(endpoint)
+        });
+
+        let result = client
+            .send_spans_with_deadline(batch(), Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn size_limit_not_learned_from_single_span() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        // A batch of a single span cannot be split, and is dropped.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(413)?;
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Dropped);
+
+        let mut endpoint = handle.join().expect("endpoint thread panicked")?;
+        endpoint.next_payload()?;
+
+        // Larger batches are still sent unsplit.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+
+            let payload: serde_json::Value = serde_json::from_str(&endpoint.next_payload()?.body)?;
+            assert_eq!(payload[0]["spans"].as_array().map(Vec::len), Some(2));
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![
+            Span::new("id2", "tid1", 1000),
+            Span::new("id3", "tid1", 1000),
+        ]
+        .into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn check_connectivity() -> Result<()> {
+        let (mut endpoint, client) = setup()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            assert_json_eq!(&endpoint.next_payload()?.body, r#"[{"spans": []}]"#);
+
+            endpoint.reply_details(403, vec![], r#"{"error": "invalid key"}"#)?;
+            Ok(endpoint)
+        });
+
+        assert_eq!(
+            client.check_connectivity(Duration::from_secs(5)).await,
+            Ok(())
+        );
+        assert!(matches!(
+            client.check_connectivity(Duration::from_secs(5)).await,
+            Err(ConnectivityError::Unauthorized(ResponseError {
+                status: 403,
+                ..
+            }))
+        ));
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RotatingKey {
+        refreshes: Mutex<u32>,
+    }
+
+    impl KeyProvider for RotatingKey {
+        fn api_key(&self) -> String {
+            format!("key-{}", self.refreshes.lock().unwrap())
+        }
+
+        fn refresh(&self) -> bool {
+            *self.refreshes.lock().unwrap() += 1;
+            true
+        }
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn key_provider_refresh() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let provider = Arc::new(RotatingKey::default());
+        let client = ClientBuilder::new("")
+            .key_provider(provider.clone())
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .backoff_factor(Duration::from_secs(3600))
+            .tls(false)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            // Rejected keys are refreshed once, without backoff.
+            endpoint.reply(403)?;
+            endpoint.reply(202)?;
+            endpoint.reply(401)?;
+            endpoint.reply(401)?;
+
+            let keys: Vec<_> = (0..4)
+                .map(|_| {
+                    endpoint
+                        .next_payload()
+                        .map(|p| p.headers["api-key"].clone())
+                })
+                .collect::<Result<_>>()?;
+            assert_eq!(keys, vec!["key-0", "key-1", "key-1", "key-2"]);
+
+            Ok(endpoint)
+        });
+
+        let result = client.send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(60));
+        assert_eq!(result.await, SendResult::Sent);
+
+        // Keys rejected after a refresh are not refreshed again.
+        let result = client.send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(60));
+        assert!(matches!(
+            result.await,
+            SendResult::Rejected(ResponseError { status: 401, .. })
+        ));
+        assert_eq!(*provider.refreshes.lock().unwrap(), 2);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn key_provider_refresh_without_retries() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let provider = Arc::new(RotatingKey::default());
+        let client = ClientBuilder::new("")
+            .key_provider(provider.clone())
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .retries_max(1)
+            .tls(false)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(401)?;
+            endpoint.reply(202)?;
+
+            assert_eq!(endpoint.next_payload()?.headers["api-key"], "key-0");
+            assert_eq!(endpoint.next_payload()?.headers["api-key"], "key-1");
+
+            Ok(endpoint)
+        });
+
+        // The request with the refreshed key doesn't count as a retry.
+        let result = client.send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(60));
+        assert_eq!(result.await, SendResult::Sent);
+        assert_eq!(*provider.refreshes.lock().unwrap(), 1);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn multi_client() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let endpoints = vec![Endpoint::new(), Endpoint::new()];
+        let client = MultiClient::new(endpoints.iter().map(|endpoint| {
+            ClientBuilder::new(&endpoint.license)
+                .endpoint_traces(&endpoint.host, Some(endpoint.port))
+                .tls(false)
+        }))?;
+
+        // The first account accepts the batch, the second one rejects it.
+        let handles: Vec<_> = endpoints
+            .into_iter()
+            .zip(vec![202, 400])
+            .zip(vec!["batch", "batch-1"])
+            .map(|((mut endpoint, code), request_id)| {
+                thread::spawn(move || -> Result<Endpoint> {
+                    endpoint.reply(code)?;
+                    let payload = endpoint.next_payload()?;
+                    assert_json_eq!(
+                        &payload.body,
+                        r#"[{"spans": [{"id": "id", "trace.id": "tid", "timestamp": 1}]}]"#
+                    );
+                    assert_eq!(payload.headers["x-request-id"], request_id);
+                    Ok(endpoint)
+                })
+            })
+            .collect();
+
+        let mut batch = SpanBatch::from(vec![Span::new("id", "tid", 1)]);
+        batch.set_uuid("batch");
+        let results = client.send_spans(batch).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], SendResult::Sent);
+        assert!(matches!(
+            results[1],
+            SendResult::Rejected(ResponseError { status: 400, .. })
+        ));
+
+        let endpoints = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("endpoint thread panicked"))
+            .collect::<Result<Vec<_>>>()?;
+        drop(client);
+        drop(endpoints);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn check_connectivity_unreachable() -> Result<()> {
+        // Bind and release a port, so no one is listening on it.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+
+        let client = ClientBuilder::new("license")
+            .endpoint_traces("127.0.0.1", Some(port))
+            .tls(false)
+            .build()?;
+
+        assert!(matches!(
+            client.check_connectivity(Duration::from_secs(5)).await,
+            Err(ConnectivityError::Unreachable(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn transport_failure() -> Result<()> {
+        // Bind and release a port, so no one is listening on it.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+
+        let client = ClientBuilder::new("license")
+            .endpoint_traces("127.0.0.1", Some(port))
+            .tls(false)
+            .retries_max(1)
+            .build()?;
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Failed(TransportFailure::Connect));
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn stream_payloads() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .stream_payloads(true)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            let payload = endpoint.next_payload()?;
+
+            assert_eq!(payload.headers["content-encoding"], "gzip");
+            assert_eq!(payload.headers["transfer-encoding"], "chunked");
+            assert_json_eq!(
+                &payload.body,
+                r#"[{"spans": [{"id": "id1", "timestamp": 1000, "trace.id": "tid1"}]}]"#
+            );
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let endpoint = handle.join().expect("endpoint thread panicked")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn dedup_window() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .dedup_window(10)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            for _ in 0..4 {
+                endpoint.reply(202)?;
+                endpoint.next_payload()?;
+            }
+
+            Ok(endpoint)
+        });
+
+        let expected = [
+            ("id1", SendResult::Sent),
+            ("id1", SendResult::Duplicate),
+            ("id2", SendResult::Sent),
+        ];
+        for (id, expected) in &expected {
+            let batch: SpanBatch = vec![Span::new(id, "tid1", 1000)].into();
+            let result = client
+                .send_spans_with_deadline(batch, Duration::from_secs(5))
+                .await;
+            assert_eq!(&result, expected);
+        }
+
+        // Empty batches are never skipped.
+        for _ in 0..2 {
+            let result = client
+                .send_spans_with_deadline(SpanBatch::new(), Duration::from_secs(5))
+                .await;
+            assert_eq!(result, SendResult::Sent);
+        }
+
+        let mut endpoint = handle.join().expect("endpoint thread panicked")?;
+        assert!(endpoint.next_payload().is_err(), "duplicate payload sent");
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn simple() -> Result<()> {
         let (mut endpoint, client) = setup()?;
@@ -146,7 +675,7 @@ mod client {
                     "trace.id": "tid1",
                     "attributes": {
                       "bool_attr": true,
-                      "float_attr": 3.14159,
+                      "float_attr": 1.5,
                       "str_attr": "string",
                       "int_attr": 40,
                       "neg_int_attr": -40,
@@ -163,7 +692,7 @@ mod client {
         span_batch.record(
             Span::new("id1", "tid1", 1000)
                 .attribute("bool_attr", true)
-                .attribute("float_attr", 3.14159)
+                .attribute("float_attr", 1.5)
                 .attribute("str_attr", "string")
                 .attribute("int_attr", 40)
                 .attribute("neg_int_attr", -40)
@@ -216,4 +745,601 @@ mod client {
 
         Ok(())
     }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn http2_only() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .http2_only(true)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("error from endpoint thread")?;
+        assert!(endpoint.next_payload().is_ok(), "payload sent");
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn resolve() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces("trace-api.newrelic.com", Some(endpoint.port))
+            .resolve("trace-api.newrelic.com", endpoint.host.parse()?)
+            .tls(false)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("error from endpoint thread")?;
+        let payload = endpoint.next_payload()?;
+        assert_eq!(
+            payload.headers["host"],
+            format!("trace-api.newrelic.com:{}", endpoint.port)
+        );
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn local_agent() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new("")
+            .local_agent(Some(endpoint.port))
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("error from endpoint thread")?;
+        let payload = endpoint.next_payload()?;
+        assert!(!payload.headers.contains_key("api-key"));
+        assert!(!payload.headers.contains_key("content-encoding"));
+        assert_json_eq!(
+            &payload.body,
+            r#"[{"spans": [{"id": "id1", "timestamp": 1000, "trace.id": "tid1"}]}]"#
+        );
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn dry_run() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .dry_run(true)
+            .build()?;
+
+        let span_batch = vec![Span::new("id1", "tid1", 1000)].into();
+
+        client.send_spans(span_batch).await;
+
+        assert!(endpoint.next_payload().is_err(), "no payload sent");
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn enrich_attributes() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let mut endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .service_name("service")
+            .enrich_attributes(true)
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<()> {
+            endpoint.reply(202)?;
+
+            let payload: serde_json::Value = serde_json::from_str(&endpoint.next_payload()?.body)?;
+            let common = &payload[0]["common"]["attributes"];
+
+            assert_eq!(common["service.name"], "service");
+            assert_eq!(common["process.pid"], std::process::id());
+            assert_eq!(common["custom"], "value");
+
+            Ok(())
+        });
+
+        let span_batch =
+            SpanBatch::from(vec![Span::new("id1", "tid1", 1000)]).attribute("custom", "value");
+
+        client.send_spans(span_batch).await;
+
+        handle.join().expect("error from endpoint thread")?;
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn trim_single_span() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(413)?;
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        let span = Span::new("id1", "tid1", 1000)
+            .name("name1")
+            .attribute("large", "x".repeat(1000).as_str());
+        let result = client
+            .send_spans_with_deadline(vec![span].into(), Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("error from endpoint thread")?;
+
+        // Skip the first payload that is rejected.
+        endpoint.next_payload()?;
+
+        assert_json_eq!(
+            &endpoint.next_payload()?.body,
+            r#"
+            [{
+              "spans": [{
+                "id": "id1",
+                "timestamp": 1000,
+                "trace.id": "tid1",
+                "attributes": {
+                  "name": "name1",
+                  "truncated.attributes": "large"
+                }
+              }]
+            }]"#
+        );
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn trim_single_span_attributes() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        // All attributes that have to be removed are removed at once.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(413)?;
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        let span = Span::new("id1", "tid1", 1000)
+            .attribute("a", "x".repeat(700_000).as_str())
+            .attribute("b", "x".repeat(600_000).as_str())
+            .attribute("c", "x".repeat(500_000).as_str());
+        let result = client
+            .send_spans_with_deadline(vec![span].into(), Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let mut endpoint = handle.join().expect("error from endpoint thread")?;
+
+        // Skip the first payload that is rejected.
+        endpoint.next_payload()?;
+
+        let payload: serde_json::Value = serde_json::from_str(&endpoint.next_payload()?.body)?;
+        let attributes = &payload[0]["spans"][0]["attributes"];
+        assert_eq!(attributes["truncated.attributes"], "a,b");
+        assert!(attributes["a"].is_null());
+        assert!(attributes["b"].is_null());
+        assert!(attributes["c"].is_string());
+
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn shutdown() -> Result<()> {
+        let (mut endpoint, client) = setup()?;
+
+        let sender = client.clone();
+        let send = tokio::spawn(async move {
+            sender
+                .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+                .await
+        });
+
+        // Give the request time to arrive at the endpoint before shutting down.
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+
+        let handle = thread::spawn(move || -> Result<()> {
+            thread::sleep(Duration::from_millis(200));
+            endpoint.reply(202)?;
+
+            assert!(endpoint.next_payload().is_ok(), "payload sent");
+
+            Ok(())
+        });
+
+        let report = client.shutdown(Duration::from_secs(5)).await;
+        assert!(report.is_clean());
+
+        send.await?;
+        handle.join().expect("error from endpoint thread")?;
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn shutdown_timeout() -> Result<()> {
+        let (mut endpoint, client) = setup()?;
+
+        let sender = client.clone();
+        let send = tokio::spawn(async move {
+            sender
+                .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+                .await
+        });
+
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+
+        let report = client.shutdown(Duration::from_millis(50)).await;
+        assert_eq!(report.abandoned.len(), 1);
+
+        // Batches sent after shutting down are dropped.
+        client
+            .send_spans(vec![Span::new("id2", "tid2", 1000)].into())
+            .await;
+
+        endpoint.reply(202)?;
+        send.await?;
+
+        assert!(endpoint.next_payload().is_ok(), "first payload sent");
+        assert!(endpoint.next_payload().is_err(), "second payload dropped");
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn shutdown_timeout_manual_clock() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        // A clock that doesn't advance, which must not delay the shutdown.
+        let clock = Arc::new(ManualClock::new(SystemTime::now()));
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .clock(clock)
+            .build()?;
+
+        let sender = client.clone();
+        let send = tokio::spawn(async move {
+            sender
+                .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+                .await
+        });
+
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+
+        let report = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.shutdown(Duration::from_millis(50)),
+        )
+        .await?;
+        assert_eq!(report.abandoned.len(), 1);
+
+        endpoint.reply(202)?;
+        send.await?;
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn deadline() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+            endpoint.reply_details(
+                400,
+                vec![("X-RateLimit-Remaining".to_string(), "0".to_string())],
+                r#"{"requestId": "id", "error": "invalid"}"#,
+            )?;
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        let batch: SpanBatch = vec![Span::new("id2", "tid2", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(
+            result,
+            SendResult::Rejected(ResponseError {
+                status: 400,
+                message: Some("invalid".to_string()),
+                request_id: Some("id".to_string()),
+                headers: vec![("x-ratelimit-remaining".to_string(), "0".to_string())],
+            })
+        );
+
+        // Close the connection before stopping the endpoint.
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn deadline_timeout() -> Result<()> {
+        let (endpoint, client) = setup()?;
+
+        // The endpoint doesn't reply before the deadline passes.
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_millis(100))
+            .await;
+        assert_eq!(result, SendResult::Timeout);
+
+        // Release the pending request, so the endpoint can shut down.
+        endpoint.reply(202)?;
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn retry_budget() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .backoff_factor(Duration::from_secs(10))
+            .retry_budget(Duration::from_secs(5))
+            .build()?;
+
+        // The first retry is immediate, waiting for the second retry would
+        // exceed the budget.
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(500)?;
+            endpoint.reply(500)?;
+
+            Ok(endpoint)
+        });
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Dropped);
+
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(client);
+        drop(endpoint);
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn response_hook() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let responses = Arc::new(Mutex::new(vec![]));
+        let hook_responses = responses.clone();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .backoff_factor(Duration::from_secs(0))
+            .response_hook(move |status, headers| {
+                let limit = headers
+                    .get("x-rate-limit")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                hook_responses.lock().unwrap().push((status, limit));
+            })
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(500)?;
+            endpoint.reply_details(202, vec![("x-rate-limit".into(), "100".into())], "{}")?;
+
+            Ok(endpoint)
+        });
+
+        client
+            .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+            .await;
+
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(client);
+        drop(endpoint);
+
+        assert_eq!(
+            *responses.lock().unwrap(),
+            vec![(500, None), (202, Some("100".to_string()))]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn timing_hook() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let timings = Arc::new(Mutex::new(vec![]));
+        let hook_timings = timings.clone();
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .backoff_factor(Duration::from_millis(10))
+            .timing_hook(move |timing| hook_timings.lock().unwrap().push(timing.clone()))
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(500)?;
+            endpoint.reply(500)?;
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        client
+            .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+            .await;
+
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(client);
+        drop(endpoint);
+
+        let timings = timings.lock().unwrap();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].attempts, 3);
+        assert!(timings[0].requests > Duration::from_secs(0));
+        // The first retry is immediate, the second one backs off.
+        assert_eq!(timings[0].backoff, Duration::from_millis(10));
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn clock() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let timings = Arc::new(Mutex::new(vec![]));
+        let hook_timings = timings.clone();
+
+        // A clock that doesn't advance, so requests take no time.
+        let clock = Arc::new(ManualClock::new(SystemTime::now()));
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .clock(clock)
+            .timing_hook(move |timing| hook_timings.lock().unwrap().push(timing.clone()))
+            .build()?;
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            endpoint.reply(202)?;
+
+            Ok(endpoint)
+        });
+
+        client
+            .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+            .await;
+
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(client);
+        drop(endpoint);
+
+        assert_eq!(timings.lock().unwrap()[0].requests, Duration::from_secs(0));
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn correct_clock_skew() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        // The local clock is one hour behind the endpoint.
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_600_000_000 - 3600));
+
+        let endpoint = Endpoint::new();
+        let client = ClientBuilder::new(&endpoint.license)
+            .endpoint_traces(&endpoint.host, Some(endpoint.port))
+            .tls(false)
+            .clock(clock)
+            .correct_clock_skew(true)
+            .build()?;
+
+        assert_eq!(client.clock().timestamp(), 1_599_996_400_000);
+
+        let handle = thread::spawn(move || -> Result<Endpoint> {
+            let date = (
+                "date".to_string(),
+                "Sun, 13 Sep 2020 12:26:40 GMT".to_string(),
+            );
+            endpoint.reply_details(202, vec![date], "{}")?;
+
+            Ok(endpoint)
+        });
+
+        client
+            .send_spans(vec![Span::new("id1", "tid1", 1000)].into())
+            .await;
+
+        let endpoint = handle.join().expect("error from endpoint thread")?;
+        drop(endpoint);
+
+        assert_eq!(client.clock().timestamp(), 1_600_000_000_000);
+
+        Ok(())
+    }
 }
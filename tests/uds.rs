@@ -0,0 +1,72 @@
+///
+/// Copyright 2020 New Relic Corporation. All rights reserved.
+/// SPDX-License-Identifier: Apache-2.0
+///
+#[cfg(all(unix, feature = "uds"))]
+mod uds {
+    use anyhow::Result;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Request, Response};
+    use newrelic_telemetry::{ClientBuilder, SendResult, Span, SpanBatch};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use std::{env, fs, process};
+    use tokio::net::UnixListener;
+
+    #[tokio::test(threaded_scheduler)]
+    async fn send_spans() -> Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let path = env::temp_dir().join(format!("newrelic-telemetry-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut listener = UnixListener::bind(&path)?;
+
+        // Record the path and host header of each request.
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server_requests = requests.clone();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("cannot accept");
+            let service = service_fn(move |req: Request<Body>| {
+                let host = req
+                    .headers()
+                    .get("host")
+                    .map(|h| h.to_str().unwrap().to_string());
+                server_requests
+                    .lock()
+                    .unwrap()
+                    .push((req.uri().path().to_string(), host));
+
+                async {
+                    Ok::<_, Infallible>(
+                        Response::builder().status(202).body(Body::empty()).unwrap(),
+                    )
+                }
+            });
+
+            let _ = Http::new().serve_connection(stream, service).await;
+        });
+
+        let client = ClientBuilder::new("license").endpoint_uds(&path).build()?;
+
+        let batch: SpanBatch = vec![Span::new("id1", "tid1", 1000)].into();
+        let result = client
+            .send_spans_with_deadline(batch, Duration::from_secs(5))
+            .await;
+        assert_eq!(result, SendResult::Sent);
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "/trace/v1".to_string(),
+                Some("trace-api.newrelic.com".to_string())
+            )]
+        );
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}